@@ -0,0 +1,55 @@
+use crate::error::{Error, Result};
+use lofty::prelude::{Accessor, ItemKey, TaggedFileExt};
+use lofty::probe::Probe;
+
+
+/// Tags read from an audio file's ID3, Vorbis comment, FLAC, MP4, or APE metadata.
+#[derive(Debug, Clone, Default)]
+pub struct AudioInfo {
+    /// The track artist, falling back to the album artist.
+    pub artist: Option<String>,
+
+    /// The album title.
+    pub album: Option<String>,
+
+    /// The track title.
+    pub title: Option<String>,
+
+    /// The genre.
+    pub genre: Option<String>,
+
+    /// The track number.
+    pub track: Option<u32>,
+
+    /// The disc number.
+    pub disc: Option<u32>,
+
+    /// The release year.
+    pub year: Option<u32>,
+}
+
+
+/// Reads the tags from the audio file at `path`. If the file has several tags, e.g. both ID3v2
+/// and ID3v1, the format's primary tag is preferred.
+pub fn read(path: &str) -> Result<AudioInfo> {
+    let tagged_file = Probe::open(crate::longpath::extended(std::path::Path::new(path)))
+        .and_then(|probe| probe.read())
+        .map_err(|err| Error::Transform(format!("cannot read audio tags from '{}': {}", path, err)))?;
+    let tag = match tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
+        Some(tag) => tag,
+        None => return Ok(AudioInfo::default()),
+    };
+
+    let text = |value: Option<std::borrow::Cow<str>>| {
+        value.map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+    };
+    Ok(AudioInfo {
+        artist: text(tag.artist()).or_else(|| text(tag.get_string(&ItemKey::AlbumArtist).map(Into::into))),
+        album: text(tag.album()),
+        title: text(tag.title()),
+        genre: text(tag.genre()),
+        track: tag.track(),
+        disc: tag.disk(),
+        year: tag.year(),
+    })
+}
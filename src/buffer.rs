@@ -0,0 +1,688 @@
+use crate::error::{Error, Result};
+use crate::journal::{escape, unescape};
+use crate::longpath::extended;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+
+/// Lines in the buffer beginning with this prefix are comments and are ignored, unless they name
+/// an input file. (A single '#' marks a file for deletion.)
+pub const COMMENT_PREFIX: &str = "##";
+
+
+/// The default prefix marking a file for deletion. This is also the marker the planner expects, so
+/// output filenames using a different prefix are translated by `parse()`.
+pub const DELETE_PREFIX: &str = "#";
+
+
+/// Output filenames beginning with this prefix mark the input file for permanent deletion,
+/// bypassing the trash.
+pub const PURGE_PREFIX: &str = "!";
+
+
+/// Lines in the buffer beginning with this prefix name new, empty files to create. Names ending
+/// with a path separator are created as directories.
+pub const CREATE_PREFIX: &str = "+";
+
+
+/// Output filenames beginning with this prefix mark the input file for copying rather than
+/// renaming, e.g. '> b.txt' copies the file to 'b.txt' and leaves the original in place.
+pub const COPY_PREFIX: &str = ">";
+
+
+/// Separates a filename in the buffer from the annotation describing the file, e.g.
+/// 'photo.jpg\t## 2.3M  2024-01-03  image/jpeg'. Annotations are removed by `strip_annotations()`.
+pub const ANNOTATION_PREFIX: &str = "\t## ";
+
+
+/// Returns the destination of an output filename marked for copying with `COPY_PREFIX`, with any
+/// whitespace following the prefix removed.
+pub fn copy_destination(output_file: &str) -> Option<&str> {
+    output_file.strip_prefix(COPY_PREFIX).map(|name| name.trim_start())
+}
+
+
+/// The layout of the list of filenames presented for editing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferFormat {
+    /// One filename per line. Output filenames are matched to input filenames by line number.
+    Single,
+
+    /// Two tab-separated columns per line: the original filename and an editable destination.
+    /// Output filenames are matched to input filenames by the first column.
+    Dual,
+
+    /// A number and a tab followed by the filename on each line, as used by vidir. Output
+    /// filenames are matched to input filenames by number.
+    Numbered,
+
+    /// Filenames grouped by directory under a `## dir/` header, with the directory removed from
+    /// each filename. Output filenames are matched to input filenames by position, as in the
+    /// single format.
+    Grouped,
+}
+
+
+impl BufferFormat {
+    /// Parses a buffer format name: 'single', 'dual', 'numbered', or 'grouped'.
+    pub fn from_name(name: &str) -> Option<BufferFormat> {
+        match name {
+            "single" => Some(BufferFormat::Single),
+            "dual" => Some(BufferFormat::Dual),
+            "numbered" => Some(BufferFormat::Numbered),
+            "grouped" => Some(BufferFormat::Grouped),
+            _ => None,
+        }
+    }
+
+    /// Returns the format's name, as accepted by `from_name()`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BufferFormat::Single => "single",
+            BufferFormat::Dual => "dual",
+            BufferFormat::Numbered => "numbered",
+            BufferFormat::Grouped => "grouped",
+        }
+    }
+}
+
+
+/// Returns the text to be opened in the editor for the specified input filenames. The `outputs`
+/// are the initial, editable output filenames, one for each input filename; usually these are
+/// just the input filenames. The `header` lines are written at the top of the buffer as comments.
+/// If `escaped` is true, backslashes, tabs, and newlines in filenames are written as escape
+/// sequences, otherwise filenames containing newlines are rejected. If `annotations` isn't empty,
+/// each file's line ends with its annotation after `ANNOTATION_PREFIX`.
+pub fn render(
+    inputs: &[String],
+    outputs: &[String],
+    format: BufferFormat,
+    header: &[String],
+    escaped: bool,
+    annotations: &[String],
+) -> Result<String> {
+    let inputs = buffer_names(inputs, escaped);
+    let outputs = buffer_names(outputs, escaped);
+    if let Some(name) = inputs.iter().chain(outputs.iter()).find(|name| name.contains(['\n', '\r'])) {
+        return Err(Error::Buffer(format!(
+            "the filename '{}' contains a newline character and can only be edited with --null",
+            name.escape_debug()
+        )));
+    }
+    if !annotations.is_empty() {
+        if let Some(name) = inputs.iter().chain(outputs.iter()).find(|name| name.contains(ANNOTATION_PREFIX)) {
+            return Err(Error::Buffer(format!(
+                "the filename '{}' contains a tab followed by '##' and cannot be annotated",
+                name.escape_debug()
+            )));
+        }
+    }
+    let note = |index: usize| match annotations.get(index) {
+        Some(annotation) if !annotation.is_empty() => format!("{}{}", ANNOTATION_PREFIX, annotation),
+        _ => String::new(),
+    };
+    let mut text = String::new();
+    for line in header {
+        if line.is_empty() {
+            text.push_str(&format!("{}\n", COMMENT_PREFIX));
+        } else {
+            text.push_str(&format!("{} {}\n", COMMENT_PREFIX, line));
+        }
+    }
+    match format {
+        BufferFormat::Single => {
+            for (index, output_file) in outputs.iter().enumerate() {
+                text.push_str(output_file);
+                text.push_str(&note(index));
+                text.push('\n');
+            }
+            Ok(text)
+        }
+        BufferFormat::Dual => {
+            if let Some(name) = inputs.iter().chain(outputs.iter()).find(|name| name.contains('\t')) {
+                return Err(Error::Buffer(format!(
+                    "the filename '{}' contains a tab character and cannot be edited in dual format",
+                    name
+                )));
+            }
+
+            // We pad the first column with tabs so the destinations line up, assuming 8-column
+            // tab stops. Tabs (unlike spaces) can't be confused with part of a filename.
+            let width = inputs.iter().map(|input_file| input_file.chars().count()).max().unwrap_or(0);
+            for (index, (input_file, output_file)) in inputs.iter().zip(outputs.iter()).enumerate() {
+                let padding = width / 8 - input_file.chars().count() / 8 + 1;
+                text.push_str(input_file);
+                text.push_str(&"\t".repeat(padding));
+                text.push_str(output_file);
+                text.push_str(&note(index));
+                text.push('\n');
+            }
+            Ok(text)
+        }
+        BufferFormat::Numbered => {
+            let width = inputs.len().to_string().len();
+            for (index, output_file) in outputs.iter().enumerate() {
+                text.push_str(&format!("{:0width$}\t{}{}\n", index + 1, output_file, note(index), width = width));
+            }
+            Ok(text)
+        }
+        BufferFormat::Grouped => {
+            // Files are listed in order of their directories' first appearance. A new header is
+            // written whenever the directory of the output filename changes. (This is usually
+            // the input file's directory but a transformation may have moved the file.)
+            let mut current: Option<&str> = None;
+            for index in group_order(&inputs) {
+                let (prefix, name) = split_dir_prefix(&outputs[index]);
+                if current != Some(prefix) {
+                    if current.is_some() {
+                        text.push('\n');
+                    }
+                    text.push_str(&format!("{} {}\n", COMMENT_PREFIX, if prefix.is_empty() { "./" } else { prefix }));
+                    current = Some(prefix);
+                }
+                text.push_str(name);
+                text.push_str(&note(index));
+                text.push('\n');
+            }
+            Ok(text)
+        }
+    }
+}
+
+
+/// Parses the edited text and returns the list of output filenames, one for each input filename.
+/// Comment lines and lines naming new files to create are ignored. If `escaped` is true, escape
+/// sequences in the text are decoded. If `allow_missing_lines` is true and lines have been
+/// removed from a single-format buffer, the remaining lines are matched to the input filenames
+/// in order and the unmatched input files are left unchanged. Unchanged lines are matched to
+/// their own filenames and changed lines must fill the gaps between them exactly, otherwise the
+/// match is ambiguous and the short list is returned for the planner to reject.
+///
+/// Changed filenames beginning with `delete_prefix` mark files for deletion. They're returned as
+/// rewritten by `normalize_deletions`, so deletions always begin with `DELETE_PREFIX`.
+pub fn parse(
+    inputs: &[String],
+    text: &str,
+    format: BufferFormat,
+    escaped: bool,
+    allow_missing_lines: bool,
+    delete_prefix: &str,
+) -> Result<Vec<String>> {
+    let names = buffer_names(inputs, escaped);
+    let mut outputs = parse_lines(&names, text, format, delete_prefix)?;
+    if allow_missing_lines && outputs.len() < names.len() {
+        if format == BufferFormat::Grouped {
+            // Align against the inputs in group order, then restore the input order.
+            let order = group_order(&names);
+            let ordered: Vec<String> = order.iter().map(|&index| names[index].clone()).collect();
+            if let Some(aligned) = align(&ordered, &outputs) {
+                outputs = vec![String::new(); names.len()];
+                for (index, output_file) in order.into_iter().zip(aligned) {
+                    outputs[index] = output_file;
+                }
+            }
+        } else if let Some(aligned) = align(&names, &outputs) {
+            outputs = aligned;
+        }
+    }
+    if escaped {
+        outputs = outputs.iter().map(|output_file| unescape(output_file)).collect();
+    }
+    normalize_deletions(inputs, &mut outputs, delete_prefix);
+    Ok(outputs)
+}
+
+
+/// Rewrites changed output filenames marked for deletion with `delete_prefix` to use
+/// `DELETE_PREFIX` instead, and gives other changed filenames beginning with `DELETE_PREFIX` a
+/// leading './' so the planner doesn't mistake them for deletions. Does nothing if
+/// `delete_prefix` is `DELETE_PREFIX`.
+pub fn normalize_deletions(inputs: &[String], outputs: &mut [String], delete_prefix: &str) {
+    if delete_prefix == DELETE_PREFIX {
+        return;
+    }
+    for (input_file, output_file) in inputs.iter().zip(outputs.iter_mut()) {
+        if input_file == output_file {
+            continue;
+        }
+        if let Some(name) = output_file.strip_prefix(delete_prefix) {
+            *output_file = format!("{}{}", DELETE_PREFIX, name);
+        } else if output_file.starts_with(DELETE_PREFIX) {
+            *output_file = format!("./{}", output_file);
+        }
+    }
+}
+
+
+/// Returns the output filenames with a trailing '/' added to those whose input files are
+/// directories, so directories stand out in the buffer. Symlinks to directories aren't marked.
+pub fn mark_directories(inputs: &[String], outputs: &[String]) -> Vec<String> {
+    inputs
+        .iter()
+        .zip(outputs)
+        .map(|(input_file, output_file)| {
+            if is_directory(input_file) && !output_file.ends_with(['/', std::path::MAIN_SEPARATOR]) {
+                format!("{}/", output_file)
+            } else {
+                output_file.clone()
+            }
+        })
+        .collect()
+}
+
+
+/// Removes the trailing separators from the output filenames of directories, as added by
+/// `mark_directories()`.
+pub fn unmark_directories(inputs: &[String], outputs: &mut [String]) {
+    for (input_file, output_file) in inputs.iter().zip(outputs.iter_mut()) {
+        let trimmed = output_file.trim_end_matches(['/', std::path::MAIN_SEPARATOR]);
+        if trimmed.len() < output_file.len() && !trimmed.is_empty() && is_directory(input_file) {
+            output_file.truncate(trimmed.len());
+        }
+    }
+}
+
+
+// Returns true if `path` is a directory rather than a file or a symlink.
+fn is_directory(path: &str) -> bool {
+    extended(Path::new(path)).symlink_metadata().is_ok_and(|metadata| metadata.is_dir())
+}
+
+
+/// Removes the annotations added by `render()` from the edited text.
+pub fn strip_annotations(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    for line in text.lines() {
+        stripped.push_str(line.split_once(ANNOTATION_PREFIX).map_or(line, |(line, _)| line));
+        stripped.push('\n');
+    }
+    stripped
+}
+
+
+/// Returns the new files to create named by lines beginning with `CREATE_PREFIX` in the edited
+/// text. In the grouped format, names are relative to their group's directory. Lines for input
+/// files whose names begin with `CREATE_PREFIX` are left alone.
+pub fn created_files(
+    inputs: &[String],
+    text: &str,
+    format: BufferFormat,
+    escaped: bool,
+) -> Result<Vec<String>> {
+    let names = buffer_names(inputs, escaped);
+    let existing = line_starts(&names, format);
+    let mut prefix = String::new();
+    let mut created = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if format == BufferFormat::Grouped {
+            if let Some(dir) = group_header(line) {
+                prefix = if dir == "./" { String::new() } else { dir.to_string() };
+                continue;
+            }
+        }
+        let name = match line.strip_prefix(CREATE_PREFIX) {
+            Some(name) if is_create(line, &existing) => name,
+            _ => continue,
+        };
+        if name.trim().is_empty() {
+            return Err(Error::Buffer(format!("line {} does not name a file to create: {}", index + 1, line)));
+        }
+        let path = format!("{}{}", prefix, name);
+        created.push(if escaped { unescape(&path) } else { path });
+    }
+    Ok(created)
+}
+
+
+/// Returns the line number in the edited text, counting from 1, of the output filename for each
+/// input filename, so problems with the output filenames can be reported by line. The number is
+/// `None` if the input file has no line, e.g. because its line was removed, or if lines were
+/// removed from a single-format or grouped buffer and the lines can't be matched to the inputs by
+/// position.
+pub fn line_numbers(inputs: &[String], text: &str, format: BufferFormat, escaped: bool) -> Vec<Option<usize>> {
+    let names = buffer_names(inputs, escaped);
+    let existing = line_starts(&names, format);
+    let mut numbers = vec![None; names.len()];
+    match format {
+        BufferFormat::Single | BufferFormat::Grouped => {
+            // As in `parse_lines()`, blank lines in a single-format buffer count as filenames
+            // except at the start and end of the buffer. Group headers are comments.
+            let lines: Vec<(usize, &str)> = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !is_comment(line, &existing) && !is_create(line, &existing))
+                .filter(|(_, line)| format == BufferFormat::Single || !line.trim().is_empty())
+                .collect();
+            let start = lines.iter().position(|(_, line)| !line.trim().is_empty()).unwrap_or(lines.len());
+            let end = lines.iter().rposition(|(_, line)| !line.trim().is_empty()).map_or(start, |end| end + 1);
+            let lines = &lines[start..end];
+            if lines.len() != names.len() {
+                return numbers;
+            }
+            let order: Vec<usize> = match format {
+                BufferFormat::Grouped => group_order(&names),
+                _ => (0..names.len()).collect(),
+            };
+            for (index, (line_index, _)) in order.into_iter().zip(lines) {
+                numbers[index] = Some(line_index + 1);
+            }
+        }
+        BufferFormat::Dual => {
+            let indices: HashMap<&str, usize> =
+                names.iter().enumerate().map(|(index, name)| (name.as_str(), index)).collect();
+            for (line_index, line) in text.lines().enumerate() {
+                if is_comment(line, &existing) || is_create(line, &existing) {
+                    continue;
+                }
+                if let Some(&index) = line.split_once('\t').and_then(|(input_file, _)| indices.get(input_file)) {
+                    numbers[index] = Some(line_index + 1);
+                }
+            }
+        }
+        BufferFormat::Numbered => {
+            for (line_index, line) in text.lines().enumerate() {
+                if is_comment(line, &existing) || is_create(line, &existing) {
+                    continue;
+                }
+                let number = line.split_once('\t').and_then(|(number, _)| number.trim().parse::<usize>().ok());
+                if let Some(number) = number.filter(|number| *number >= 1 && *number <= names.len()) {
+                    numbers[number - 1] = Some(line_index + 1);
+                }
+            }
+        }
+    }
+    numbers
+}
+
+
+// Returns the filenames as they appear in the buffer.
+fn buffer_names(inputs: &[String], escaped: bool) -> Vec<String> {
+    if escaped {
+        inputs.iter().map(|input_file| escape(input_file)).collect()
+    } else {
+        inputs.to_vec()
+    }
+}
+
+
+// Parses the edited text and returns the list of output filenames as they appear in the buffer.
+// In the grouped format, `delete_prefix` and `PURGE_PREFIX` are moved ahead of the group's
+// directory.
+fn parse_lines(inputs: &[String], text: &str, format: BufferFormat, delete_prefix: &str) -> Result<Vec<String>> {
+    let existing = line_starts(inputs, format);
+    match format {
+        BufferFormat::Single => {
+            let lines: Vec<&str> =
+                text.lines().filter(|line| !is_comment(line, &existing) && !is_create(line, &existing)).collect();
+            Ok(lines.join("\n").trim().lines().map(|s| s.to_string()).collect())
+        }
+        BufferFormat::Dual => {
+            let mut destinations: HashMap<&str, &str> = HashMap::new();
+            for (index, line) in text.lines().enumerate() {
+                if line.trim().is_empty() || is_comment(line, &existing) || is_create(line, &existing) {
+                    continue;
+                }
+                let (input_file, output_file) = line.split_once('\t').ok_or_else(|| {
+                    Error::Buffer(format!("line {} is missing the destination column: {}", index + 1, line))
+                })?;
+                if !inputs.iter().any(|s| s == input_file) {
+                    return Err(Error::Buffer(format!(
+                        "line {} does not begin with one of the original filenames: {}",
+                        index + 1,
+                        line
+                    )));
+                }
+                if destinations.insert(input_file, output_file.trim_start_matches('\t')).is_some() {
+                    return Err(Error::Buffer(format!(
+                        "the filename '{}' appears in the first column multiple times",
+                        input_file
+                    )));
+                }
+            }
+
+            // Lines removed from the buffer leave the corresponding files unchanged.
+            Ok(inputs
+                .iter()
+                .map(|input_file| match destinations.get(input_file.as_str()) {
+                    Some(output_file) => output_file.to_string(),
+                    None => input_file.to_string(),
+                })
+                .collect())
+        }
+        BufferFormat::Numbered => {
+            let mut outputs = inputs.to_vec();
+            let mut seen = vec![false; inputs.len()];
+            for (index, line) in text.lines().enumerate() {
+                if line.trim().is_empty() || is_comment(line, &existing) || is_create(line, &existing) {
+                    continue;
+                }
+                let parsed = line
+                    .split_once('\t')
+                    .and_then(|(number, output_file)| Some((number.trim().parse::<usize>().ok()?, output_file)))
+                    .filter(|(number, _)| *number >= 1 && *number <= inputs.len());
+                let (number, output_file) = parsed.ok_or_else(|| {
+                    Error::Buffer(format!("line {} does not begin with a valid file number: {}", index + 1, line))
+                })?;
+                if seen[number - 1] {
+                    return Err(Error::Buffer(format!("the file number {} appears multiple times", number)));
+                }
+                seen[number - 1] = true;
+                outputs[number - 1] = output_file.to_string();
+            }
+
+            // Lines removed from the buffer leave the corresponding files unchanged.
+            Ok(outputs)
+        }
+        BufferFormat::Grouped => {
+            let mut prefix = String::new();
+            let mut grouped = Vec::new();
+            for line in text.lines() {
+                if let Some(dir) = group_header(line).filter(|_| !existing.contains(line)) {
+                    prefix = if dir == "./" { String::new() } else { dir.to_string() };
+                    continue;
+                }
+                if line.trim().is_empty() || is_comment(line, &existing) || is_create(line, &existing) {
+                    continue;
+                }
+                // An unchanged line for a file whose name begins with a prefix keeps its name. A
+                // leading './' escapes a prefix inside a group's directory.
+                if existing.contains(line) {
+                    grouped.push(format!("{}{}", prefix, line));
+                } else if let Some(name) = line.strip_prefix(delete_prefix) {
+                    grouped.push(format!("{}{}{}", delete_prefix, prefix, name));
+                } else if let Some(name) = line.strip_prefix(PURGE_PREFIX) {
+                    grouped.push(format!("{}{}{}", PURGE_PREFIX, prefix, name));
+                } else if let Some(name) = copy_destination(line) {
+                    grouped.push(format!("{}{}{}", COPY_PREFIX, prefix, name));
+                } else if let Some(name) = line.strip_prefix("./").filter(|_| !prefix.is_empty()) {
+                    grouped.push(format!("{}{}", prefix, name));
+                } else {
+                    grouped.push(format!("{}{}", prefix, line));
+                }
+            }
+
+            // The lines are in group order. We restore the input order if the line count
+            // matches, otherwise we leave the lines in group order so `align()` can match them
+            // against the inputs in the same order.
+            let order = group_order(inputs);
+            if grouped.len() != inputs.len() {
+                return Ok(grouped);
+            }
+            let mut outputs = vec![String::new(); inputs.len()];
+            for (index, output_file) in order.into_iter().zip(grouped) {
+                outputs[index] = output_file;
+            }
+            Ok(outputs)
+        }
+    }
+}
+
+
+// Returns the indices of the filenames ordered by directory. Directories appear in the order of
+// their first filename; within each directory, the original order is preserved.
+fn group_order(names: &[String]) -> Vec<usize> {
+    let mut ranks: HashMap<&str, usize> = HashMap::new();
+    for name in names {
+        let (prefix, _) = split_dir_prefix(name);
+        let next = ranks.len();
+        ranks.entry(prefix).or_insert(next);
+    }
+    let mut order: Vec<usize> = (0..names.len()).collect();
+    order.sort_by_key(|&index| ranks[split_dir_prefix(&names[index]).0]);
+    order
+}
+
+
+// Splits a filename into its directory prefix, including the trailing separator, and the final
+// component, e.g. 'photos/a.jpg' becomes ('photos/', 'a.jpg'). The prefix is empty for a bare
+// filename. A trailing separator stays with the final component.
+fn split_dir_prefix(name: &str) -> (&str, &str) {
+    let separators: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+    let separator = name.trim_end_matches(separators).rfind(separators);
+    match separator {
+        Some(index) => name.split_at(index + 1),
+        None => ("", name),
+    }
+}
+
+
+// Returns the directory named by a group header line in the grouped format, i.e. a comment whose
+// text ends with a path separator.
+fn group_header(line: &str) -> Option<&str> {
+    let dir = line.strip_prefix(COMMENT_PREFIX)?.trim();
+    if dir.ends_with('/') || (cfg!(windows) && dir.ends_with('\\')) {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+
+// Returns true if the line is a comment. As in `is_create()`, a line for an existing file whose
+// name happens to begin with `COMMENT_PREFIX` isn't mistaken for a comment.
+fn is_comment(line: &str, existing: &HashSet<&str>) -> bool {
+    line.starts_with(COMMENT_PREFIX) && !existing.contains(line.split('\t').next().unwrap_or(line))
+}
+
+
+// Returns true if the line names a new file to create. The `existing` filenames are those that can
+// begin a line in the buffer, as returned by `line_starts()`, so a line for an existing file whose
+// name happens to begin with `CREATE_PREFIX` isn't mistaken for a new file.
+fn is_create(line: &str, existing: &HashSet<&str>) -> bool {
+    line.starts_with(CREATE_PREFIX) && !existing.contains(line.split('\t').next().unwrap_or(line))
+}
+
+
+// Returns the filenames as they can appear at the start of a line in the buffer: the final
+// components of the filenames in the grouped format, otherwise the filenames themselves.
+fn line_starts(names: &[String], format: BufferFormat) -> HashSet<&str> {
+    match format {
+        BufferFormat::Grouped => names.iter().map(|name| split_dir_prefix(name).1).collect(),
+        _ => names.iter().map(|name| name.as_str()).collect(),
+    }
+}
+
+
+// Matches a shortened list of output filenames to the input filenames, preserving their order.
+// Unchanged lines anchor to their input filenames. The changed lines between two anchors must
+// replace every input filename between them, so each has exactly one possible match. If there
+// are fewer, we can't tell which files they belong to and return `None`. Input files without a
+// matching output are left unchanged.
+fn align(inputs: &[String], outputs: &[String]) -> Option<Vec<String>> {
+    let positions: HashMap<&str, usize> =
+        inputs.iter().enumerate().map(|(index, input_file)| (input_file.as_str(), index)).collect();
+    let mut aligned = inputs.to_vec();
+
+    // The index of the first input filename after the last anchor, and the changed lines since.
+    let mut next = 0;
+    let mut changed: Vec<&String> = Vec::new();
+    for output_file in outputs.iter().map(Some).chain([None]) {
+        // The end of the input list acts as a final anchor.
+        let anchor = match output_file {
+            Some(output_file) => match positions.get(output_file.as_str()) {
+                Some(&index) => index,
+                None => {
+                    changed.push(output_file);
+                    continue;
+                }
+            },
+            None => inputs.len(),
+        };
+        if anchor < next || (!changed.is_empty() && changed.len() != anchor - next) {
+            return None;
+        }
+        for (index, output_file) in (next..anchor).zip(changed.drain(..)) {
+            aligned[index] = output_file.clone();
+        }
+        next = anchor + 1;
+    }
+    Some(aligned)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    // Parses a single-format buffer with --allow-missing-lines set.
+    fn parse_missing(inputs: &[&str], text: &str) -> Vec<String> {
+        parse(&strings(inputs), text, BufferFormat::Single, false, true, DELETE_PREFIX).unwrap()
+    }
+
+    #[test]
+    fn removed_unchanged_lines() {
+        let outputs = parse_missing(&["a.txt", "b.txt", "c.txt", "d.txt"], "a.txt\nd.txt\n");
+        assert_eq!(outputs, strings(&["a.txt", "b.txt", "c.txt", "d.txt"]));
+    }
+
+    #[test]
+    fn changed_line_with_one_match() {
+        let outputs = parse_missing(&["a.txt", "b.txt", "c.txt", "d.txt"], "a.txt\nB.txt\nc.txt\n");
+        assert_eq!(outputs, strings(&["a.txt", "B.txt", "c.txt", "d.txt"]));
+
+        let outputs = parse_missing(&["a.txt", "b.txt", "c.txt", "d.txt"], "A.txt\nb.txt\nd.txt\n");
+        assert_eq!(outputs, strings(&["A.txt", "b.txt", "c.txt", "d.txt"]));
+    }
+
+    #[test]
+    fn changed_line_with_several_matches() {
+        // The edited line could replace either of the last two lines so nothing is matched.
+        let inputs = ["img_001.jpg", "img_002.jpg", "img_003.jpg"];
+        let outputs = parse_missing(&inputs, "img_001.jpg\nholiday.jpg\n");
+        assert_eq!(outputs, strings(&["img_001.jpg", "holiday.jpg"]));
+
+        let outputs = parse_missing(&["a.txt", "b.txt", "c.txt", "d.txt"], "a.txt\nB.txt\nd.txt\n");
+        assert_eq!(outputs, strings(&["a.txt", "B.txt", "d.txt"]));
+    }
+
+    #[test]
+    fn reordered_lines() {
+        let outputs = parse_missing(&["a.txt", "b.txt", "c.txt"], "c.txt\na.txt\n");
+        assert_eq!(outputs, strings(&["c.txt", "a.txt"]));
+    }
+
+    #[test]
+    fn input_beginning_with_comment_prefix() {
+        let inputs = strings(&["##notes.txt", "b.txt"]);
+        let outputs = parse(&inputs, "## A comment.\n##notes.txt\nc.txt\n", BufferFormat::Single, false, false, "#");
+        assert_eq!(outputs.unwrap(), strings(&["##notes.txt", "c.txt"]));
+
+        let inputs = strings(&["dir/##notes.txt", "dir/b.txt"]);
+        let text = "## dir/\n##notes.txt\nc.txt\n";
+        let outputs = parse(&inputs, text, BufferFormat::Grouped, false, false, "#");
+        assert_eq!(outputs.unwrap(), strings(&["dir/##notes.txt", "dir/c.txt"]));
+    }
+
+    #[test]
+    fn align_fills_gaps() {
+        let inputs = strings(&["a", "b", "c", "d", "e"]);
+        assert_eq!(align(&inputs, &strings(&["a", "B", "C", "d"])), Some(strings(&["a", "B", "C", "d", "e"])));
+        assert_eq!(align(&inputs, &strings(&["b", "d", "E"])), Some(strings(&["a", "b", "c", "d", "E"])));
+        assert_eq!(align(&inputs, &strings(&["b", "D"])), None);
+        assert_eq!(align(&inputs, &strings(&["b", "b"])), None);
+    }
+}
@@ -0,0 +1,353 @@
+// Generates shell completion scripts from the help text so they can't drift out of sync with the
+// flags and options the program actually accepts.
+
+
+// A flag or option parsed from a help text.
+struct Opt {
+    short: Option<String>,
+    long: String,
+
+    // The value placeholder, e.g. 'file' for '--log <file>'. `None` for flags.
+    value: Option<String>,
+    description: String,
+}
+
+
+// The flags and options accepted by the main command (with an empty name) or by a subcommand.
+struct Command {
+    name: String,
+    options: Vec<Opt>,
+}
+
+
+// The shells we can generate completion scripts for.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+
+// Returns the completion script for the specified shell, or `None` if the shell isn't supported.
+// `helptext` is the main help text and `commands` lists each subcommand's name and help text.
+// Subcommand descriptions are read from the 'Commands:' section of the main help text.
+pub fn generate(shell: &str, helptext: &str, commands: &[(&str, &str)]) -> Option<String> {
+    let mut parsed = vec![Command { name: String::new(), options: parse_options(helptext) }];
+    for (name, text) in commands {
+        parsed.push(Command { name: name.to_string(), options: parse_options(text) });
+    }
+    let descriptions = parse_commands(helptext);
+    match shell {
+        "bash" => Some(bash(&parsed)),
+        "zsh" => Some(zsh(&parsed, &descriptions)),
+        "fish" => Some(fish(&parsed, &descriptions)),
+        "powershell" => Some(powershell(&parsed, &descriptions)),
+        _ => None,
+    }
+}
+
+
+// Parses the 'Options:' and 'Flags:' sections of a help text. An option whose description
+// doesn't fit on its line takes its description from the following line.
+fn parse_options(helptext: &str) -> Vec<Opt> {
+    let mut options: Vec<Opt> = Vec::new();
+    let mut in_section = false;
+    for line in helptext.lines() {
+        if !line.starts_with(' ') && !line.is_empty() {
+            in_section = line == "Options:" || line == "Flags:";
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let trimmed = line.trim();
+        if !trimmed.starts_with('-') {
+            if let Some(last) = options.last_mut().filter(|last| last.description.is_empty()) {
+                last.description = trimmed.to_string();
+            }
+            continue;
+        }
+        let (spec, description) = match trimmed.find("  ") {
+            Some(index) => (&trimmed[..index], trimmed[index..].trim()),
+            None => (trimmed, ""),
+        };
+        let mut opt = Opt { short: None, long: String::new(), value: None, description: description.to_string() };
+        for word in spec.split_whitespace() {
+            let word = word.trim_end_matches(',');
+            if let Some(long) = word.strip_prefix("--") {
+                opt.long = long.to_string();
+            } else if let Some(short) = word.strip_prefix('-') {
+                opt.short = Some(short.to_string());
+            } else if word.starts_with('<') {
+                opt.value = Some(word.trim_matches(['<', '>']).to_string());
+            }
+        }
+        if !opt.long.is_empty() {
+            options.push(opt);
+        }
+    }
+    options
+}
+
+
+// Parses the names and descriptions of the subcommands from the 'Commands:' section of the main
+// help text.
+fn parse_commands(helptext: &str) -> Vec<(String, String)> {
+    let mut commands = Vec::new();
+    let mut in_section = false;
+    for line in helptext.lines() {
+        if !line.starts_with(' ') && !line.is_empty() {
+            in_section = line == "Commands:";
+            continue;
+        }
+        if in_section {
+            if let Some((name, description)) = line.trim().split_once("  ") {
+                commands.push((name.to_string(), description.trim().to_string()));
+            }
+        }
+    }
+    commands
+}
+
+
+// Returns true if the option's value is a path, so the shell should complete filenames.
+fn takes_path(opt: &Opt) -> bool {
+    matches!(opt.value.as_deref(), Some("file") | Some("dir"))
+}
+
+
+fn bash(commands: &[Command]) -> String {
+    let mut script = String::from("# Bash completion for vimv. Add to ~/.bashrc:\n");
+    script.push_str("#   source <(vimv completions bash)\n\n");
+    script.push_str("_vimv() {\n");
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    script.push_str("    local prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n\n");
+
+    // Options that take a value complete filenames for paths and nothing otherwise.
+    let mut path_options = Vec::new();
+    let mut value_options = Vec::new();
+    for opt in commands.iter().flat_map(|command| &command.options).filter(|opt| opt.value.is_some()) {
+        let names = if takes_path(opt) { &mut path_options } else { &mut value_options };
+        names.push(format!("--{}", opt.long));
+        if let Some(short) = &opt.short {
+            names.push(format!("-{}", short));
+        }
+    }
+    path_options.sort();
+    path_options.dedup();
+    value_options.sort();
+    value_options.dedup();
+    script.push_str("    case \"$prev\" in\n");
+    if !path_options.is_empty() {
+        script.push_str(&format!("        {})\n", path_options.join("|")));
+        script.push_str("            COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+        script.push_str("            return\n");
+        script.push_str("            ;;\n");
+    }
+    if !value_options.is_empty() {
+        script.push_str(&format!("        {})\n", value_options.join("|")));
+        script.push_str("            COMPREPLY=()\n");
+        script.push_str("            return\n");
+        script.push_str("            ;;\n");
+    }
+    script.push_str("    esac\n\n");
+
+    script.push_str("    local opts\n");
+    script.push_str("    case \"${COMP_WORDS[1]}\" in\n");
+    for command in commands.iter().filter(|command| !command.name.is_empty()) {
+        script.push_str(&format!("        {}) opts=\"{}\" ;;\n", command.name, bash_words(command)));
+    }
+    script.push_str(&format!("        *) opts=\"{}\" ;;\n", bash_words(&commands[0])));
+    script.push_str("    esac\n\n");
+
+    script.push_str("    if [[ \"$cur\" == -* ]]; then\n");
+    script.push_str("        COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))\n");
+    script.push_str("    elif [[ $COMP_CWORD -eq 1 ]]; then\n");
+    let names: Vec<&str> = commands.iter().skip(1).map(|command| command.name.as_str()).collect();
+    script.push_str(&format!(
+        "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -f -- \"$cur\"))\n",
+        names.join(" ")
+    ));
+    script.push_str("    else\n");
+    script.push_str("        COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+    script.push_str("    fi\n");
+    script.push_str("}\n\n");
+    script.push_str("complete -o filenames -F _vimv vimv\n");
+    script
+}
+
+
+// Returns the space-separated list of a command's option names for Bash.
+fn bash_words(command: &Command) -> String {
+    let mut words = Vec::new();
+    for opt in &command.options {
+        words.push(format!("--{}", opt.long));
+        if let Some(short) = &opt.short {
+            words.push(format!("-{}", short));
+        }
+    }
+    words.join(" ")
+}
+
+
+fn zsh(commands: &[Command], descriptions: &[(String, String)]) -> String {
+    let mut script = String::from("#compdef vimv\n\n");
+    script.push_str("# Zsh completion for vimv. Save as '_vimv' in a directory on your $fpath.\n\n");
+    script.push_str("_vimv_first() {\n");
+    script.push_str("    local -a commands\n");
+    script.push_str("    commands=(\n");
+    for (name, description) in descriptions {
+        script.push_str(&format!("        {}\n", zsh_quote(&format!("{}:{}", name, description.replace(':', "\\:")))));
+    }
+    script.push_str("    )\n");
+    script.push_str("    _describe -t commands 'command' commands\n");
+    script.push_str("    _files\n");
+    script.push_str("}\n\n");
+
+    script.push_str("_vimv() {\n");
+    script.push_str("    local -a specs\n");
+    script.push_str("    case $words[2] in\n");
+    for command in commands.iter().filter(|command| !command.name.is_empty()) {
+        script.push_str(&format!("        {})\n", command.name));
+        script.push_str("            specs=(\n");
+        for opt in &command.options {
+            script.push_str(&format!("                {}\n", zsh_spec(opt)));
+        }
+        script.push_str("            )\n");
+        script.push_str("            ;;\n");
+    }
+    script.push_str("        *)\n");
+    script.push_str("            specs=(\n");
+    for opt in &commands[0].options {
+        script.push_str(&format!("                {}\n", zsh_spec(opt)));
+    }
+    script.push_str("                '1: :_vimv_first'\n");
+    script.push_str("            )\n");
+    script.push_str("            ;;\n");
+    script.push_str("    esac\n");
+    script.push_str("    _arguments -s -S $specs '*:file:_files'\n");
+    script.push_str("}\n\n");
+    script.push_str("_vimv \"$@\"\n");
+    script
+}
+
+
+// Returns the `_arguments` spec for an option.
+fn zsh_spec(opt: &Opt) -> String {
+    let description = opt.description.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]");
+    let value = match &opt.value {
+        Some(value) if takes_path(opt) => format!(":{}:_files", value),
+        Some(value) => format!(":{}: ", value),
+        None => String::new(),
+    };
+    match &opt.short {
+        Some(short) => format!(
+            "'(-{} --{})'{{-{},--{}}}{}",
+            short,
+            opt.long,
+            short,
+            opt.long,
+            zsh_quote(&format!("[{}]{}", description, value))
+        ),
+        None => zsh_quote(&format!("--{}[{}]{}", opt.long, description, value)),
+    }
+}
+
+
+// Quotes a string for zsh using single quotes.
+fn zsh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+
+fn fish(commands: &[Command], descriptions: &[(String, String)]) -> String {
+    let mut script = String::from("# Fish completion for vimv. Save as ~/.config/fish/completions/vimv.fish.\n\n");
+    let names: Vec<&str> = descriptions.iter().map(|(name, _)| name.as_str()).collect();
+    for (name, description) in descriptions {
+        script.push_str(&format!(
+            "complete -c vimv -n 'not __fish_seen_subcommand_from {}' -a {} -d {}\n",
+            names.join(" "),
+            name,
+            fish_quote(description)
+        ));
+    }
+    for command in commands {
+        let condition = if command.name.is_empty() {
+            format!("not __fish_seen_subcommand_from {}", names.join(" "))
+        } else {
+            format!("__fish_seen_subcommand_from {}", command.name)
+        };
+        for opt in &command.options {
+            let mut line = format!("complete -c vimv -n {}", fish_quote(&condition));
+            if let Some(short) = &opt.short {
+                line.push_str(&format!(" -s {}", short));
+            }
+            line.push_str(&format!(" -l {}", opt.long));
+            if opt.value.is_some() {
+                line.push_str(if takes_path(opt) { " -r -F" } else { " -x" });
+            }
+            line.push_str(&format!(" -d {}\n", fish_quote(&opt.description)));
+            script.push_str(&line);
+        }
+    }
+    script
+}
+
+
+// Quotes a string for fish using single quotes.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+
+fn powershell(commands: &[Command], descriptions: &[(String, String)]) -> String {
+    let mut script = String::from("# PowerShell completion for vimv. Add to your $PROFILE:\n");
+    script.push_str("#   vimv completions powershell | Out-String | Invoke-Expression\n\n");
+    script.push_str("using namespace System.Management.Automation\n\n");
+    script.push_str("Register-ArgumentCompleter -Native -CommandName vimv -ScriptBlock {\n");
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n\n");
+    script.push_str("    $elements = $commandAst.CommandElements\n");
+    script.push_str("    $command = if ($elements.Count -gt 1) { $elements[1].Extent.Text } else { '' }\n");
+    script.push_str("    $completions = @()\n\n");
+
+    // Subcommands are only offered as the first argument.
+    script.push_str("    if ($elements.Count -le 2 -and -not $wordToComplete.StartsWith('-')) {\n");
+    script.push_str("        $completions = @(\n");
+    for (name, description) in descriptions {
+        script.push_str(&format!(
+            "            [CompletionResult]::new({}, {}, [CompletionResultType]::Command, {})\n",
+            powershell_quote(name),
+            powershell_quote(name),
+            powershell_quote(description)
+        ));
+    }
+    script.push_str("        )\n");
+    script.push_str("    } else {\n");
+    script.push_str("        $completions = switch ($command) {\n");
+    for command in commands {
+        let label = if command.name.is_empty() { "default".to_string() } else { powershell_quote(&command.name) };
+        script.push_str(&format!("            {} {{\n", label));
+        for opt in &command.options {
+            let mut names = vec![format!("--{}", opt.long)];
+            if let Some(short) = &opt.short {
+                names.push(format!("-{}", short));
+            }
+            for name in names {
+                script.push_str(&format!(
+                    "                [CompletionResult]::new({}, {}, [CompletionResultType]::ParameterName, {})\n",
+                    powershell_quote(&name),
+                    powershell_quote(&opt.long),
+                    powershell_quote(if opt.description.is_empty() { &opt.long } else { &opt.description })
+                ));
+            }
+        }
+        script.push_str("            }\n");
+    }
+    script.push_str("        }\n");
+    script.push_str("    }\n\n");
+    script.push_str("    $completions | Where-Object { $_.CompletionText -like \"$wordToComplete*\" }\n");
+    script.push_str("}\n");
+    script
+}
+
+
+// Quotes a string for PowerShell using single quotes.
+fn powershell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,86 @@
+// Word-level diffs for highlighting the changed portions of filenames.
+
+
+// The kind of a run of text in a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // Text common to both filenames.
+    Same,
+
+    // Text only in the old filename.
+    Removed,
+
+    // Text only in the new filename.
+    Added,
+}
+
+
+// Returns a word-level diff of `old` and `new` as a list of runs of text. Words are runs of
+// letters and digits; every other character counts as a word on its own so separators like '_'
+// and '.' are matched individually. We find the longest common subsequence of words, then merge
+// adjacent words of the same kind.
+pub fn word_diff(old: &str, new: &str) -> Vec<(Kind, String)> {
+    let old_words = split_words(old);
+    let new_words = split_words(new);
+    let (n, m) = (old_words.len(), new_words.len());
+
+    // `lcs[i][j]` is the length of the longest common subsequence of the words from `i` onwards
+    // in `old` and from `j` onwards in `new`.
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_words[i] == new_words[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut runs: Vec<(Kind, String)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_words[i] == new_words[j] {
+            push(&mut runs, Kind::Same, old_words[i]);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            push(&mut runs, Kind::Removed, old_words[i]);
+            i += 1;
+        } else {
+            push(&mut runs, Kind::Added, new_words[j]);
+            j += 1;
+        }
+    }
+    runs
+}
+
+
+// Appends a word to the list of runs, extending the last run if it has the same kind.
+fn push(runs: &mut Vec<(Kind, String)>, kind: Kind, word: &str) {
+    match runs.last_mut() {
+        Some((last_kind, text)) if *last_kind == kind => text.push_str(word),
+        _ => runs.push((kind, word.to_string())),
+    }
+}
+
+
+// Splits text into words: runs of letters and digits, and single other characters.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+    for (index, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            start.get_or_insert(index);
+            continue;
+        }
+        if let Some(word_start) = start.take() {
+            words.push(&text[word_start..index]);
+        }
+        words.push(&text[index..index + c.len_utf8()]);
+    }
+    if let Some(word_start) = start {
+        words.push(&text[word_start..]);
+    }
+    words
+}
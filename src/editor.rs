@@ -0,0 +1,160 @@
+// Opens text in the user's editor.
+
+use rand::Rng;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+
+// Editors to try, in order, if no editor has been configured or the configured editor can't be
+// launched.
+#[cfg(windows)]
+const FALLBACK_EDITORS: &[&str] = &["notepad.exe"];
+#[cfg(not(windows))]
+const FALLBACK_EDITORS: &[&str] = &["sensible-editor", "nano", "vi"];
+
+
+// The result of an editing session.
+pub struct Edited {
+    // The edited text.
+    pub text: String,
+
+    // The editor command that was used.
+    pub editor: String,
+
+    // Notes on configured editors that were skipped, e.g. because they couldn't be launched.
+    pub notes: Vec<String>,
+}
+
+
+// Writes `text` to a temporary file, opens it in the editor, and returns the edited text once the
+// editor exits. The editor command is taken from `editor` if specified, otherwise from $VISUAL or
+// $EDITOR. The command is split into words like a shell would so it can include arguments, e.g.
+// "code --wait" or "emacsclient -t". If no editor has been configured or the configured editor
+// can't be launched, we fall back through a list of common editors. The temporary file is named
+// 'vimv-XXXXXXXX.<extension>' so editors can recognise Vimv buffers.
+pub fn edit(text: &str, editor: Option<&str>, extension: &str) -> Result<Edited, String> {
+    let configured = editor.map(|editor| editor.to_string()).or_else(env_editor);
+    let mut notes = Vec::new();
+    if configured.is_none() {
+        notes.push("no editor configured, set $EDITOR or use --editor".to_string());
+    }
+    let candidates = configured.iter().map(|command| command.as_str()).chain(FALLBACK_EDITORS.iter().copied());
+
+    let path = create_temp_file(text, extension)?;
+    let mut result = Err("cannot launch an editor, set $EDITOR or use --editor".to_string());
+    for command in candidates {
+        let words = match split_command(command) {
+            Some(words) if !words.is_empty() => words,
+            _ => {
+                notes.push(format!("cannot parse the editor command '{}'", command));
+                continue;
+            }
+        };
+        let status = match Command::new(&words[0]).args(&words[1..]).arg(&path).status() {
+            Ok(status) => status,
+            Err(err) => {
+                if configured.as_deref() == Some(command) {
+                    notes.push(format!("cannot launch the editor '{}': {}", command, err));
+                }
+                continue;
+            }
+        };
+        result = if status.success() {
+            fs::read_to_string(&path)
+                .map(|text| Edited { text, editor: command.to_string(), notes: std::mem::take(&mut notes) })
+                .map_err(|err| format!("cannot read the edited file '{}': {}", path.display(), err))
+        } else {
+            Err(format!("the editor '{}' exited with {}", command, status))
+        };
+        break;
+    }
+    let _ = fs::remove_file(&path);
+    result
+}
+
+
+// Returns the editor command specified by $VISUAL or $EDITOR, if either is set and non-empty.
+fn env_editor() -> Option<String> {
+    ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+
+// Creates a new temporary file containing `text` with the specified extension and returns its path.
+fn create_temp_file(text: &str, extension: &str) -> Result<PathBuf, String> {
+    let mut rng = rand::thread_rng();
+    for _ in 0..10 {
+        let path = env::temp_dir().join(format!("vimv-{:08x}.{}", rng.gen::<u32>(), extension));
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(format!("cannot create the temporary file '{}': {}", path.display(), err)),
+        };
+        file.write_all(text.as_bytes())
+            .map_err(|err| format!("cannot write the temporary file '{}': {}", path.display(), err))?;
+        return Ok(path);
+    }
+    Err("failed to generate a unique temporary filename for the editor buffer".to_string())
+}
+
+
+// Splits a command into words like a POSIX shell: words are separated by whitespace, single
+// quotes preserve their content literally, and double quotes allow '\"' and '\\' escapes. Outside
+// quotes, a backslash escapes the next character except on Windows where backslashes are path
+// separators. Returns `None` if a quote is unclosed.
+pub fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' if !cfg!(windows) => {
+                let word = word.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = word {
+        words.push(word);
+    }
+    Some(words)
+}
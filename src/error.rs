@@ -0,0 +1,180 @@
+use std::fmt;
+
+
+/// Convenience alias for results returned by the library.
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+/// Errors returned when validating, planning, or executing a batch of operations.
+#[derive(Debug)]
+pub enum Error {
+    /// An input file does not exist.
+    InputNotFound(String),
+
+    /// A filename appears in the input list more than once.
+    DuplicateInput(String),
+
+    /// The number of output filenames doesn't match the number of input filenames.
+    CountMismatch { inputs: usize, outputs: usize },
+
+    /// A filename appears in the output list more than once.
+    DuplicateOutput(String),
+
+    /// A filename appears in the output list more than once when compared case-insensitively.
+    DuplicateOutputCaseInsensitive(String),
+
+    /// An output filename isn't valid, e.g. on the target platform. The second field explains why.
+    InvalidOutput(String, String),
+
+    /// An output filename would overwrite an existing directory.
+    DirectoryExists(String),
+
+    /// An output filename would overwrite an existing file and overwriting isn't allowed.
+    FileExists(String),
+
+    /// A file to create already exists.
+    CreateExists(String),
+
+    /// An output filename would overwrite an existing file whose content differs from the input
+    /// file replacing it and only duplicates may be overwritten.
+    OverwriteUnique(String, String),
+
+    /// An output filename or a file to create resolves to a path outside the root directory the
+    /// batch is restricted to. The second field is the root directory.
+    OutsideRoot(String, String),
+
+    /// An input file is a directory and can't be hard linked.
+    LinkDirectory(String),
+
+    /// An output filename is on a different file system to its input file and can't be hard linked.
+    LinkCrossesDevices(String, String),
+
+    /// Validation found more than one problem with the output filenames. Each problem is one of
+    /// the errors above.
+    Multiple(Vec<Error>),
+
+    /// An output file was created by another process after the plan was checked. It hasn't been
+    /// overwritten.
+    OutputAppeared(String),
+
+    /// The renames of a directory and the paths inside it can't be ordered consistently.
+    NestedRenames(String),
+
+    /// Failed to generate a unique temporary filename based on the specified filename.
+    TempFilename(String),
+
+    /// Failed to read an input directory or file list.
+    Input(String),
+
+    /// The edited buffer is malformed.
+    Buffer(String),
+
+    /// A transformation expression, e.g. a --regex substitution, is malformed.
+    Transform(String),
+
+    /// A file system operation failed.
+    Operation(String),
+
+    /// Another run holds the lock on a directory containing one of the input files.
+    Locked(String),
+
+    /// One or more operations failed in keep-going mode.
+    Incomplete(usize),
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InputNotFound(path) => {
+                write!(f, "the input file '{}' does not exist", path)
+            }
+            Error::DuplicateInput(path) => {
+                write!(f, "the filename '{}' appears in the input list multiple times", path)
+            }
+            Error::CountMismatch { inputs, outputs } => write!(
+                f,
+                "the number of input filenames ({}) does not match the number of output filenames ({})",
+                inputs,
+                outputs
+            ),
+            Error::DuplicateOutput(path) => {
+                write!(f, "the filename '{}' appears in the output list multiple times", path)
+            }
+            Error::DuplicateOutputCaseInsensitive(path) => write!(
+                f,
+                "the filename '{}' appears multiple times in the output list (case \
+                insensitively); this may be intentional but Vimv always treats this situation \
+                as an error to avoid accidentally overwriting files on case-insensitive \
+                file systems",
+                path
+            ),
+            Error::InvalidOutput(path, reason) => {
+                write!(f, "the output filename '{}' is invalid: {}", path, reason)
+            }
+            Error::DirectoryExists(path) => {
+                write!(f, "cannot overwrite the existing directory '{}'", path)
+            }
+            Error::FileExists(path) => {
+                write!(f, "the output file '{}' already exists, use --force to overwrite it", path)
+            }
+            Error::CreateExists(path) => {
+                write!(f, "cannot create '{}', a file with that name already exists", path)
+            }
+            Error::OverwriteUnique(output_file, input_file) => write!(
+                f,
+                "the output file '{}' already exists and its content differs from '{}', use --force to overwrite it",
+                output_file,
+                input_file
+            ),
+            Error::OutsideRoot(path, root) => {
+                write!(f, "the output filename '{}' is outside the restricted directory '{}'", path, root)
+            }
+            Error::LinkDirectory(path) => {
+                write!(f, "cannot hard link the directory '{}', only files can be hard linked", path)
+            }
+            Error::LinkCrossesDevices(input_file, output_file) => write!(
+                f,
+                "cannot hard link '{}' to '{}', hard links cannot cross file systems",
+                input_file,
+                output_file
+            ),
+            Error::Multiple(problems) => {
+                write!(f, "found {} problems with the changes:", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            Error::OutputAppeared(path) => write!(
+                f,
+                "the output file '{}' was created by another process after the changes were checked, \
+                it has not been overwritten",
+                path
+            ),
+            Error::NestedRenames(path) => write!(
+                f,
+                "cannot find a consistent order for renaming '{}' and the renamed directories containing it",
+                path
+            ),
+            Error::TempFilename(base) => write!(
+                f,
+                "failed to generate a unique temporary filename of the form '{}.vimv_temp_XXXX'",
+                base
+            ),
+            Error::Input(message) => write!(f, "{}", message),
+            Error::Buffer(message) => write!(f, "{}", message),
+            Error::Transform(message) => write!(f, "{}", message),
+            Error::Operation(message) => write!(f, "{}", message),
+            Error::Locked(dir) => write!(
+                f,
+                "another instance of vimv is operating on files in '{}', use --wait-lock to wait for it to finish",
+                dir
+            ),
+            Error::Incomplete(count) => write!(f, "{} operation(s) failed", count),
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
@@ -0,0 +1,1168 @@
+use crate::error::{Error, Result};
+use crate::vcs::Vcs;
+use crate::journal::absolute;
+use crate::longpath::extended;
+use crate::metadata::{copy_metadata, Preserve};
+use crate::plan::{get_temp_filename, Operation, RenamePlan};
+use crate::sys;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+
+/// Options controlling how a plan is executed.
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Use the version control system's own commands to rename and delete tracked files.
+    pub vcs: Option<Vcs>,
+
+    /// Don't fall back to copy-and-delete when a rename fails across file systems.
+    pub strict_rename: bool,
+
+    /// Back up existing files before overwriting them.
+    pub backup: Option<Backup>,
+
+    /// Delete files permanently instead of moving them to the trash.
+    pub permanent_delete: bool,
+
+    /// Move deleted files into this directory, preserving their relative paths, instead of moving
+    /// them to the trash. Ignored for permanent deletions.
+    pub archive_dir: Option<PathBuf>,
+
+    /// Move deleted files into this trash directory instead of the system's default trash. The
+    /// directory is laid out as a freedesktop.org trash, with `files` and `info` subdirectories.
+    /// Ignored for permanent deletions and archived files.
+    pub trash_dir: Option<PathBuf>,
+
+    /// Metadata to preserve when copying files.
+    pub preserve: Preserve,
+
+    /// Leave a symlink at the original location of each renamed file pointing to its new location.
+    pub leave_symlink: bool,
+
+    /// Continue executing the remaining operations after a failure.
+    pub keep_going: bool,
+
+    /// Flush the affected directories to disk after each operation so completed operations
+    /// survive a power loss.
+    pub fsync: bool,
+
+    /// The number of operations to execute concurrently. Only operations that touch unrelated
+    /// paths run at the same time. Plans using a version control system or backups always run
+    /// serially. Values of 0 and 1 both mean serial execution.
+    pub jobs: usize,
+
+    /// The number of times to retry a file system call that fails with a transient error, e.g.
+    /// because another process briefly has the file open.
+    pub retries: u32,
+
+    /// The delay before the first retry. The delay doubles with each further retry.
+    pub retry_delay: Duration,
+}
+
+
+/// Controls where existing files are backed up before being overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backup {
+    /// Rename the file by appending a suffix, e.g. `name~`.
+    Suffix(String),
+
+    /// Move the file into a backup directory, preserving its relative path where possible.
+    Dir(PathBuf),
+}
+
+
+/// Receives notifications as a plan is executed. All methods have empty default implementations.
+pub trait Reporter {
+    /// Called when an operation is about to start. With several jobs, this is called as the
+    /// operation is handed to a worker thread, while the other notifications for the operation are
+    /// only sent once it has finished, so operations can start and finish out of order.
+    fn started(&mut self, _op: &Operation) {}
+
+    /// Called before an operation is executed.
+    fn before(&mut self, _op: &Operation) {}
+
+    /// Called after a directory has been created along the path to an output file.
+    fn created_dir(&mut self, _dir: &Path) {}
+
+    /// Called after an existing file has been backed up before being overwritten.
+    fn backed_up(&mut self, _path: &str, _backup: &str) {}
+
+    /// Called after a deleted file has been moved into the archive directory.
+    fn archived(&mut self, _path: &str, _archive: &str) {}
+
+    /// Called after a deleted file has been moved into the trash directory set by the `trash_dir`
+    /// option. `trashed` is the file's path inside the trash directory.
+    fn trashed(&mut self, _path: &str, _trashed: &str) {}
+
+    /// Called after a symlink has been created at `path` pointing to `target`.
+    fn created_symlink(&mut self, _path: &str, _target: &Path) {}
+
+    /// Called after an operation has been executed successfully. `overwrote` is true if the
+    /// operation replaced an existing file.
+    fn after(&mut self, _op: &Operation, _overwrote: bool) {}
+
+    /// Called after an operation has failed or been skipped in keep-going mode.
+    fn failed(&mut self, _op: &Operation, _err: &Error) {}
+}
+
+
+/// A reporter that ignores all notifications.
+pub struct NullReporter;
+
+impl Reporter for NullReporter {}
+
+
+/// Executes the operations in `plan` in order. Execution stops at the first failure unless the
+/// `keep_going` option is set, in which case every remaining operation that doesn't depend on a
+/// failed operation is attempted and `Error::Incomplete` is returned at the end.
+///
+/// If the `jobs` option is greater than 1, independent operations run concurrently. Each
+/// operation still starts only once every earlier operation touching the same paths has
+/// finished, and the reporter is notified of each operation as a whole once it finishes.
+pub fn execute(plan: &RenamePlan, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    check_links(plan)?;
+    if let Some(vcs) = options.vcs {
+        vcs.prepare().map_err(|err| {
+            Error::Operation(format!("cannot prepare the {} repository: {}", vcs.name(), err))
+        })?;
+    }
+
+    // Paths touched by failed operations. Any later operation involving one of these paths is
+    // skipped, e.g. in a cycle we mustn't rename B to A if renaming A out of the way failed.
+    let mut blocked: HashSet<&str> = HashSet::new();
+    let mut failures = 0;
+
+    // Version control commands and backups can't safely run side by side: git takes a lock on
+    // its index and backups can land in shared directories.
+    if options.jobs > 1 && options.vcs.is_none() && options.backup.is_none() {
+        execute_concurrently(plan, options, reporter, &mut blocked, &mut failures)?;
+    } else {
+        for op in &plan.operations {
+            let result = if is_blocked(op, &blocked) {
+                Err(skipped_error())
+            } else {
+                reporter.started(op);
+                execute_step(plan, op, options, reporter)
+            };
+            if let Err(err) = result {
+                handle_failure(op, err, options, reporter, &mut blocked, &mut failures)?;
+            }
+        }
+    }
+    if options.leave_symlink {
+        leave_symlinks(plan, &blocked, reporter)?;
+    }
+    if failures > 0 {
+        return Err(Error::Incomplete(failures));
+    }
+    Ok(())
+}
+
+
+// Backs up the output file if required, then executes the operation. Only output files the plan
+// expects to overwrite may be replaced.
+fn execute_step(
+    plan: &RenamePlan,
+    op: &Operation,
+    options: &ExecOptions,
+    reporter: &mut dyn Reporter,
+) -> Result<()> {
+    let mut replace = false;
+    if let Operation::Rename(_, output_file) | Operation::Copy(_, output_file) | Operation::Link(_, output_file) = op {
+        replace = plan.is_overwrite(output_file);
+        if let Some(backup) = &options.backup {
+            if replace && Path::new(output_file).exists() {
+                backup_file(output_file, backup, options, reporter)?;
+            }
+        }
+    }
+    run_operation(op, options, replace, reporter)
+}
+
+
+// Records a failed operation in keep-going mode so later operations on the same paths are skipped.
+// Otherwise, returns the error to stop execution.
+fn handle_failure<'a>(
+    op: &'a Operation,
+    err: Error,
+    options: &ExecOptions,
+    reporter: &mut dyn Reporter,
+    blocked: &mut HashSet<&'a str>,
+    failures: &mut usize,
+) -> Result<()> {
+    if !options.keep_going {
+        return Err(err);
+    }
+    reporter.failed(op, &err);
+    blocked.extend(operation_paths(op));
+    *failures += 1;
+    Ok(())
+}
+
+
+// Returns true if the operation touches a path involved in a failed operation.
+fn is_blocked(op: &Operation, blocked: &HashSet<&str>) -> bool {
+    operation_paths(op).iter().any(|path| blocked.contains(path))
+}
+
+
+// Returns the error reported for an operation skipped because of an earlier failure.
+fn skipped_error() -> Error {
+    Error::Operation("skipped because an earlier operation on the same file failed".to_string())
+}
+
+
+// A notification recorded by a worker thread, to be passed on to the real reporter on the main
+// thread once the operation finishes.
+enum Event {
+    Before,
+    CreatedDir(PathBuf),
+    BackedUp(String, String),
+    Archived(String, String),
+    Trashed(String, String),
+    After(bool),
+}
+
+
+// A reporter that records notifications so they can be replayed later.
+#[derive(Default)]
+struct EventRecorder {
+    events: Vec<Event>,
+}
+
+
+impl Reporter for EventRecorder {
+    fn before(&mut self, _op: &Operation) {
+        self.events.push(Event::Before);
+    }
+
+    fn created_dir(&mut self, dir: &Path) {
+        self.events.push(Event::CreatedDir(dir.to_path_buf()));
+    }
+
+    fn backed_up(&mut self, path: &str, backup: &str) {
+        self.events.push(Event::BackedUp(path.to_string(), backup.to_string()));
+    }
+
+    fn archived(&mut self, path: &str, archive: &str) {
+        self.events.push(Event::Archived(path.to_string(), archive.to_string()));
+    }
+
+    fn trashed(&mut self, path: &str, trashed: &str) {
+        self.events.push(Event::Trashed(path.to_string(), trashed.to_string()));
+    }
+
+    fn after(&mut self, _op: &Operation, overwrote: bool) {
+        self.events.push(Event::After(overwrote));
+    }
+}
+
+
+// Executes the operations on a pool of `options.jobs` worker threads. Operations are started in
+// plan order, each once no running operation touches a related path, so an operation always sees
+// the results of the earlier operations it depends on, e.g. the renames of a cycle still run one
+// after another. Deletions to the trash run alone. Without keep-going, no new operations are
+// started after a failure but the running operations are allowed to finish.
+fn execute_concurrently<'a>(
+    plan: &'a RenamePlan,
+    options: &ExecOptions,
+    reporter: &mut dyn Reporter,
+    blocked: &mut HashSet<&'a str>,
+    failures: &mut usize,
+) -> Result<()> {
+    let (job_sender, job_receiver) = mpsc::channel::<usize>();
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|scope| {
+        let (done_sender, done_receiver) = mpsc::channel::<(usize, Vec<Event>, Result<()>)>();
+        for _ in 0..options.jobs {
+            let (job_receiver, done_sender) = (&job_receiver, done_sender.clone());
+            scope.spawn(move || loop {
+                let index = match job_receiver.lock().map(|receiver| receiver.recv()) {
+                    Ok(Ok(index)) => index,
+                    _ => break,
+                };
+                let mut recorder = EventRecorder::default();
+                let result = execute_step(plan, &plan.operations[index], options, &mut recorder);
+                if done_sender.send((index, recorder.events, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(done_sender);
+
+        // The running operations as (index, related paths, exclusive) tuples.
+        let mut running: Vec<(usize, Vec<PathBuf>, bool)> = Vec::new();
+        let mut first_error: Option<Error> = None;
+        let mut finish = |reporter: &mut dyn Reporter,
+                          blocked: &mut HashSet<&'a str>,
+                          index: usize,
+                          events: Vec<Event>,
+                          result: Result<()>| {
+            let op = &plan.operations[index];
+            for event in events {
+                match event {
+                    Event::Before => reporter.before(op),
+                    Event::CreatedDir(dir) => reporter.created_dir(&dir),
+                    Event::BackedUp(path, backup) => reporter.backed_up(&path, &backup),
+                    Event::Archived(path, archive) => reporter.archived(&path, &archive),
+                    Event::Trashed(path, trashed) => reporter.trashed(&path, &trashed),
+                    Event::After(overwrote) => reporter.after(op, overwrote),
+                }
+            }
+            if let Err(err) = result {
+                // Without keep-going, we return the first error and report any others.
+                if first_error.is_some() {
+                    reporter.failed(op, &err);
+                } else if let Err(err) = handle_failure(op, err, options, reporter, blocked, failures) {
+                    first_error = Some(err);
+                }
+            }
+            first_error.is_some()
+        };
+
+        let mut stopped = false;
+        for (index, op) in plan.operations.iter().enumerate() {
+            let paths = related_paths(op);
+            let exclusive = matches!(op, Operation::Delete(_)) && !options.permanent_delete;
+            let is_waiting = |running: &[(usize, Vec<PathBuf>, bool)]| {
+                running.len() >= options.jobs
+                    || running.iter().any(|(_, other_paths, other_exclusive)| {
+                        exclusive || *other_exclusive || paths_overlap(&paths, other_paths)
+                    })
+            };
+            while !stopped && is_waiting(&running) {
+                let (done, events, result) = done_receiver.recv().expect("worker threads have exited");
+                running.retain(|(other, _, _)| *other != done);
+                stopped = finish(reporter, blocked, done, events, result);
+            }
+            if stopped {
+                break;
+            }
+            if is_blocked(op, blocked) {
+                stopped = finish(reporter, blocked, index, Vec::new(), Err(skipped_error()));
+                continue;
+            }
+            reporter.started(op);
+            job_sender.send(index).expect("worker threads have exited");
+            running.push((index, paths, exclusive));
+        }
+        drop(job_sender);
+
+        while !running.is_empty() {
+            let (done, events, result) = done_receiver.recv().expect("worker threads have exited");
+            running.retain(|(other, _, _)| *other != done);
+            finish(reporter, blocked, done, events, result);
+        }
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    })
+}
+
+
+// Returns the paths an operation touches for the purposes of running operations concurrently:
+// its input and output paths, plus the outermost missing directory along the path to its output
+// as creating it would race with other operations creating the same directories.
+fn related_paths(op: &Operation) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = operation_paths(op).iter().map(PathBuf::from).collect();
+    if let Operation::Rename(_, output_file)
+    | Operation::Copy(_, output_file)
+    | Operation::Link(_, output_file)
+    | Operation::Create(output_file) = op
+    {
+        if let Some(dir) = missing_dirs(output_file).pop() {
+            paths.push(dir);
+        }
+    }
+    paths
+}
+
+
+// Returns true if any path in `a` is the same as, inside, or contains any path in `b`.
+fn paths_overlap(a: &[PathBuf], b: &[PathBuf]) -> bool {
+    a.iter().any(|a| b.iter().any(|b| a.starts_with(b) || b.starts_with(a)))
+}
+
+
+// Returns the paths read or written by an operation.
+fn operation_paths(op: &Operation) -> Vec<&str> {
+    match op {
+        Operation::Delete(input_file) | Operation::Purge(input_file) => vec![input_file],
+        Operation::Rename(input_file, output_file)
+        | Operation::Copy(input_file, output_file)
+        | Operation::Link(input_file, output_file)
+        | Operation::Swap(input_file, output_file) => vec![input_file, output_file],
+        Operation::Create(path) => vec![path],
+    }
+}
+
+
+// Create a symlink at the original location of each renamed file pointing to its new location.
+// We skip files whose original location has been taken by another file in the batch, e.g. as
+// part of a cycle, and files involved in failed operations. Symlink targets are relative so the
+// links survive moving the whole tree.
+fn leave_symlinks(plan: &RenamePlan, blocked: &HashSet<&str>, reporter: &mut dyn Reporter) -> Result<()> {
+    let operations = plan.logical_operations();
+    for op in &operations {
+        if let Operation::Rename(input_file, output_file) = op {
+            if blocked.contains(input_file.as_str()) || blocked.contains(output_file.as_str()) {
+                continue;
+            }
+            let is_reused = operations.iter().any(|other| match other {
+                Operation::Rename(_, dst) | Operation::Copy(_, dst) | Operation::Link(_, dst) => dst == input_file,
+                Operation::Swap(a, b) => a == input_file || b == input_file,
+                Operation::Delete(_) | Operation::Purge(_) | Operation::Create(_) => false,
+            });
+            if is_reused {
+                continue;
+            }
+            let path = Path::new(input_file);
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let target = relative_path(dir, Path::new(output_file))
+                .and_then(|target| symlink(&target, path, Path::new(output_file).is_dir()).map(|_| target))
+                .map_err(|err| {
+                    Error::Operation(format!(
+                        "cannot create a symlink at '{}' pointing to '{}': {}",
+                        input_file,
+                        output_file,
+                        err
+                    ))
+                })?;
+            reporter.created_symlink(input_file, &target);
+        }
+    }
+    Ok(())
+}
+
+
+// Returns the path to `target` relative to the directory `from`. The parent directories of both
+// paths must exist.
+fn relative_path(from: &Path, target: &Path) -> io::Result<PathBuf> {
+    let from = from.canonicalize()?;
+    let target = match (target.parent(), target.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            parent.canonicalize()?.join(name)
+        }
+        _ => target.canonicalize()?,
+    };
+    let from: Vec<Component> = from.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(target.iter()).take_while(|(a, b)| a == b).count();
+    let mut result = PathBuf::new();
+    for _ in common..from.len() {
+        result.push("..");
+    }
+    for component in &target[common..] {
+        result.push(component);
+    }
+    Ok(result)
+}
+
+
+// Creates a symlink at `path` pointing to `target`. Windows distinguishes between symlinks to
+// files and symlinks to directories.
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path, _is_dir: bool) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path, is_dir: bool) -> io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+
+// Hard links can't cross file systems. We check every link up front so we can fail cleanly
+// before making any changes. We compare the device of each input file with the device of the
+// nearest existing directory along the path to its output file.
+#[cfg(unix)]
+fn check_links(plan: &RenamePlan) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    for op in &plan.operations {
+        if let Operation::Link(input_file, output_file) = op {
+            let input_dev = match fs::metadata(input_file) {
+                Ok(metadata) => metadata.dev(),
+                Err(_) => continue,
+            };
+            let output_dev = Path::new(output_file)
+                .ancestors()
+                .skip(1)
+                .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+                .find_map(|dir| fs::metadata(dir).ok())
+                .map(|metadata| metadata.dev());
+            if output_dev.is_some_and(|dev| dev != input_dev) {
+                return Err(Error::LinkCrossesDevices(input_file.to_string(), output_file.to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// On other platforms we rely on the link operation itself to report the error.
+#[cfg(not(unix))]
+fn check_links(_plan: &RenamePlan) -> Result<()> {
+    Ok(())
+}
+
+
+// Move an existing file out of the way before it gets overwritten. In directory mode, we preserve
+// the file's relative path inside the backup directory.
+fn backup_file(path: &str, backup: &Backup, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    let backup_path = match backup {
+        Backup::Suffix(suffix) => format!("{}{}", path, suffix),
+        Backup::Dir(dir) => path_in_dir(path, dir),
+    };
+    create_parent_dirs(&backup_path, reporter)?;
+    if Path::new(&backup_path).is_dir() {
+        return Err(Error::DirectoryExists(backup_path));
+    }
+    let (src, dst) = (Path::new(path), Path::new(&backup_path));
+    with_retries(options, || rename(src, dst, options.strict_rename, &options.preserve)).map_err(|err| {
+        Error::Operation(format!("cannot back up the file '{}' to '{}': {}", path, backup_path, err))
+    })?;
+    if options.fsync {
+        sync_paths(&[path, &backup_path], &[])?;
+    }
+    reporter.backed_up(path, &backup_path);
+    Ok(())
+}
+
+
+// Move a deleted file into the archive directory, preserving its relative path.
+fn archive_file(path: &str, dir: &Path, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    let archive_path = path_in_dir(path, dir);
+    create_parent_dirs(&archive_path, reporter)?;
+    let (src, dst) = (Path::new(path), Path::new(&archive_path));
+    with_retries(options, || rename(src, dst, options.strict_rename, &options.preserve)).map_err(|err| {
+        Error::Operation(format!("cannot archive the file '{}' to '{}': {}", path, archive_path, err))
+    })?;
+    if options.fsync {
+        sync_paths(&[path, &archive_path], &[])?;
+    }
+    reporter.archived(path, &archive_path);
+    Ok(())
+}
+
+
+// Move a deleted file into a freedesktop.org trash directory: the file goes in `files` and a
+// '.trashinfo' file recording its original path and deletion time goes in `info`, so file
+// managers can list and restore it. We claim a name by creating the info file first, appending a
+// number if the name is already taken.
+fn trash_to_dir(path: &str, dir: &Path, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    let (files_dir, info_dir) = (dir.join("files"), dir.join("info"));
+    for subdir in [&files_dir, &info_dir] {
+        fs::create_dir_all(extended(subdir)).map_err(|err| {
+            Error::Operation(format!("cannot create the trash directory '{}': {}", subdir.display(), err))
+        })?;
+    }
+    let name = Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy());
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&absolute(path)),
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    let mut counter = 1;
+    let mut candidate = name.to_string();
+    let (trashed, info_path) = loop {
+        let (trashed, info_path) = (files_dir.join(&candidate), info_dir.join(format!("{}.trashinfo", candidate)));
+        if !trashed.exists() {
+            match fs::OpenOptions::new().write(true).create_new(true).open(extended(&info_path)) {
+                Ok(mut file) => {
+                    io::Write::write_all(&mut file, info.as_bytes()).map_err(|err| {
+                        Error::Operation(format!("cannot write the file '{}': {}", info_path.display(), err))
+                    })?;
+                    break (trashed, info_path);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    return Err(Error::Operation(format!(
+                        "cannot create the file '{}': {}",
+                        info_path.display(),
+                        err
+                    )));
+                }
+            }
+        }
+        candidate = format!("{}.{}", name, counter);
+        counter += 1;
+    };
+    let result = with_retries(options, || rename(Path::new(path), &trashed, options.strict_rename, &options.preserve));
+    if let Err(err) = result {
+        let _ = fs::remove_file(extended(&info_path));
+        return Err(Error::Operation(format!("cannot move the file '{}' to the trash: {}", path, err)));
+    }
+    let trashed = trashed.to_string_lossy().to_string();
+    if options.fsync {
+        sync_paths(&[path, &trashed, &info_path.to_string_lossy()], &[])?;
+    }
+    reporter.trashed(path, &trashed);
+    Ok(())
+}
+
+
+// Percent-encodes a path for a '.trashinfo' file, leaving path separators and unreserved
+// characters as they are.
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+
+/// Moves a file deleted to a trash directory set by the `trash_dir` option back to its original
+/// path and removes its '.trashinfo' file. Fails if a file already exists at the original path.
+pub fn restore_from_trash_dir(original: &Path, trashed: &Path) -> Result<()> {
+    let op = Operation::Rename(trashed.to_string_lossy().to_string(), original.to_string_lossy().to_string());
+    execute_operation(&op, &ExecOptions::default(), &mut NullReporter)?;
+    let info_path = match (trashed.parent().and_then(|dir| dir.parent()), trashed.file_name()) {
+        (Some(dir), Some(name)) => dir.join("info").join(format!("{}.trashinfo", name.to_string_lossy())),
+        _ => return Ok(()),
+    };
+    match fs::remove_file(extended(&info_path)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::Operation(format!(
+            "cannot remove the file '{}': {}",
+            info_path.display(),
+            err
+        ))),
+        _ => Ok(()),
+    }
+}
+
+
+// Returns the path for `path` inside the directory `dir`: its relative path if it's a plain
+// relative path, otherwise just its filename. A numbered suffix is appended if a file with the
+// same name already exists.
+fn path_in_dir(path: &str, dir: &Path) -> String {
+    let path = Path::new(path);
+    let is_plain_relative =
+        path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    let candidate = if is_plain_relative {
+        dir.join(path)
+    } else {
+        dir.join(path.file_name().unwrap_or(path.as_os_str()))
+    };
+    let mut candidate = candidate.to_string_lossy().to_string();
+    let base = candidate.clone();
+    let mut counter = 1;
+    while Path::new(&candidate).exists() {
+        candidate = format!("{}.~{}~", base, counter);
+        counter += 1;
+    }
+    candidate
+}
+
+
+/// Executes a single operation. Existing output files are never replaced: if an output file
+/// exists, the operation fails with `Error::OutputAppeared`. The check is atomic where the
+/// platform supports it.
+pub fn execute_operation(op: &Operation, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    run_operation(op, options, false, reporter)
+}
+
+
+// Executes a single operation, replacing an existing output file only if `replace` is true.
+fn run_operation(op: &Operation, options: &ExecOptions, replace: bool, reporter: &mut dyn Reporter) -> Result<()> {
+    reporter.before(op);
+
+    // Directories created along the path to the output file need their own entries flushed.
+    let new_dirs = match op {
+        Operation::Rename(_, output_file)
+        | Operation::Copy(_, output_file)
+        | Operation::Link(_, output_file)
+        | Operation::Create(output_file)
+            if options.fsync =>
+        {
+            missing_dirs(output_file)
+        }
+        _ => Vec::new(),
+    };
+    let overwrote = match op {
+        Operation::Delete(input_file) => {
+            delete_file(input_file, options.permanent_delete, options, reporter)?;
+            false
+        }
+        Operation::Purge(input_file) => {
+            delete_file(input_file, true, options, reporter)?;
+            false
+        }
+        Operation::Rename(input_file, output_file) => {
+            let overwrote = extended(Path::new(output_file)).exists();
+            create_parent_dirs(output_file, reporter)?;
+            move_file(input_file, output_file, options, replace)?;
+            overwrote
+        }
+        Operation::Copy(input_file, output_file) => {
+            let overwrote = extended(Path::new(output_file)).exists();
+            create_parent_dirs(output_file, reporter)?;
+            copy_tree(Path::new(input_file), Path::new(output_file), &options.preserve, replace).map_err(|err| {
+                if err.kind() == io::ErrorKind::AlreadyExists {
+                    return Error::OutputAppeared(output_file.to_string());
+                }
+                Error::Operation(format!("cannot copy the file '{}' to '{}': {}", input_file, output_file, err))
+            })?;
+            overwrote
+        }
+        Operation::Link(input_file, output_file) => {
+            let overwrote = extended(Path::new(output_file)).exists();
+            create_parent_dirs(output_file, reporter)?;
+            link_file(input_file, output_file, options, replace)?;
+            overwrote
+        }
+        Operation::Swap(input_file, output_file) => {
+            swap_files(input_file, output_file, options)?;
+            false
+        }
+        Operation::Create(path) => {
+            create_parent_dirs(path, reporter)?;
+            create_path(path)?;
+            false
+        }
+    };
+    if options.fsync {
+        sync_operation(op, &new_dirs)?;
+    }
+    reporter.after(op, overwrote);
+    Ok(())
+}
+
+
+// Flushes the results of an operation to disk: the directories containing the input and output
+// files, the parents of any directories created along the way, and the content of copied files.
+fn sync_operation(op: &Operation, new_dirs: &[PathBuf]) -> Result<()> {
+    match op {
+        Operation::Delete(input_file) | Operation::Purge(input_file) | Operation::Create(input_file) => {
+            sync_paths(&[input_file], new_dirs)
+        }
+        Operation::Rename(input_file, output_file) | Operation::Swap(input_file, output_file) => {
+            sync_paths(&[input_file, output_file], new_dirs)
+        }
+        Operation::Copy(_, output_file) | Operation::Link(_, output_file) => {
+            let path = extended(Path::new(output_file));
+            if path.is_file() {
+                fs::File::open(&path).and_then(|file| file.sync_all()).map_err(|err| {
+                    Error::Operation(format!("cannot flush the file '{}' to disk: {}", output_file, err))
+                })?;
+            }
+            sync_paths(&[output_file], new_dirs)
+        }
+    }
+}
+
+
+// Flushes the directories containing `paths` and the parent directories of `new_dirs` to disk.
+fn sync_paths(paths: &[&str], new_dirs: &[PathBuf]) -> Result<()> {
+    let mut dirs: Vec<&Path> = Vec::new();
+    let parents = paths.iter().map(|path| Path::new(*path)).chain(new_dirs.iter().map(|dir| dir.as_path()));
+    for path in parents {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    for dir in dirs {
+        sync_dir(dir).map_err(|err| {
+            Error::Operation(format!("cannot flush the directory '{}' to disk: {}", dir.display(), err))
+        })?;
+    }
+    Ok(())
+}
+
+
+/// Flushes a directory's entries to disk so renames into and out of it survive a power loss.
+#[cfg(unix)]
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(extended(dir))?.sync_all()
+}
+
+
+/// Directories can't be flushed on this platform. Renames are flushed by the file system.
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+
+// Move the specified file to the system's trash/recycle bin. If the --vcs option is set and the
+// file is tracked, remove it using the VCS instead, e.g. `git rm`. If `permanent` is true, i.e. the
+// --rm option is set or the file was marked with a '!', delete the file permanently. Otherwise, if
+// the --archive-dir or --trash-dir option is set, move the file into that directory.
+fn delete_file(input_file: &str, permanent: bool, options: &ExecOptions, reporter: &mut dyn Reporter) -> Result<()> {
+    let result = if let Some(vcs) = options.vcs.filter(|vcs| vcs.is_tracked(input_file)) {
+        vcs.rm(input_file)
+    } else if let Some(dir) = options.archive_dir.as_deref().filter(|_| !permanent) {
+        return archive_file(input_file, dir, options, reporter);
+    } else if let Some(dir) = options.trash_dir.as_deref().filter(|_| !permanent) {
+        return trash_to_dir(input_file, dir, options, reporter);
+    } else if permanent {
+        with_retries(options, || remove_recursive(Path::new(input_file))).map_err(|err| err.to_string())
+    } else {
+        trash::delete(input_file).map_err(|err| err.to_string())
+    };
+    result.map_err(|err| Error::Operation(format!("cannot delete the file '{}': {}", input_file, err)))
+}
+
+
+// Rename `input_file` to `output_file`, replacing an existing output file only if `replace` is
+// true. If the --vcs option is set and the file is tracked, rename it using the VCS, e.g. `git mv`.
+fn move_file(input_file: &str, output_file: &str, options: &ExecOptions, replace: bool) -> Result<()> {
+    let result = if let Some(vcs) = options.vcs.filter(|vcs| vcs.is_tracked(input_file)) {
+        vcs.mv(input_file, output_file, replace && Path::new(output_file).exists())
+    } else {
+        let (src, dst) = (Path::new(input_file), Path::new(output_file));
+        match with_retries(options, || move_path(src, dst, options.strict_rename, &options.preserve, replace)) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::OutputAppeared(output_file.to_string()));
+            }
+            result => result.map_err(|err| err.to_string()),
+        }
+    };
+    result.map_err(|err| {
+        Error::Operation(format!("cannot rename the file '{}' to '{}': {}", input_file, output_file, err))
+    })
+}
+
+
+// Create a new, empty file at `path`, or a directory if `path` ends with a path separator. Fails
+// with `Error::OutputAppeared` rather than touching an existing file.
+fn create_path(path: &str) -> Result<()> {
+    let target = extended(Path::new(path));
+    let result = if path.ends_with(['/', std::path::MAIN_SEPARATOR]) {
+        fs::create_dir(&target)
+    } else {
+        fs::OpenOptions::new().write(true).create_new(true).open(&target).map(|_| ())
+    };
+    result.map_err(|err| {
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            return Error::OutputAppeared(path.to_string());
+        }
+        Error::Operation(format!("cannot create '{}': {}", path, err))
+    })
+}
+
+
+// Swap the files `a` and `b`. We use an atomic exchange where the platform and the file system
+// support it so there's no point at which either file is missing. Otherwise, and for files
+// tracked by the version control system, we fall back on renaming via a temporary file.
+fn swap_files(a: &str, b: &str, options: &ExecOptions) -> Result<()> {
+    let tracked = options.vcs.is_some_and(|vcs| vcs.is_tracked(a) || vcs.is_tracked(b));
+    if !tracked {
+        match with_retries(options, || sys::exchange(&extended(Path::new(a)), &extended(Path::new(b)))) {
+            Ok(()) => return Ok(()),
+            Err(err) if matches!(err.kind(), io::ErrorKind::Unsupported | io::ErrorKind::CrossesDevices) => {}
+            Err(err) => {
+                return Err(Error::Operation(format!("cannot swap the files '{}' and '{}': {}", a, b, err)));
+            }
+        }
+    }
+    let temp_file = get_temp_filename(a)?;
+    move_file(a, &temp_file, options, false)?;
+    move_file(b, a, options, false)?;
+    move_file(&temp_file, b, options, false)
+}
+
+
+// Create a hard link to `input_file` at `output_file`. If `replace` is true, an existing file at
+// `output_file` is replaced atomically by linking to a temporary filename and renaming it into
+// place. Otherwise, linking fails if the file exists.
+fn link_file(input_file: &str, output_file: &str, options: &ExecOptions, replace: bool) -> Result<()> {
+    let src = extended(Path::new(input_file));
+    let dst = extended(Path::new(output_file));
+    let result = with_retries(options, || link_path(&src, &dst, output_file, replace));
+    result.map_err(|err| {
+        if err.kind() == io::ErrorKind::CrossesDevices {
+            return Error::LinkCrossesDevices(input_file.to_string(), output_file.to_string());
+        }
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            return Error::OutputAppeared(output_file.to_string());
+        }
+        Error::Operation(format!("cannot link the file '{}' to '{}': {}", input_file, output_file, err))
+    })
+}
+
+
+// Creates the hard link for `link_file`.
+fn link_path(src: &Path, dst: &Path, output_file: &str, replace: bool) -> io::Result<()> {
+    if replace && dst.exists() {
+        let temp_file = format!("{}.vimv_link", output_file);
+        let temp_file = extended(Path::new(&temp_file));
+        fs::hard_link(src, &temp_file).and_then(|_| {
+            fs::rename(&temp_file, dst).inspect_err(|_| {
+                let _ = fs::remove_file(&temp_file);
+            })
+        })
+    } else {
+        fs::hard_link(src, dst)
+    }
+}
+
+
+// Runs `f`, retrying it with exponential backoff while it fails with a transient error, up to
+// `options.retries` times.
+fn with_retries<T>(options: &ExecOptions, mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = options.retry_delay;
+    for _ in 0..options.retries {
+        match f() {
+            Err(err) if is_transient(&err) => {
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+    f()
+}
+
+
+// Returns true if an error may go away if the call is retried, e.g. because the file is busy or
+// the network file system timed out.
+#[cfg(not(windows))]
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ResourceBusy
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+
+// On Windows, antivirus scanners and search indexers briefly hold files open, which causes
+// sharing violations, lock violations, and access-denied errors for files being renamed.
+#[cfg(windows)]
+fn is_transient(err: &io::Error) -> bool {
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    matches!(err.raw_os_error(), Some(ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION))
+        || matches!(err.kind(), io::ErrorKind::ResourceBusy | io::ErrorKind::TimedOut)
+}
+
+
+/// Creates any missing directories along the path to `output_file`, notifying the reporter of
+/// each directory created.
+pub fn create_parent_dirs(output_file: &str, reporter: &mut dyn Reporter) -> Result<()> {
+    if let Some(parent_path) = Path::new(output_file).parent() {
+        if !parent_path.as_os_str().is_empty() && !extended(parent_path).is_dir() {
+            let new_dirs = missing_dirs(output_file);
+            fs::create_dir_all(extended(parent_path)).map_err(|err| {
+                Error::Operation(format!(
+                    "cannot create the required directory '{}': {}",
+                    parent_path.display(),
+                    err
+                ))
+            })?;
+            for dir in new_dirs.iter().rev() {
+                reporter.created_dir(dir);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// Returns the directories along the path to `output_file` that don't exist yet, deepest first.
+fn missing_dirs(output_file: &str) -> Vec<PathBuf> {
+    match Path::new(output_file).parent() {
+        Some(parent_path) => parent_path
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !extended(dir).exists())
+            .map(|dir| dir.to_path_buf())
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+
+/// Renames `src` to `dst`. If the rename fails with a cross-device error and `strict` is false,
+/// copies `src` to `dst`, preserving the selected metadata, and then deletes `src`.
+pub fn rename(src: &Path, dst: &Path, strict: bool, preserve: &Preserve) -> io::Result<()> {
+    move_path(src, dst, strict, preserve, true)
+}
+
+
+// Renames `src` to `dst` like `rename()`. If `replace` is false, fails with an `AlreadyExists`
+// error rather than replacing an existing `dst`.
+fn move_path(src: &Path, dst: &Path, strict: bool, preserve: &Preserve, replace: bool) -> io::Result<()> {
+    let (src, dst) = (extended(src), extended(dst));
+    let result = if replace { fs::rename(&src, &dst) } else { rename_no_replace(&src, &dst) };
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices && !strict => {
+            if let Err(err) = copy_tree(&src, &dst, preserve, replace) {
+                // If the output file appeared in the meantime, it isn't ours to remove.
+                if err.kind() != io::ErrorKind::AlreadyExists {
+                    let _ = remove_recursive(&dst);
+                }
+                return Err(err);
+            }
+            remove_recursive(&src)
+        }
+        result => result,
+    }
+}
+
+
+// Renames `src` to `dst` without replacing an existing `dst`. The check is atomic where the
+// platform and the file system support it. Elsewhere, we check for `dst` before renaming, which
+// leaves a small window for another process to create it. A case-only rename on a
+// case-insensitive file system finds `src` at `dst` and is allowed to go ahead.
+fn rename_no_replace(src: &Path, dst: &Path) -> io::Result<()> {
+    match sys::rename_no_replace(src, dst) {
+        Err(err) if err.kind() == io::ErrorKind::Unsupported || err.kind() == io::ErrorKind::AlreadyExists => {
+            if dst.symlink_metadata().is_ok() && !is_same_file(src, dst) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the destination already exists"));
+            }
+            fs::rename(src, dst)
+        }
+        result => result,
+    }
+}
+
+
+// Returns true if `a` and `b` are the same file, e.g. two spellings of a filename on a
+// case-insensitive file system.
+#[cfg(unix)]
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (a.symlink_metadata(), b.symlink_metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+
+#[cfg(not(unix))]
+pub(crate) fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+
+/// Copies a file or directory tree from `src` to `dst`, preserving the selected metadata.
+/// Symlinks are copied as symlinks on Unix.
+pub fn copy_recursive(src: &Path, dst: &Path, preserve: &Preserve) -> io::Result<()> {
+    copy_tree(src, dst, preserve, true)
+}
+
+
+// Copies a file or directory tree like `copy_recursive()`. If `replace` is false, fails with an
+// `AlreadyExists` error rather than replacing an existing file at `dst`.
+fn copy_tree(src: &Path, dst: &Path, preserve: &Preserve, replace: bool) -> io::Result<()> {
+    let (src, dst) = (extended(src), extended(dst));
+    #[cfg(unix)]
+    if src.is_symlink() {
+        return std::os::unix::fs::symlink(fs::read_link(&src)?, &dst);
+    }
+    if src.is_dir() {
+        fs::create_dir(&dst)?;
+        for entry in fs::read_dir(&src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()), preserve, false)?;
+        }
+    } else {
+        copy_file(&src, &dst, replace)?;
+    }
+    copy_metadata(&src, &dst, preserve)
+}
+
+
+// Copies the content of the file `src` to `dst`. We clone the file where the file system supports
+// it so even large files are copied instantly without duplicating their storage. Otherwise we
+// copy the content into a new file rather than using fs::copy() as the latter copies the
+// permission bits, which may leave us unable to set the remaining metadata. (On Linux, io::copy()
+// uses copy_file_range(2) so the data is copied in the kernel, and on file systems like NFS,
+// without being sent over the network.)
+fn copy_file(src: &Path, dst: &Path, replace: bool) -> io::Result<()> {
+    if sys::clone_path(src, dst).is_ok() {
+        return Ok(());
+    }
+    let mut reader = fs::File::open(src)?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    if replace {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let mut writer = options.open(dst)?;
+    if sys::clone_file(&reader, &writer).is_err() {
+        io::copy(&mut reader, &mut writer)?;
+    }
+    Ok(())
+}
+
+
+/// Removes a file or directory tree. Symlinks are removed, not followed.
+pub fn remove_recursive(path: &Path) -> io::Result<()> {
+    let path = extended(path);
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+
+/// Restores the most recently deleted file with the specified original path from the system's
+/// trash/recycle bin. Returns `Ok(false)` if no such file is found.
+#[cfg(any(
+    target_os = "windows",
+    all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))
+))]
+pub fn restore_from_trash(path: &Path) -> Result<bool> {
+    let items = trash::os_limited::list().map_err(|err| {
+        Error::Operation(format!("cannot list the contents of the trash: {}", err))
+    })?;
+    let item = items.into_iter()
+        .filter(|item| item.original_path() == path)
+        .max_by_key(|item| item.time_deleted);
+    match item {
+        Some(item) => {
+            trash::os_limited::restore_all(vec![item]).map_err(|err| {
+                Error::Operation(format!("cannot restore the file '{}': {}", path.display(), err))
+            })?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+
+/// Restoring files from the trash isn't supported on this platform.
+#[cfg(not(any(
+    target_os = "windows",
+    all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))
+)))]
+pub fn restore_from_trash(path: &Path) -> Result<bool> {
+    Err(Error::Operation(format!(
+        "cannot restore the file '{}', restoring from the trash is not supported on this platform",
+        path.display()
+    )))
+}
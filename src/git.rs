@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+
+/// Returns true if the current working directory is inside a git work tree.
+pub fn is_inside_work_tree() -> bool {
+    Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+
+/// Returns true if `path` is tracked by git. A directory counts as tracked if it contains any
+/// tracked files.
+pub fn is_tracked(path: &str) -> bool {
+    let dir = parent_dir(path);
+    let name = Path::new(path).file_name().map(|s| s.to_os_string()).unwrap_or_default();
+    Command::new("git")
+        .current_dir(dir)
+        .args(["ls-files", "--error-unmatch", "--"])
+        .arg(name)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+
+/// Returns the subset of `names`, entries in the directory `dir`, that git ignores, e.g. because
+/// they match a pattern in a .gitignore file. Each name is paired with a flag indicating whether
+/// the entry is a directory. Tracked files are never reported as ignored. Returns an empty set if
+/// `dir` isn't inside a git work tree or git can't be run.
+pub fn ignored(dir: &Path, names: &[(String, bool)]) -> HashSet<String> {
+    let mut ignored = HashSet::new();
+    if names.is_empty() {
+        return ignored;
+    }
+    let child = Command::new("git")
+        .current_dir(dir)
+        .args(["check-ignore", "--stdin", "-z"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return ignored,
+    };
+
+    // Directories are passed with a trailing slash so patterns like 'target/' match them. We write
+    // to git's stdin on a separate thread so a full stdout pipe can't deadlock us.
+    let mut input = Vec::new();
+    for (name, is_dir) in names {
+        input.extend_from_slice(name.as_bytes());
+        if *is_dir {
+            input.push(b'/');
+        }
+        input.push(0);
+    }
+    let mut stdin = child.stdin.take();
+    let writer = std::thread::spawn(move || {
+        if let Some(stdin) = &mut stdin {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+
+    // Git exits with status 1 if no names are ignored and 128 on a fatal error, e.g. if `dir`
+    // isn't inside a work tree.
+    if let Ok(output) = output {
+        if output.status.success() {
+            for name in output.stdout.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+                let name = String::from_utf8_lossy(name);
+                ignored.insert(name.trim_end_matches('/').to_string());
+            }
+        }
+    }
+    ignored
+}
+
+
+/// Renames `input_file` to `output_file` using `git mv`.
+pub fn mv(input_file: &str, output_file: &str, force: bool) -> Result<(), String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("mv");
+    if force {
+        cmd.arg("-f");
+    }
+    cmd.arg("--").arg(input_file).arg(output_file);
+    run(cmd)
+}
+
+
+/// Removes `input_file` from the working tree and the index using `git rm`.
+pub fn rm(input_file: &str) -> Result<(), String> {
+    let mut cmd = Command::new("git");
+    cmd.args(["rm", "-r", "-q", "--"]).arg(input_file);
+    run(cmd)
+}
+
+
+// Run a git command, returning its error output on failure.
+fn run(mut cmd: Command) -> Result<(), String> {
+    let output = cmd.stdout(Stdio::null()).output().map_err(|err| format!("failed to run git: {}", err))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+
+// Returns the parent directory of `path`, defaulting to the current directory.
+fn parent_dir(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
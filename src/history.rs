@@ -0,0 +1,120 @@
+use crate::error::{Error, Result};
+use crate::journal::{data_dir, escape, unescape};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// A completed session recorded in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// The path to the session's history file.
+    pub file: PathBuf,
+
+    /// The time the session completed, in seconds since the Unix epoch.
+    pub time: i64,
+
+    /// The working directory the session was run from. Relative filenames in the mapping and
+    /// list of deletions are relative to this directory.
+    pub cwd: PathBuf,
+
+    /// The journal file recording the session's operations, used to undo the session. The file
+    /// is removed once the session has been undone.
+    pub journal: Option<PathBuf>,
+
+    /// The completed renames as (input, output) pairs.
+    pub mapping: Vec<(String, String)>,
+
+    /// The files deleted by the session.
+    pub deletions: Vec<String>,
+}
+
+
+/// Returns the directory used to store history files.
+pub fn history_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("history"))
+}
+
+
+/// Records a completed session in the history. Each session is written to its own file, named
+/// by timestamp so files sort in chronological order. Returns the path to the new file.
+pub fn record(
+    cwd: &Path,
+    journal: Option<&Path>,
+    mapping: &[(String, String)],
+    deletions: &[String],
+) -> Result<PathBuf> {
+    let dir = history_dir().ok_or_else(|| {
+        Error::Operation("failed to locate a data directory for the history, set $XDG_DATA_HOME".to_string())
+    })?;
+    fs::create_dir_all(&dir).map_err(|err| {
+        Error::Operation(format!("cannot create the history directory '{}': {}", dir.display(), err))
+    })?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let mut content = format!("time\t{}\ncwd\t{}\n", now.as_secs(), escape(&cwd.to_string_lossy()));
+    if let Some(journal) = journal {
+        content.push_str(&format!("journal\t{}\n", escape(&journal.to_string_lossy())));
+    }
+    for (input_file, output_file) in mapping {
+        content.push_str(&format!("rename\t{}\t{}\n", escape(input_file), escape(output_file)));
+    }
+    for input_file in deletions {
+        content.push_str(&format!("delete\t{}\n", escape(input_file)));
+    }
+
+    let path = dir.join(format!("{:020}.history", now.as_millis()));
+    fs::write(&path, content).map_err(|err| {
+        Error::Operation(format!("cannot write the history file '{}': {}", path.display(), err))
+    })?;
+    Ok(path)
+}
+
+
+/// Reads the list of recorded sessions, oldest first. Returns an empty list if no sessions have
+/// been recorded.
+pub fn read() -> Result<Vec<Session>> {
+    let dir = match history_dir() {
+        Some(dir) if dir.exists() => dir,
+        _ => return Ok(Vec::new()),
+    };
+    let entries = fs::read_dir(&dir).map_err(|err| {
+        Error::Input(format!("failed to read the history directory '{}': {}", dir.display(), err))
+    })?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "history"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| read_session(path)).collect()
+}
+
+
+/// Reads a single session from a history file.
+pub fn read_session(path: &Path) -> Result<Session> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        Error::Input(format!("failed to read the history file '{}': {}", path.display(), err))
+    })?;
+    let mut session = Session {
+        file: path.to_path_buf(),
+        time: 0,
+        cwd: PathBuf::new(),
+        journal: None,
+        mapping: Vec::new(),
+        deletions: Vec::new(),
+    };
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let corrupt = || Error::Input(format!("the history file '{}' is corrupt: {}", path.display(), line));
+        match (fields[0].as_str(), fields.len()) {
+            ("time", 2) => session.time = fields[1].parse().map_err(|_| corrupt())?,
+            ("cwd", 2) => session.cwd = PathBuf::from(&fields[1]),
+            ("journal", 2) => session.journal = Some(PathBuf::from(&fields[1])),
+            ("rename", 3) => session.mapping.push((fields[1].clone(), fields[2].clone())),
+            ("delete", 2) => session.deletions.push(fields[1].clone()),
+            _ => return Err(corrupt()),
+        }
+    }
+    Ok(session)
+}
@@ -0,0 +1,404 @@
+use crate::error::{Error, Result};
+use crate::git;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::iter::Peekable;
+use std::path::{Component, Path, PathBuf};
+use std::str::Chars;
+
+
+/// The order in which input files are presented for editing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Alphabetical order.
+    Name,
+
+    /// Alphabetical order with runs of digits compared as numbers.
+    Natural,
+
+    /// By modification time, newest first.
+    Mtime,
+
+    /// By size, largest first.
+    Size,
+
+    /// Preserve the input order.
+    None,
+}
+
+
+impl SortOrder {
+    /// Parses a sort order name: 'name', 'natural', 'mtime', 'size', or 'none'.
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match name {
+            "name" => Some(SortOrder::Name),
+            "natural" => Some(SortOrder::Natural),
+            "mtime" => Some(SortOrder::Mtime),
+            "size" => Some(SortOrder::Size),
+            "none" => Some(SortOrder::None),
+            _ => None,
+        }
+    }
+}
+
+
+/// Returns a sorted list of the entries in the current working directory. Hidden entries are
+/// skipped if `skip_hidden` is true.
+pub fn list_current_dir(skip_hidden: bool) -> Result<Vec<String>> {
+    let current_dir = std::env::current_dir().map_err(|err| {
+        Error::Input(format!("failed to locate current directory: {}", err))
+    })?;
+    let current_dir_iterator = fs::read_dir(current_dir).map_err(|err| {
+        Error::Input(format!("failed to read current directory: {}", err))
+    })?;
+    let mut files = Vec::new();
+    for entry in current_dir_iterator {
+        let entry = entry.map_err(|err| {
+            Error::Input(format!("failed to read current directory entry: {}", err))
+        })?;
+        let entry_as_string = entry.file_name().into_string().map_err(|err| {
+            Error::Input(format!("failed to decode current directory entry name: {:?}", err))
+        })?;
+        if skip_hidden && is_hidden(&entry.path()) {
+            continue;
+        }
+        files.push(entry_as_string);
+    }
+    files.sort();
+    Ok(files)
+}
+
+
+/// Options controlling how directories are expanded in recursive mode.
+#[derive(Debug, Clone, Default)]
+pub struct ExpandOptions {
+    /// The maximum number of levels to descend below each directory. Directories at the limit
+    /// are listed instead of being expanded. `None` means no limit.
+    pub max_depth: Option<usize>,
+
+    /// List directories themselves as well as the files they contain.
+    pub list_dirs: bool,
+
+    /// Skip hidden files and directories.
+    pub skip_hidden: bool,
+
+    /// Skip files and directories ignored by git, e.g. because they match a pattern in a
+    /// .gitignore file, along with git's own '.git' directories.
+    pub skip_ignored: bool,
+}
+
+
+/// Recursively adds the files contained in `dir` to `files`. Symlinks to directories are treated
+/// as files and not followed.
+pub fn expand_directory(dir: &str, options: &ExpandOptions, files: &mut Vec<String>) -> Result<()> {
+    expand_directory_to_depth(dir, options, 1, files)
+}
+
+
+// Adds the entries in `dir`, which are at the specified depth below the directory originally
+// being expanded, to `files`.
+fn expand_directory_to_depth(dir: &str, options: &ExpandOptions, depth: usize, files: &mut Vec<String>) -> Result<()> {
+    let dir_iterator = fs::read_dir(dir).map_err(|err| {
+        Error::Input(format!("failed to read the directory '{}': {}", dir, err))
+    })?;
+    let mut entries = Vec::new();
+    for entry in dir_iterator {
+        let entry = entry.map_err(|err| {
+            Error::Input(format!("failed to read an entry in the directory '{}': {}", dir, err))
+        })?;
+        let path = Path::new(dir).join(entry.file_name());
+        if options.skip_hidden && is_hidden(&path) {
+            continue;
+        }
+        let name = entry.file_name().into_string().map_err(|err| {
+            Error::Input(format!("failed to decode directory entry name: {:?}", err))
+        })?;
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        entries.push((name, is_dir));
+    }
+    if options.skip_ignored {
+        let ignored = git::ignored(Path::new(dir), &entries);
+        entries.retain(|(name, _)| name != ".git" && !ignored.contains(name));
+    }
+    let mut entries: Vec<(String, bool)> = entries
+        .into_iter()
+        .map(|(name, is_dir)| (Path::new(dir).join(name).to_string_lossy().to_string(), is_dir))
+        .collect();
+    entries.sort();
+    for (path, is_dir) in entries {
+        let at_limit = options.max_depth.is_some_and(|max_depth| depth >= max_depth);
+        if is_dir && !at_limit {
+            if options.list_dirs {
+                files.push(path.clone());
+            }
+            expand_directory_to_depth(&path, options, depth + 1, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+
+/// Returns true if `arg` contains glob wildcards: '*', '?', or '['.
+pub fn is_glob_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+
+/// Expands a glob pattern into a sorted list of the matching paths. Supports the same syntax as
+/// Unix shells, plus '**' to match any number of directories. If `skip_hidden` is true, wildcards
+/// don't match hidden files, as in Unix shells, although a pattern like '.*' still does. Returns
+/// an error if nothing matches, as the pattern would otherwise be treated as a filename.
+pub fn expand_glob(pattern: &str, skip_hidden: bool) -> Result<Vec<String>> {
+    let match_options = glob::MatchOptions { require_literal_leading_dot: skip_hidden, ..Default::default() };
+    let paths = glob::glob_with(pattern, match_options).map_err(|err| {
+        Error::Input(format!("invalid glob pattern '{}': {}", pattern, err))
+    })?;
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.map_err(|err| {
+            Error::Input(format!("failed to expand the glob pattern '{}': {}", pattern, err))
+        })?;
+        if skip_hidden && has_hidden_attribute(&path) {
+            continue;
+        }
+        let path_as_string = path.into_os_string().into_string().map_err(|err| {
+            Error::Input(format!("failed to decode matched path: {:?}", err))
+        })?;
+        files.push(path_as_string);
+    }
+    if files.is_empty() {
+        return Err(Error::Input(format!("no files match the glob pattern '{}'", pattern)));
+    }
+    Ok(files)
+}
+
+
+/// Returns true if the file at `path` is hidden: its name begins with '.' or, on Windows, it has
+/// the hidden attribute.
+pub fn is_hidden(path: &Path) -> bool {
+    let dotfile = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    dotfile || has_hidden_attribute(path)
+}
+
+
+// Returns true if the file at `path` has the Windows hidden attribute.
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+
+// Files only have a hidden attribute on Windows.
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+
+/// Sorts a list of files in the specified order.
+pub fn sort_files(files: &mut [String], order: SortOrder) {
+    match order {
+        SortOrder::Name => files.sort(),
+        SortOrder::Natural => files.sort_by(|a, b| natural_cmp(a, b)),
+        SortOrder::Mtime => files.sort_by_cached_key(|file| {
+            let mtime = fs::symlink_metadata(file).and_then(|metadata| metadata.modified()).ok();
+            std::cmp::Reverse(mtime)
+        }),
+        SortOrder::Size => files.sort_by_cached_key(|file| {
+            let size = fs::symlink_metadata(file).map(|metadata| metadata.len()).unwrap_or(0);
+            std::cmp::Reverse(size)
+        }),
+        SortOrder::None => {}
+    }
+}
+
+
+/// Filters a list of files using glob patterns. If `includes` isn't empty, only files matching at
+/// least one of the patterns are kept. Files matching any of the `excludes` patterns are removed.
+pub fn filter_files(files: &mut Vec<String>, includes: &[String], excludes: &[String]) {
+    files.retain(|file| {
+        (includes.is_empty() || includes.iter().any(|pattern| glob_match(pattern, file)))
+            && !excludes.iter().any(|pattern| glob_match(pattern, file))
+    });
+}
+
+
+/// Removes exact duplicates from a list of files, keeping the first occurrence of each.
+pub fn dedupe_files(files: &mut Vec<String>) {
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+}
+
+
+/// Converts each file in a list to an absolute path. Parent directories are fully resolved but
+/// the final component is left as is so a symlink keeps its own name.
+pub fn absolute_files(files: &mut [String]) {
+    for file in files.iter_mut() {
+        *file = crate::journal::absolute(file);
+    }
+}
+
+
+/// Converts each file in a list to a path relative to the current directory, e.g.
+/// '../notes/a.txt'. Files that can't be reached by a relative path, e.g. on another drive on
+/// Windows, are left absolute.
+pub fn relative_files(files: &mut [String]) {
+    let cwd = match std::env::current_dir().and_then(|cwd| cwd.canonicalize()) {
+        Ok(cwd) => cwd,
+        Err(_) => return,
+    };
+    for file in files.iter_mut() {
+        let path = PathBuf::from(crate::journal::absolute(file));
+        if let Some(relative) = relative_to(&path, &cwd) {
+            *file = relative.to_string_lossy().to_string();
+        }
+    }
+}
+
+
+// Returns the absolute `path` relative to the absolute directory `base`, e.g. '../b/c' for
+// '/a/b/c' relative to '/a/d'. Returns None if the two paths have no common root.
+fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path_components: Vec<Component> = path.components().collect();
+    let base_components: Vec<Component> = base.components().collect();
+    if path_components.first() != base_components.first() {
+        return None;
+    }
+    let common = path_components.iter().zip(&base_components).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+
+/// Returns true if `path` matches the glob `pattern`. Patterns containing a '/' are matched
+/// against the full path, other patterns are matched against the filename only. Supports '*'
+/// (any run of characters except '/'), '**' (any run of characters), '?' (any single character
+/// except '/'), and character classes like '[abc]', '[a-z]', or '[!0-9]'.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let text = if pattern.contains('/') {
+        path
+    } else {
+        Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path)
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text)
+}
+
+
+// Matches a glob pattern against a string, backtracking on wildcards.
+fn glob_match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            (0..=text.len()).any(|i| glob_match_chars(&pattern[2..], &text[i..]))
+        }
+        Some('*') => {
+            for i in 0..=text.len() {
+                if glob_match_chars(&pattern[1..], &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => {
+            !text.is_empty() && text[0] != '/' && glob_match_chars(&pattern[1..], &text[1..])
+        }
+        Some('[') => match match_class(&pattern[1..], text.first().copied()) {
+            Some((true, len)) => glob_match_chars(&pattern[len + 1..], &text[1..]),
+            Some((false, _)) => false,
+            None => text.first() == Some(&'[') && glob_match_chars(&pattern[1..], &text[1..]),
+        },
+        Some(c) => text.first() == Some(c) && glob_match_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+
+// Matches a character class against `c`. `class` is the pattern following the opening '['.
+// Returns whether the character matched and the length of the class including the closing ']',
+// or `None` if the class isn't terminated, in which case the '[' is treated as a literal.
+fn match_class(class: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    let start = if negated { 1 } else { 0 };
+
+    // A ']' immediately after the opening '[' (or '[!') is a literal.
+    let end = class.iter().skip(start + 1).position(|&member| member == ']').map(|index| index + start + 1)?;
+
+    let c = match c {
+        Some(c) if c != '/' => c,
+        _ => return Some((false, end + 1)),
+    };
+    let members = &class[start..end];
+    let mut matched = false;
+    let mut i = 0;
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            matched |= members[i] <= c && c <= members[i + 2];
+            i += 3;
+        } else {
+            matched |= members[i] == c;
+            i += 1;
+        }
+    }
+    Some((matched != negated, end + 1))
+}
+
+
+/// Compares two strings in natural order, i.e. treating runs of digits as numbers so 'file2'
+/// sorts before 'file10'.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let a_digits = take_digits(&mut a_chars);
+                let b_digits = take_digits(&mut b_chars);
+                let a_trimmed = a_digits.trim_start_matches('0');
+                let b_trimmed = b_digits.trim_start_matches('0');
+                let ordering = a_trimmed.len().cmp(&b_trimmed.len()).then_with(|| a_trimmed.cmp(b_trimmed));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(a_char), Some(b_char)) => {
+                if a_char != b_char {
+                    return a_char.cmp(&b_char);
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+
+// Consume a run of ASCII digits from the iterator.
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
@@ -0,0 +1,561 @@
+use crate::error::{Error, Result};
+use crate::exec::sync_dir;
+use crate::plan::{Operation, RenamePlan};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// A journal records the operations performed by a single batch so the batch can be undone.
+/// Each batch is written to its own file in the journal directory. Operations are appended as
+/// they complete so a batch that fails halfway through can still be reversed.
+///
+/// Before execution, the full plan is also written to a '.pending' file alongside the journal
+/// file. Each operation is appended to the '.pending' file as it starts and again as it
+/// completes, and the file is removed once the batch has finished, so a leftover '.pending' file
+/// marks a batch that was interrupted.
+pub struct Journal {
+    file: Option<File>,
+    path: Option<PathBuf>,
+    pending: Option<File>,
+    pending_path: Option<PathBuf>,
+    steps: usize,
+    fsync: bool,
+}
+
+
+/// A batch that was interrupted before it finished, read from its '.pending' file.
+#[derive(Debug, Clone)]
+pub struct PendingBatch {
+    /// The path to the '.pending' file.
+    pub pending_file: PathBuf,
+
+    /// The path to the batch's journal file.
+    pub journal_file: PathBuf,
+
+    /// The working directory the batch was executed in. Paths in the plan are relative to this
+    /// directory.
+    pub cwd: PathBuf,
+
+    /// The full plan for the batch.
+    pub plan: RenamePlan,
+
+    /// True if the batch deleted files permanently instead of moving them to the trash.
+    pub permanent_delete: bool,
+
+    /// One flag per operation in the plan, set if the operation was recorded as completed.
+    pub completed: Vec<bool>,
+
+    /// One flag per operation in the plan, set if the operation was recorded as started. Files
+    /// written by older versions of Vimv don't record started operations.
+    pub started: Vec<bool>,
+}
+
+
+/// A single recorded operation.
+pub enum Entry {
+    Rename(PathBuf, PathBuf),
+    Copy(PathBuf, PathBuf),
+    Delete(PathBuf),
+    Trash(PathBuf, PathBuf),
+    Purge(PathBuf),
+    Overwrite(PathBuf),
+    CreateDir(PathBuf),
+    Symlink(PathBuf),
+    Swap(PathBuf, PathBuf),
+    Create(PathBuf),
+}
+
+
+impl Journal {
+    /// Creates a new journal file for the current batch.
+    pub fn new() -> Result<Journal> {
+        let dir = journal_dir().ok_or_else(|| {
+            Error::Operation("failed to locate a data directory for the journal, set $XDG_DATA_HOME".to_string())
+        })?;
+        fs::create_dir_all(&dir).map_err(|err| {
+            Error::Operation(format!("cannot create the journal directory '{}': {}", dir.display(), err))
+        })?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = dir.join(format!("{:020}.journal", timestamp));
+        let file = File::create(&path).map_err(|err| {
+            Error::Operation(format!("cannot create the journal file '{}': {}", path.display(), err))
+        })?;
+        Ok(Journal { file: Some(file), path: Some(path), pending: None, pending_path: None, steps: 0, fsync: false })
+    }
+
+    /// Reopens the journal of an interrupted batch so the rest of the batch can be recorded in it.
+    pub fn resume(batch: &PendingBatch) -> Result<Journal> {
+        let open = |path: &Path| {
+            fs::OpenOptions::new().append(true).open(path).map_err(|err| {
+                Error::Operation(format!("cannot open the journal file '{}': {}", path.display(), err))
+            })
+        };
+        Ok(Journal {
+            file: Some(open(&batch.journal_file)?),
+            path: Some(batch.journal_file.clone()),
+            pending: Some(open(&batch.pending_file)?),
+            pending_path: Some(batch.pending_file.clone()),
+            steps: batch.completed.iter().filter(|&&done| done).count(),
+            fsync: false,
+        })
+    }
+
+    /// Returns a journal that discards everything written to it.
+    pub fn disabled() -> Journal {
+        Journal { file: None, path: None, pending: None, pending_path: None, steps: 0, fsync: false }
+    }
+
+    /// Flush every write to disk before returning so the journal survives a power loss.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
+
+    /// Writes the full plan for the batch to the '.pending' file before anything is executed.
+    /// Paths are stored as given in the plan along with the current working directory.
+    pub fn write_plan(&mut self, plan: &RenamePlan, permanent_delete: bool) -> Result<()> {
+        let pending_path = match &self.path {
+            Some(path) => path.with_extension("pending"),
+            None => return Ok(()),
+        };
+        let cwd = env::current_dir().map_err(|err| {
+            Error::Operation(format!("cannot determine the working directory: {}", err))
+        })?;
+        let mut content = format!("cwd\t{}\n", escape(&cwd.to_string_lossy()));
+        if permanent_delete {
+            content.push_str("rm\n");
+        }
+        for op in &plan.operations {
+            content.push_str(&format!("op\t{}\n", format_operation(op)));
+        }
+        for temp_file in &plan.temp_files {
+            content.push_str(&format!("temp\t{}\n", escape(temp_file)));
+        }
+        for output_file in &plan.overwrites {
+            content.push_str(&format!("overwrite\t{}\n", escape(output_file)));
+        }
+        let mut file = File::create(&pending_path).map_err(|err| {
+            Error::Operation(format!("cannot create the journal file '{}': {}", pending_path.display(), err))
+        })?;
+        file.write_all(content.as_bytes()).and_then(|_| self.sync(&mut file)).map_err(|err| {
+            Error::Operation(format!("cannot write the journal file '{}': {}", pending_path.display(), err))
+        })?;
+        if self.fsync {
+            if let Some(dir) = pending_path.parent() {
+                sync_dir(dir).map_err(|err| {
+                    Error::Operation(format!("cannot flush the journal directory '{}': {}", dir.display(), err))
+                })?;
+            }
+        }
+        self.pending = Some(file);
+        self.pending_path = Some(pending_path);
+        Ok(())
+    }
+
+    /// Records that an operation from the plan is about to start. With several jobs, operations
+    /// can complete out of order, so recovery needs to know every operation that was in progress.
+    pub fn record_start(&mut self, op: &Operation) {
+        self.write_pending(&format!("start\t{}", format_operation(op)));
+    }
+
+    /// Records that an operation from the plan has completed.
+    pub fn record_step(&mut self, op: &Operation) {
+        self.steps += 1;
+        self.write_pending(&format!("done\t{}", format_operation(op)));
+    }
+
+    // Appends a line to the '.pending' file.
+    fn write_pending(&mut self, line: &str) {
+        if let Some(mut file) = self.pending.take() {
+            match writeln!(file, "{}", line).and_then(|_| self.sync(&mut file)) {
+                Ok(()) => self.pending = Some(file),
+                Err(err) => eprintln!("warning: failed to write to the journal file: {}", err),
+            }
+        }
+    }
+
+    /// Returns the number of operations in the batch recorded as completed.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Returns the path to the '.pending' file, if the plan has been written.
+    pub fn pending_path(&self) -> Option<&Path> {
+        self.pending_path.as_deref()
+    }
+
+    /// Marks the batch as finished by removing the '.pending' file.
+    pub fn finish(&mut self) {
+        self.pending = None;
+        if let Some(path) = self.pending_path.take() {
+            if let Err(err) = fs::remove_file(&path) {
+                eprintln!("warning: failed to remove the journal file '{}': {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Returns the path to the journal file, if the journal isn't disabled.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Records a completed rename operation.
+    pub fn record_rename(&mut self, input_file: &str, output_file: &str) {
+        let line = format!("rename\t{}\t{}", escape(&absolute(input_file)), escape(&absolute(output_file)));
+        self.write_line(&line);
+    }
+
+    /// Records a completed swap operation.
+    pub fn record_swap(&mut self, a: &str, b: &str) {
+        let line = format!("swap\t{}\t{}", escape(&absolute(a)), escape(&absolute(b)));
+        self.write_line(&line);
+    }
+
+    /// Records a completed copy operation.
+    pub fn record_copy(&mut self, input_file: &str, output_file: &str) {
+        let line = format!("copy\t{}\t{}", escape(&absolute(input_file)), escape(&absolute(output_file)));
+        self.write_line(&line);
+    }
+
+    /// Records a completed delete operation.
+    pub fn record_delete(&mut self, input_file: &str) {
+        let line = format!("delete\t{}", escape(&absolute(input_file)));
+        self.write_line(&line);
+    }
+
+    /// Records a completed delete operation that moved the file into a trash directory set by the
+    /// --trash-dir option rather than the system's trash.
+    pub fn record_trash(&mut self, input_file: &str, trashed: &str) {
+        let line = format!("trash\t{}\t{}", escape(&absolute(input_file)), escape(&absolute(trashed)));
+        self.write_line(&line);
+    }
+
+    /// Records a completed permanent delete operation.
+    pub fn record_purge(&mut self, input_file: &str) {
+        let line = format!("purge\t{}", escape(&absolute(input_file)));
+        self.write_line(&line);
+    }
+
+    /// Records that an existing file has been overwritten.
+    pub fn record_overwrite(&mut self, output_file: &str) {
+        let line = format!("overwrite\t{}", escape(&absolute(output_file)));
+        self.write_line(&line);
+    }
+
+    /// Records the creation of a directory.
+    pub fn record_create_dir(&mut self, dir: &Path) {
+        let line = format!("mkdir\t{}", escape(&absolute(&dir.to_string_lossy())));
+        self.write_line(&line);
+    }
+
+    /// Records the creation of a new, empty file. (New directories are recorded with
+    /// `record_create_dir()`.)
+    pub fn record_create(&mut self, path: &str) {
+        let line = format!("create\t{}", escape(&absolute(path)));
+        self.write_line(&line);
+    }
+
+    /// Records the creation of a symlink.
+    pub fn record_symlink(&mut self, path: &str) {
+        let line = format!("symlink\t{}", escape(&absolute(path)));
+        self.write_line(&line);
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Some(mut file) = self.file.take() {
+            match writeln!(file, "{}", line).and_then(|_| self.sync(&mut file)) {
+                Ok(()) => self.file = Some(file),
+                Err(err) => eprintln!("warning: failed to write to the journal file: {}", err),
+            }
+        }
+    }
+
+    // Flushes a file's buffered writes, and in fsync mode, its data to disk.
+    fn sync(&self, file: &mut File) -> std::io::Result<()> {
+        file.flush()?;
+        if self.fsync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns the directory used to store journal files.
+pub fn journal_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("journal"))
+}
+
+
+/// Returns the application's data directory, i.e. `$XDG_DATA_HOME/vimv` on Unix.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|s| !s.is_empty()) {
+        return Some(PathBuf::from(dir).join("vimv"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("LOCALAPPDATA").filter(|s| !s.is_empty()) {
+            return Some(PathBuf::from(dir).join("vimv"));
+        }
+    }
+    env::var_os("HOME")
+        .filter(|s| !s.is_empty())
+        .map(|dir| PathBuf::from(dir).join(".local").join("share").join("vimv"))
+}
+
+
+/// Returns the path to the most recent journal file, if any.
+pub fn latest_journal() -> Option<PathBuf> {
+    journals().pop()
+}
+
+
+/// Returns the paths to the journal files of the batches that haven't been undone, oldest first.
+pub fn journals() -> Vec<PathBuf> {
+    files_with_extension("journal")
+}
+
+
+/// Returns the paths to the '.pending' files of interrupted batches, oldest first.
+pub fn pending_batches() -> Vec<PathBuf> {
+    files_with_extension("pending")
+}
+
+
+// Returns the paths to the files in the journal directory with the specified extension, sorted
+// by name, i.e. oldest first.
+fn files_with_extension(extension: &str) -> Vec<PathBuf> {
+    let entries = match journal_dir().and_then(|dir| fs::read_dir(dir).ok()) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    paths.sort();
+    paths
+}
+
+
+/// Reads an interrupted batch from its '.pending' file.
+pub fn read_pending(path: &Path) -> Result<PendingBatch> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        Error::Input(format!("failed to read the journal file '{}': {}", path.display(), err))
+    })?;
+    let mut batch = PendingBatch {
+        pending_file: path.to_path_buf(),
+        journal_file: path.with_extension("journal"),
+        cwd: PathBuf::new(),
+        plan: RenamePlan::default(),
+        permanent_delete: false,
+        completed: Vec::new(),
+        started: Vec::new(),
+    };
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let corrupt = || Error::Input(format!("the journal file '{}' is corrupt: {}", path.display(), line));
+        match fields[0].as_str() {
+            "cwd" if fields.len() == 2 => batch.cwd = PathBuf::from(&fields[1]),
+            "rm" if fields.len() == 1 => batch.permanent_delete = true,
+            "op" => {
+                batch.plan.operations.push(parse_operation(&fields[1..]).ok_or_else(corrupt)?);
+                batch.completed.push(false);
+                batch.started.push(false);
+            }
+            "temp" if fields.len() == 2 => {
+                batch.plan.temp_files.insert(fields[1].clone());
+            }
+            "overwrite" if fields.len() == 2 => {
+                batch.plan.overwrites.insert(fields[1].clone());
+            }
+            "start" | "done" => {
+                let op = parse_operation(&fields[1..]).ok_or_else(corrupt)?;
+                let index = batch.plan.operations.iter().position(|other| *other == op).ok_or_else(corrupt)?;
+                if fields[0] == "start" {
+                    batch.started[index] = true;
+                } else {
+                    batch.completed[index] = true;
+                }
+            }
+            _ => return Err(corrupt()),
+        }
+    }
+    Ok(batch)
+}
+
+
+// Formats an operation as tab-separated fields for a '.pending' file.
+fn format_operation(op: &Operation) -> String {
+    match op {
+        Operation::Delete(input_file) => format!("delete\t{}", escape(input_file)),
+        Operation::Purge(input_file) => format!("purge\t{}", escape(input_file)),
+        Operation::Rename(input_file, output_file) => {
+            format!("rename\t{}\t{}", escape(input_file), escape(output_file))
+        }
+        Operation::Copy(input_file, output_file) => format!("copy\t{}\t{}", escape(input_file), escape(output_file)),
+        Operation::Link(input_file, output_file) => format!("link\t{}\t{}", escape(input_file), escape(output_file)),
+        Operation::Swap(input_file, output_file) => format!("swap\t{}\t{}", escape(input_file), escape(output_file)),
+        Operation::Create(path) => format!("create\t{}", escape(path)),
+    }
+}
+
+
+// Reverses `format_operation()`. The fields have already been unescaped.
+fn parse_operation(fields: &[String]) -> Option<Operation> {
+    match (fields.first()?.as_str(), fields.len()) {
+        ("delete", 2) => Some(Operation::Delete(fields[1].clone())),
+        ("purge", 2) => Some(Operation::Purge(fields[1].clone())),
+        ("rename", 3) => Some(Operation::Rename(fields[1].clone(), fields[2].clone())),
+        ("copy", 3) => Some(Operation::Copy(fields[1].clone(), fields[2].clone())),
+        ("link", 3) => Some(Operation::Link(fields[1].clone(), fields[2].clone())),
+        ("swap", 3) => Some(Operation::Swap(fields[1].clone(), fields[2].clone())),
+        ("create", 2) => Some(Operation::Create(fields[1].clone())),
+        _ => None,
+    }
+}
+
+
+/// Reads the list of entries from a journal file.
+pub fn read_journal(path: &Path) -> Result<Vec<Entry>> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        Error::Input(format!("failed to read the journal file '{}': {}", path.display(), err))
+    })?;
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        match (fields[0].as_str(), fields.len()) {
+            ("rename", 3) => entries.push(Entry::Rename(PathBuf::from(&fields[1]), PathBuf::from(&fields[2]))),
+            ("copy", 3) => entries.push(Entry::Copy(PathBuf::from(&fields[1]), PathBuf::from(&fields[2]))),
+            ("delete", 2) => entries.push(Entry::Delete(PathBuf::from(&fields[1]))),
+            ("trash", 3) => entries.push(Entry::Trash(PathBuf::from(&fields[1]), PathBuf::from(&fields[2]))),
+            ("purge", 2) => entries.push(Entry::Purge(PathBuf::from(&fields[1]))),
+            ("overwrite", 2) => entries.push(Entry::Overwrite(PathBuf::from(&fields[1]))),
+            ("mkdir", 2) => entries.push(Entry::CreateDir(PathBuf::from(&fields[1]))),
+            ("symlink", 2) => entries.push(Entry::Symlink(PathBuf::from(&fields[1]))),
+            ("swap", 3) => entries.push(Entry::Swap(PathBuf::from(&fields[1]), PathBuf::from(&fields[2]))),
+            ("create", 2) => entries.push(Entry::Create(PathBuf::from(&fields[1]))),
+            _ => {
+                return Err(Error::Input(format!("the journal file '{}' is corrupt: {}", path.display(), line)));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+
+// Converts a path to an absolute path. We canonicalize the parent directory (which must exist
+// at the point an operation is recorded) but not the file itself as it may be a symlink.
+pub(crate) fn absolute(path: &str) -> String {
+    let path = Path::new(path);
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+        if let Ok(parent) = parent.canonicalize() {
+            return parent.join(name).to_string_lossy().to_string();
+        }
+    }
+    if path.is_absolute() {
+        return path.to_string_lossy().to_string();
+    }
+    match env::current_dir() {
+        Ok(cwd) => cwd.join(path).to_string_lossy().to_string(),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+
+// Escapes tabs, newlines, and backslashes so each entry fits on a single line.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+
+// Reverses `escape()`.
+pub(crate) fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Paths containing the characters the format escapes, including a literal '\t' sequence that
+    // mustn't be read back as a tab.
+    fn awkward_operations() -> Vec<Operation> {
+        vec![
+            Operation::Rename("a\tb".to_string(), "c\nd".to_string()),
+            Operation::Copy("back\\slash".to_string(), "literal\\t".to_string()),
+            Operation::Link("\\\t\n".to_string(), "e\r\nf".to_string()),
+            Operation::Swap("g\\nh".to_string(), "i\\".to_string()),
+            Operation::Delete("tab\t".to_string()),
+            Operation::Purge("\nnewline".to_string()),
+            Operation::Create("dir\\/".to_string()),
+        ]
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for path in ["a\tb", "c\nd", "back\\slash", "literal\\t", "\\\t\n", "e\r\nf", "i\\", ""] {
+            let escaped = escape(path);
+            assert!(!escaped.contains(['\t', '\n', '\r']));
+            assert_eq!(unescape(&escaped), path);
+        }
+    }
+
+    #[test]
+    fn operation_round_trip() {
+        for op in awkward_operations() {
+            let line = format_operation(&op);
+            let fields: Vec<String> = line.split('\t').map(unescape).collect();
+            assert_eq!(parse_operation(&fields), Some(op));
+        }
+    }
+
+    #[test]
+    fn pending_file_round_trip() {
+        let dir = env::temp_dir().join(format!("vimv-journal-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let journal_file = dir.join("batch.journal");
+        let mut journal = Journal {
+            file: None,
+            path: Some(journal_file.clone()),
+            pending: None,
+            pending_path: None,
+            steps: 0,
+            fsync: false,
+        };
+        let plan = RenamePlan { operations: awkward_operations(), ..RenamePlan::default() };
+        journal.write_plan(&plan, false).unwrap();
+        journal.record_start(&plan.operations[0]);
+        journal.record_step(&plan.operations[0]);
+        journal.record_start(&plan.operations[2]);
+
+        let batch = read_pending(&journal_file.with_extension("pending")).unwrap();
+        assert_eq!(batch.plan.operations, plan.operations);
+        assert_eq!(batch.completed, vec![true, false, false, false, false, false, false]);
+        assert_eq!(batch.started, vec![true, false, true, false, false, false, false]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
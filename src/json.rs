@@ -0,0 +1,30 @@
+// Minimal helpers for writing JSON output.
+
+
+// Returns `s` as a quoted JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+
+// Returns a single-line JSON object with string values.
+pub fn object(fields: &[(&str, &str)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}: {}", string(key), string(value)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
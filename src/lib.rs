@@ -0,0 +1,51 @@
+//! Vimv's core logic: validating a batch of renames, resolving cycles, and executing the result.
+//!
+//! ```no_run
+//! let inputs = vec!["a.txt".to_string(), "b.txt".to_string()];
+//! let outputs = vec!["b.txt".to_string(), "a.txt".to_string()];
+//! let plan = vimv::plan(&inputs, &outputs, &vimv::PlanOptions::default()).unwrap();
+//! vimv::execute(&plan, &vimv::ExecOptions::default(), &mut vimv::NullReporter).unwrap();
+//! ```
+
+mod audio;
+mod error;
+mod exec;
+mod longpath;
+mod metadata;
+mod photo;
+mod plan;
+mod sys;
+
+pub mod buffer;
+pub mod git;
+pub mod history;
+pub mod input;
+pub mod journal;
+pub mod lock;
+pub mod log;
+pub mod manifest;
+pub mod mapping;
+pub mod recovery;
+pub mod script;
+pub mod transform;
+pub mod validate;
+pub mod vcs;
+
+pub use error::{Error, Result};
+pub use exec::{
+    copy_recursive,
+    create_parent_dirs,
+    execute,
+    execute_operation,
+    remove_recursive,
+    rename,
+    restore_from_trash,
+    restore_from_trash_dir,
+    sync_dir,
+    Backup,
+    ExecOptions,
+    NullReporter,
+    Reporter,
+};
+pub use metadata::{copy_metadata, describe, Preserve};
+pub use plan::{plan, validate_inputs, Conflict, Normalization, Operation, PlanOptions, RenamePlan};
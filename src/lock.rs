@@ -0,0 +1,72 @@
+use crate::error::{Error, Result};
+use crate::journal::data_dir;
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+
+
+/// Holds exclusive locks on the directories containing a batch's input files. The locks are
+/// released when the value is dropped.
+pub struct Lock {
+    _files: Vec<File>,
+}
+
+
+/// Acquires an exclusive lock on each directory containing one of the input files so concurrent
+/// runs can't interleave operations on the same files. If `wait` is false and another run holds
+/// one of the locks, returns `Error::Locked` immediately, otherwise blocks until the lock is free.
+pub fn acquire(inputs: &[String], wait: bool) -> Result<Lock> {
+    let lock_dir = data_dir().map(|dir| dir.join("locks")).ok_or_else(|| {
+        Error::Operation("failed to locate a data directory for lock files, set $XDG_DATA_HOME".to_string())
+    })?;
+    fs::create_dir_all(&lock_dir).map_err(|err| {
+        Error::Operation(format!("cannot create the lock directory '{}': {}", lock_dir.display(), err))
+    })?;
+
+    // We lock the directories in sorted order so two runs waiting on overlapping sets of
+    // directories can't deadlock.
+    let dirs: BTreeSet<PathBuf> = inputs.iter().filter_map(|input_file| parent_dir(input_file)).collect();
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        let path = lock_dir.join(format!("{:016x}.lock", hash(&dir)));
+        let file = File::create(&path).map_err(|err| {
+            Error::Operation(format!("cannot create the lock file '{}': {}", path.display(), err))
+        })?;
+        let result = if wait {
+            file.lock().map_err(TryLockError::Error)
+        } else {
+            file.try_lock()
+        };
+        match result {
+            Ok(()) => files.push(file),
+            Err(TryLockError::WouldBlock) => return Err(Error::Locked(dir.to_string_lossy().to_string())),
+            Err(TryLockError::Error(err)) => {
+                return Err(Error::Operation(format!("cannot lock the file '{}': {}", path.display(), err)));
+            }
+        }
+    }
+    Ok(Lock { _files: files })
+}
+
+
+// Returns the canonical path of the directory containing `path`.
+fn parent_dir(path: &str) -> Option<PathBuf> {
+    let parent = Path::new(path).parent()?;
+    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    parent.canonicalize().ok()
+}
+
+
+// Returns a hash of the path for use as a lock filename. We use 64-bit FNV-1a rather than the
+// standard library's hasher, whose output can change between Rust releases, so runs of different
+// builds always agree on the lock file for a directory.
+fn hash(path: &Path) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in path.as_os_str().as_encoded_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,95 @@
+use crate::error::{Error, Result};
+use crate::journal::{absolute, escape};
+use chrono::{Local, SecondsFormat};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+
+/// A log file recording every operation performed, appended to across runs as an audit trail.
+/// Each operation is written on its own line as tab-separated fields: an RFC 3339 timestamp,
+/// the operation, and the absolute paths it affected. (A symlink's target is recorded as
+/// written.) Tabs, newlines, and backslashes in paths are escaped with backslashes.
+pub struct Log {
+    file: Option<File>,
+}
+
+
+impl Log {
+    /// Opens the log file at `path` for appending, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Log> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| {
+            Error::Operation(format!("cannot open the log file '{}': {}", path.display(), err))
+        })?;
+        Ok(Log { file: Some(file) })
+    }
+
+    /// Returns a log that discards everything written to it.
+    pub fn disabled() -> Log {
+        Log { file: None }
+    }
+
+    /// Records a completed rename operation.
+    pub fn record_rename(&mut self, input_file: &str, output_file: &str) {
+        self.write_entry("rename", &[absolute(input_file), absolute(output_file)]);
+    }
+
+    /// Records a completed swap operation.
+    pub fn record_swap(&mut self, a: &str, b: &str) {
+        self.write_entry("swap", &[absolute(a), absolute(b)]);
+    }
+
+    /// Records a completed copy operation.
+    pub fn record_copy(&mut self, input_file: &str, output_file: &str) {
+        self.write_entry("copy", &[absolute(input_file), absolute(output_file)]);
+    }
+
+    /// Records a completed hard link operation.
+    pub fn record_link(&mut self, input_file: &str, output_file: &str) {
+        self.write_entry("link", &[absolute(input_file), absolute(output_file)]);
+    }
+
+    /// Records a completed delete operation. Permanent deletions are recorded as 'purge'.
+    pub fn record_delete(&mut self, input_file: &str, permanent: bool) {
+        self.write_entry(if permanent { "purge" } else { "delete" }, &[absolute(input_file)]);
+    }
+
+    /// Records the backup of an existing file before it's overwritten.
+    pub fn record_backup(&mut self, path: &str, backup: &str) {
+        self.write_entry("backup", &[absolute(path), absolute(backup)]);
+    }
+
+    /// Records a deleted file moved into the archive directory.
+    pub fn record_archive(&mut self, input_file: &str, archive: &str) {
+        self.write_entry("archive", &[absolute(input_file), absolute(archive)]);
+    }
+
+    /// Records the creation of a directory.
+    pub fn record_create_dir(&mut self, dir: &Path) {
+        self.write_entry("mkdir", &[absolute(&dir.to_string_lossy())]);
+    }
+
+    /// Records the creation of a new, empty file.
+    pub fn record_create(&mut self, path: &str) {
+        self.write_entry("create", &[absolute(path)]);
+    }
+
+    /// Records the creation of a symlink.
+    pub fn record_symlink(&mut self, path: &str, target: &Path) {
+        self.write_entry("symlink", &[absolute(path), target.to_string_lossy().to_string()]);
+    }
+
+    fn write_entry(&mut self, operation: &str, paths: &[String]) {
+        if let Some(file) = &mut self.file {
+            let mut line = format!("{}\t{}", Local::now().to_rfc3339_opts(SecondsFormat::Millis, false), operation);
+            for path in paths {
+                line.push('\t');
+                line.push_str(&escape(path));
+            }
+            if let Err(err) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                eprintln!("warning: failed to write to the log file: {}", err);
+                self.file = None;
+            }
+        }
+    }
+}
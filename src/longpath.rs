@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+
+// Paths at least this long are converted to the extended-length form. (The legacy MAX_PATH limit
+// is 260 characters including the terminating NUL but directory paths are limited to 248 so
+// there's room for an 8.3 filename.)
+#[cfg(windows)]
+const MAX_LEGACY_LEN: usize = 248;
+
+
+/// Returns `path` in a form the operating system accepts regardless of its length. On Windows,
+/// paths too long for the legacy MAX_PATH limit are converted to the `\\?\` extended-length form.
+/// On other platforms, the path is returned unchanged.
+#[cfg(windows)]
+pub fn extended(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    // Extended-length paths bypass normalization so we need an absolute path with any '.' and
+    // '..' components resolved and forward slashes converted. std::path::absolute() does this.
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return Cow::Borrowed(path),
+    };
+    let absolute_str = absolute.as_os_str().to_string_lossy();
+    if absolute_str.starts_with(r"\\?\") || absolute_str.encode_utf16().count() < MAX_LEGACY_LEN {
+        return Cow::Borrowed(path);
+    }
+
+    // UNC paths of the form \\server\share become \\?\UNC\server\share.
+    let mut extended = OsString::new();
+    if let Some(unc) = absolute_str.strip_prefix(r"\\") {
+        extended.push(r"\\?\UNC\");
+        extended.push(unc);
+    } else {
+        extended.push(r"\\?\");
+        extended.push(absolute.as_os_str());
+    }
+    Cow::Owned(PathBuf::from(extended))
+}
+
+
+/// Returns `path` in a form the operating system accepts regardless of its length. On Windows,
+/// paths too long for the legacy MAX_PATH limit are converted to the `\\?\` extended-length form.
+/// On other platforms, the path is returned unchanged.
+#[cfg(not(windows))]
+pub fn extended(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
@@ -1,30 +1,263 @@
 use arguably::ArgParser;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::env;
 use std::fs;
-use std::collections::HashSet;
-use rand::Rng;
-use std::io::Read;
+use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use colored::*;
+use chrono::{Local, TimeZone};
+use diff::Kind;
+use unicode_normalization::UnicodeNormalization;
+use vimv::buffer::BufferFormat;
+use vimv::history::{self, Session};
+use vimv::input::{ExpandOptions, SortOrder};
+use vimv::journal::{self, Entry, Journal, PendingBatch};
+use vimv::log::Log;
+use vimv::mapping::MappingFormat;
+use vimv::script::Script;
+use vimv::manifest::{self, TrashedFile};
+use vimv::transform::{Case, Replacement, Substitution, Template, Transform};
+use vimv::vcs::Vcs;
+use progress::ProgressBar;
+use vimv::{Backup, Conflict, ExecOptions, Normalization, Operation, PlanOptions, Preserve, RenamePlan, Reporter};
+
+mod completions;
+mod diff;
+mod editor;
+mod json;
+mod pager;
+mod picker;
+mod progress;
+mod tui;
+
+
+// Set to true if the --json flag has been found. Output is written as JSON instead of text.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+
+// The width of the column of verbs, e.g. 'Renaming', in the per-operation output.
+const VERB_WIDTH: usize = 10;
+
+
+// The maximum width of the column of old filenames in the per-operation output. Longer filenames
+// overflow the column rather than pushing every new filename off the screen.
+const MAX_NAME_WIDTH: usize = 50;
+
+
+// A list of input filenames paired with their output filenames.
+type Pairs = Vec<(String, String)>;
 
 
 const HELPTEXT: &str = "
 Usage: vimv [files]
+       vimv undo
+       vimv recover
+       vimv restore [files]
+       vimv history [session]
+       vimv apply <plan>
+       vimv completions <shell>
 
   This utility lets you batch-rename files using a text editor. Files to be
   renamed should be supplied as a list of command-line arguments, e.g.
 
     $ vimv *.mp3
 
-  The list of files will be opened in the editor specified by the $EDITOR
-  environment variable, one filename per line. Edit the list, save, and exit.
-  The files will be renamed to the edited filenames. Directories along the
-  renamed paths will be created as required.
+  The list of files will be opened in the editor specified by the $VISUAL or
+  $EDITOR environment variable, one filename per line. Edit the list, save,
+  and exit. The files will be renamed to the edited filenames. Directories
+  along the renamed paths will be created as required.
+
+  The editor command can include arguments, e.g. EDITOR='code --wait'. It's
+  split into words like a shell would, so quote any paths containing spaces.
+  The buffer is written to a temporary file named like 'vimv-XXXXXXXX.vimv'
+  so editors can recognize it, e.g. to apply syntax highlighting. Use the
+  --buffer-ext option to change the extension.
+
+  If no editor is configured or the configured editor can't be launched,
+  Vimv falls back to sensible-editor, nano, or vi (notepad.exe on Windows)
+  and reports which editor it used.
+
+  Use the --tui flag to edit the filenames in a built-in terminal interface
+  instead of an external editor. It lists each file with its new filename,
+  highlights conflicts as you type, and previews the selected file, showing
+  a thumbnail for images. Press Enter to edit a filename, 'd' to mark a file
+  for deletion, 'u' to revert a filename, 'w' to apply the changes, or 'q'
+  to quit without applying them. The changes go through the usual checks.
+
+  Use the --pick flag to choose which of the input files to edit in a
+  built-in fuzzy finder before the editor opens. Type to filter the list,
+  press Tab to mark a file, Ctrl-A to mark all the matching files, and Enter
+  to edit the marked files, or the highlighted file if none are marked.
+
+  If the edited filenames fail validation, e.g. because lines have been added
+  or removed or two files have the same new filename, Vimv reports every
+  problem in the buffer at once and offers to reopen the editor with your
+  edits and the errors as comments at the top, until the buffer is valid. If
+  you decline, or aren't running in a terminal, the edited buffer is saved in
+  the $XDG_DATA_HOME/vimv/recovery directory so your edits aren't lost. The
+  buffer is also saved if you abort at a review prompt or if the changes fail
+  part-way through. The error message gives its path.
+
+  Use the --resume flag to reopen the most recently saved buffer and carry
+  on editing it. Vimv switches to the directory the buffer was saved in and
+  checks the edits against the file system again. Changes that were
+  already applied before a failure are dropped from the buffer.
 
   If the input file list is empty, Vimv defaults to listing the contents of
   the current working directory.
 
+  Use the --sort option to control the order in which files are listed in
+  the editor. Files can be sorted by 'name', in 'natural' order (where
+  'file2' comes before 'file10'), by 'mtime' (newest first), or by 'size'
+  (largest first). The default, 'none', preserves the input order.
+
+  By default, removing a line from the buffer is an error as Vimv can't tell
+  which file it belonged to. Use the --allow-missing-lines flag to leave the
+  files whose lines have been removed unchanged instead. Unchanged lines are
+  matched to their own filenames. A changed line is only accepted if it's
+  clear which file it belongs to, i.e. if it replaces the only removed line
+  between two unchanged lines.
+
+  The editor buffer begins with a header of instructions and a list of the
+  flags in effect. Lines beginning with '##' are comments and are ignored,
+  unless they name one of the input files. Use the --no-header flag to omit
+  the header.
+
+  Use the --format option to control the layout of the editor buffer. The
+  default, 'single', lists one filename per line. The 'dual' format lists
+  each original filename alongside an editable destination column, separated
+  by tabs. Edit the second column only. The 'numbered' format prefixes each
+  filename with a number and a tab, like vidir. Edit the filenames only. In
+  dual and numbered format, lines are matched by their first column so
+  deleting a line leaves the file unchanged. The 'grouped' format lists files
+  under a '## dir/' header for each directory, with only the final component
+  of each filename below. Edit a header to move all the files below it.
+
+  Use the --null flag to read filenames separated by NUL characters from
+  standard input, e.g. the output of `find -print0`. In this mode,
+  backslashes, tabs, and newlines in filenames appear in the editor as the
+  escape sequences '\\\\', '\\t', and '\\n'.
+
+  Use the --glob flag to expand glob patterns like '*.mp3' in the arguments
+  instead of relying on the shell, e.g. if the pattern has been quoted. On
+  Windows, where the shell doesn't expand globs, this happens automatically.
+
+  Hidden files are files whose names begin with '.' and, on Windows, files
+  with the hidden attribute. By default, directory listings include hidden
+  files but glob wildcards don't match them, as in Unix shells. Use the
+  --hidden flag to match hidden files with wildcards and the --no-hidden
+  flag to skip them in directory listings.
+
+  Use the --recursive flag to replace directories in the input list with a
+  recursive listing of the files they contain. Use the --max-depth option to
+  limit how deep the listing goes and the --list-dirs flag to list the
+  directories themselves as well. Both imply --recursive. Inside a git
+  repository, recursive listings skip files ignored by git and the '.git'
+  directory. Use the --no-ignore flag to include them.
+
+  Use the --lower, --upper, or --title flag to change the case of each
+  filename without opening the editor. Only the final component of each path
+  is changed; --title leaves the extension alone. The results go through the
+  usual checks. Add the --edit flag to open the editor with the transformed
+  filenames for touch-ups.
+
+  Use the --prefix and --suffix options to add a string to the start or end
+  of each filename, e.g. --suffix '-paris' renames 'img1.jpg' to
+  'img1-paris.jpg'. The suffix goes before the extension. Like the case
+  flags, they skip the editor unless the --edit flag is set and are applied
+  after any other transformation.
+
+  Use the --template option to generate filenames from a template instead of
+  the editor, e.g. --template 'track-{n:03}-{stem}.{ext}'. Supported tokens
+  are {n} (a counter starting at 1, in the order the files are listed; add a
+  width like {n:03} to zero-pad it), {name} (the original filename), {stem}
+  (the filename without its extension), {ext} (the extension), {parent} (the
+  name of the containing directory), and {mtime} (the modification time,
+  formatted using a strftime spec like {mtime:%Y/%m}; the default is
+  %Y-%m-%d). Use '{{' and '}}' for literal braces. Add the --edit flag to
+  tweak the generated filenames in the editor.
+
+  For photos, the {exif.date} token inserts the date the photo was taken,
+  formatted using a strftime spec, e.g. {exif.date:%Y-%m-%d} (the default).
+  The {exif.make}, {exif.model}, and {exif.camera} tokens insert the camera
+  details. Vimv exits with an error if a file has no matching EXIF data.
+
+  Audio files support the tokens {tag.artist}, {tag.album}, {tag.title},
+  {tag.genre}, {tag.year}, {tag.track}, and {tag.disc}, read from their ID3,
+  Vorbis comment, FLAC, or MP4 tags. The track and disc numbers accept a
+  width like {tag.track:02}. Path separators in tag values are replaced with
+  underscores.
+
+  Use the --preset option in place of --template for a predefined template.
+  The 'music' preset arranges audio files as 'Artist/Album/NN - Title.ext',
+  i.e. '{tag.artist}/{tag.album}/{tag.track:02} - {tag.title}.{ext}'. The
+  'by-date' preset moves files into 'YYYY/MM' subdirectories by modification
+  time, i.e. '{mtime:%Y/%m}/{name}'.
+
+  Use the --regex option to rename files with a sed-style substitution
+  instead of the editor, e.g. --regex 's/IMG_(\\d+)/photo-$1/'. The
+  replacement can refer to capture groups as '$1' or '${name}'. Add the 'g'
+  flag to replace every match and the 'i' flag to match case-insensitively.
+  The option can be repeated to apply several substitutions in order. Like
+  the case flags, it only changes the final component of each path and works
+  with --edit and --dry-run.
+
+  Use the --replace option to replace every occurrence of a literal string in
+  the filenames before the editor opens, e.g. --replace ' - Copy/' to strip
+  ' - Copy' from every filename. The old and new strings are separated by a
+  '/', which filenames can't contain. The option can be repeated and the
+  --ignore-case flag matches the old strings case-insensitively. Like the
+  case flags, it only changes the final component of each path, but on its
+  own it doesn't skip the editor.
+
+  Use the --script option to generate filenames with a Rhai script, for rules
+  the other options can't express. The script runs once for each file and
+  evaluates to its new path. It can read the variables 'path', 'dir',
+  'name', 'stem', 'ext', 'parent', 'index', 'count', 'paths' (every input
+  path), 'size', 'mtime', 'date', and 'is_dir', and call 'pad(n, width)',
+  'regex_match(text, pattern)', and 'regex_replace(text, pattern, repl)'.
+  The generated filenames are opened in the editor for review.
+
+  Use the --output-list option to supply the new filenames from a file, or
+  from standard input if the file is '-', instead of the editor. The list
+  holds one filename per line (NUL-separated with --null), matched to the
+  input files in order, and goes through the usual checks. Prefix a filename
+  with '#', or the --delete-prefix, to delete the file, e.g.
+
+    $ ls *.txt | sed 's/txt$/md/' > names.list
+    $ vimv --output-list names.list *.txt
+
+  Use the --pairs flag to read tab-separated 'old<TAB>new' pairs of
+  filenames from standard input instead of collecting input files and
+  opening the editor, e.g. the output of another tool. With --null, pairs
+  are NUL-separated. As in the editor, prefix a new filename with '#', or the
+  --delete-prefix, to delete the file.
+
+  Use the --include and --exclude options to filter the input list with glob
+  patterns before the editor opens, e.g. --include '*.jpg' --exclude
+  '*thumb*'. Patterns containing a '/' are matched against the full path,
+  other patterns against the filename only.
+
+  Use the --annotate flag to show each file's size, modification date, and
+  type at the end of its line in the editor, after a tab and '##'. The
+  annotations are removed when the buffer is read back, so you can leave
+  them in place while renaming.
+
+  Use the --absolute flag to show the input files as absolute paths in the
+  editor, e.g. when they come from many scattered directories, or the
+  --relative flag to show them relative to the current directory. By
+  default, filenames are shown exactly as they were given.
+
+  A filename that appears in the input list more than once is an error. Use
+  the --dedupe flag to drop the duplicates instead, keeping the first
+  occurrence of each filename.
+
+  Directories are shown with a trailing '/' in the editor so they stand out
+  from files. The slash is removed when the buffer is read, so it doesn't
+  matter whether a new name for a directory keeps it.
+
   Vimv supports cycle-renaming. You can safely rename A to B, B to C, and C
   to A in a single operation.
 
@@ -33,21 +266,514 @@ Usage: vimv [files]
   to overwrite a directory, the program will exit with an error message and a
   non-zero status code.)
 
+  Use the --on-conflict option for finer control over existing files: 'error'
+  (the default) exits with an error, 'skip' leaves the input file unchanged,
+  'overwrite' is the same as --force, 'number' appends ' (1)', ' (2)', etc. to
+  the new filename, and 'ask' prompts for each file. The --skip-existing flag
+  is shorthand for --on-conflict skip. If no policy is specified and Vimv is
+  running in a terminal, it asks about each existing file.
+
+  If a new filename is already another name for the same file, e.g. a hard
+  link to it, the change is skipped with a warning rather than treated as a
+  conflict.
+
+  Use the --force-if-duplicate flag to overwrite existing files only if their
+  content is byte-identical to the file replacing them, e.g. to collapse
+  duplicate downloads. Use the --compare-overwrites flag with --force to
+  report whether each overwritten file is a duplicate (safe) or has unique
+  content (lost) in --dry-run and --review output.
+
+  Use the --backup flag with --force to back up existing files before they're
+  overwritten. By default, backups are made by appending a '~' to the
+  filename. Use the --backup-suffix option to specify a different suffix or
+  the --backup-dir option to move backups into a directory instead.
+
   You can delete a file or directory by prefixing its name with a `#` symbol.
   Deleted files and directories are moved to the system's trash/recycle bin.
+  Use the --rm flag to delete them permanently instead. You'll be asked for
+  confirmation unless the --force flag is set.
+
+  Use the --archive-dir option to move deleted files into a directory named
+  for the date and time of the run, e.g. 'archive/2024-06-01-142530/',
+  instead of the trash. The files keep their relative paths inside it and
+  `vimv undo` moves them back.
+
+  If more than 20 files are marked for deletion, they're listed and you'll
+  be asked to confirm the deletions separately, even after a --review. Use
+  the --confirm-delete flag to always ask, or the --delete-threshold option
+  to change the limit. The --yes flag answers for you.
+
+  Use the --trash-dir option to move deleted files into a specific trash
+  directory, e.g. one on the same volume as the files, instead of the
+  default trash. The directory uses the freedesktop.org layout so file
+  managers can restore the files too.
+
+  Prefix a filename with a '!' symbol instead to delete that file
+  permanently, skipping the trash. The files are listed and you'll be asked
+  for confirmation unless the --yes flag is set.
+
+  Files whose names begin with '#' or '!' are left alone if their lines are
+  unchanged. To rename a file to a name beginning with one of these symbols,
+  write it as './#name'. Use the --delete-prefix option to mark deletions
+  with a different prefix, e.g. '--delete-prefix %', which makes '#' an
+  ordinary character.
+
+  Add a line beginning with a '+' symbol anywhere in the buffer to create a
+  new, empty file, or a directory if the name ends with a '/'. New files are
+  created after the other changes and mustn't exist already. To rename a
+  file to a name beginning with '+', write it as './+name'.
+
+  Prefix a new filename with a '>' symbol to copy the file instead of
+  renaming it, leaving the original in place, e.g. '> notes-backup.txt'.
+  Use the --copy flag to copy every file.
+
+  If a file can't be renamed because the destination is on a different file
+  system, Vimv falls back to copying the file and then deleting the original.
+  Use the --strict-rename flag to disable this fallback.
+
+  Batches of more than 100 operations, and batches that copy files, show a
+  progress bar with the number of completed operations and the current file
+  instead of a line per operation. Use the --progress option to change the
+  threshold. The bar is only shown in a terminal and --quiet suppresses it.
+
+  After execution, Vimv prints a one-line summary of the run to standard
+  error, e.g. 'Renamed 42, deleted 3, created 7 directories (1.2s)'. In
+  quiet mode the summary is only printed if every operation succeeded.
+
+  Use the --jobs option to run up to n operations at once, e.g. to speed up
+  large batches on network file systems where each rename is slow. Only
+  operations on unrelated paths run side by side, so cycles and renames
+  inside renamed directories still happen in order. Batches that use a
+  version control system or make backups always run one operation at a time.
+  Operations can finish out of order, so each one is recorded in the journal
+  as it starts and `vimv recover` checks every operation that was in progress
+  when a batch was interrupted.
+
+  Use the --retries option to retry operations that fail with transient
+  errors, e.g. a file that's briefly busy on a network file system or held
+  open by a virus scanner on Windows. Vimv waits --retry-delay milliseconds
+  before the first retry and doubles the delay for each further retry.
+
+  Use the --fsync flag to flush the affected directories and the journal to
+  disk after each operation, so a power loss right after Vimv reports
+  success can't lose the renames. This makes large batches slower.
+
+  When files are copied, either in copy mode or as part of a cross-device
+  rename, Vimv preserves their permissions, timestamps, and extended
+  attributes. Use the --no-preserve option with a comma-separated list of
+  'mode', 'timestamps', 'xattr', or 'all' to opt out.
+
+  On Windows, output filenames that are reserved device names (e.g. 'CON',
+  'NUL.txt', or 'COM1') or that end with a dot or a space are rejected before
+  any changes are made. Use the --allow-reserved-names flag to skip this check.
+
+  Output filenames are also checked against the rules of the file system
+  they'll be written to, e.g. characters that FAT and NTFS don't allow or the
+  255-byte length limit on ext4. Use the --no-fs-check flag to skip this check.
+
+  Use the --restrict flag to reject new filenames, including the files to
+  create, that resolve to a path outside the current directory, e.g. a stray
+  '../../' typed into the buffer. Use the --restrict-dir option to choose a
+  different root directory.
+
+  Filenames are compared using Unicode canonical equivalence so a filename
+  that only differs from the original in its normalization form (e.g. NFC vs
+  NFD on macOS) is treated as unchanged. Use the --normalize option to
+  convert the output filenames to a specific normalization form.
+
+  Paths are compared after removing '.' components and redundant slashes, so
+  './a.txt' and 'a.txt' name the same file, both in the input list and when
+  deciding whether a line has changed. A new filename that resolves to the
+  file's current location, e.g. its absolute path, also leaves it unchanged.
+
+  By default, Vimv colorizes its output if standard output is a terminal and
+  the $NO_COLOR environment variable isn't set. Use the --color option to
+  force color on with 'always' or off with 'never'.
+
+  Use the --review flag to print a summary of the changes after the editor
+  closes and ask for confirmation before applying them. The words that
+  change in each filename are highlighted, as they are in --dry-run output.
+
+  If the --dry-run or --review output is too long to fit in the terminal, it's
+  piped through the pager specified by $PAGER, defaulting to 'less'. Use the
+  --no-pager flag to disable this.
+
+  Use the --yes flag to run without any prompts, e.g. in scripts or cron
+  jobs. The --review and --rm confirmations are answered 'yes', existing
+  files are handled by the --on-conflict policy instead of being asked
+  about, and output isn't paged. Only the editor, if used, needs a user.
+
+  Use the --leave-symlink flag to leave a symlink at the original location of
+  each renamed file pointing to its new location.
+
+  Use the --copy flag to copy files to their edited filenames instead of
+  renaming them. The original files are left in place.
+
+  Use the --hardlink flag to create hard links to the original files at
+  their edited filenames instead of renaming them. Hard links can't cross
+  file systems or point to directories.
+
+  Use the --git flag inside a git repository to perform renames using
+  `git mv` and deletions using `git rm` so the index stays consistent.
+  Untracked files are renamed and deleted as normal. Use the --vcs option
+  to choose a different version control system: 'git', 'hg' for Mercurial,
+  'jj' for Jujutsu, or 'auto' to detect the one in use.
+
+  Use the --json flag to print the planned and executed operations as JSON,
+  one object per line, for consumption by other tools. Each object has an
+  'event' field: 'plan' for a planned operation, 'rename', 'copy', 'link',
+  'delete', 'backup', 'mkdir', or 'symlink' for an executed operation, or
+  'error' if an error occurred.
+
+  By default, Vimv stops at the first operation that fails. Use the
+  --keep-going flag to attempt every remaining operation instead, skipping any
+  that depend on a failed operation, then list the failures after the summary.
+  Vimv exits with a non-zero status code if any operation failed.
+
+  Use the --validate-cmd option to check the planned changes before anything
+  is changed, e.g. to enforce a naming convention. The command receives the
+  changes on standard input as tab-separated 'old<TAB>new' lines, in the
+  format used by --save-plan; deleted files have no new filename and new
+  files have an empty old filename. If it exits with a non-zero status, no
+  changes are made and its output is reported as the error. The command is
+  run directly, not through a shell.
+
+  Use the --exec option to run a command after each completed operation,
+  e.g. to update a database that refers to the files. In each word of the
+  command, '{src}' is replaced by the original filename and '{dst}' by the
+  new filename. For deletions, a word that is exactly '{dst}' is dropped.
+  The command is run directly, not through a shell, so filenames are never
+  interpreted as shell syntax. Its output is written to standard error and
+  a command that fails only produces a warning.
+
+  Use the --print0 flag to write the completed operations to standard output
+  after execution as pairs of filenames, each followed by a NUL character:
+  the original filename, then the new filename (empty for deleted files).
+  The --print flag does the same using newlines instead of NUL characters.
+  Both flags replace the normal output.
+
+  Vimv locks the directories containing the input files so two runs can't
+  operate on the same files at once. If another run holds the lock, Vimv
+  exits with an error. Use the --wait-lock flag to wait for it instead.
+
+  Every batch of operations is recorded in a journal file. Run `vimv undo` to
+  reverse the most recent batch, including restoring deleted files from the
+  trash where the platform supports it.
+
+  The full plan for each batch is written to the journal before anything is
+  executed. If a batch is interrupted, e.g. by a crash or a failure part-way
+  through, run `vimv recover` to see which operations completed and then
+  finish the batch or roll it back.
+
+  Files moved to the trash by Vimv are listed in a manifest. Run
+  `vimv restore` to list them and `vimv restore <file>` to restore a file to
+  its original path.
+
+  Use the --save-plan option to write the mapping to a file instead of
+  executing it. Run `vimv apply <plan>` to execute a saved mapping later.
+
+  Every completed session is recorded in the history. Run `vimv history` to
+  list past sessions and `vimv history <session>` to display one.
 
 Arguments:
   [files]                   List of files to rename.
 
 Options:
-  -e, --editor <name>       Specify the editor to use. Overrides $EDITOR.
+      --archive-dir <dir>   Move deleted files into a dated directory inside this
+                            directory instead of the trash.
+      --backup-dir <dir>    Move backups of overwritten files into this directory.
+      --backup-suffix <s>   Suffix for backups of overwritten files. Defaults to '~'.
+      --buffer-ext <ext>    File extension for the editor buffer. Defaults to 'vimv'.
+      --color <when>        Colorize the output: 'auto', 'always', or 'never'.
+                            Defaults to 'auto'.
+      --delete-prefix <s>   Prefix marking a file for deletion in the editor buffer.
+                            Defaults to '#'.
+      --delete-threshold <n>
+                            Ask for confirmation before trashing more than n files.
+                            Defaults to 20.
+  -e, --editor <cmd>        Specify the editor command to use. Overrides $EDITOR.
+  -x, --exclude <glob>      Remove input files matching this pattern. Repeatable.
+      --exec <cmd>          Run this command after each completed operation, e.g.
+                            'update-db {src} {dst}'.
+      --format <fmt>        Buffer format: 'single', 'dual', 'numbered', or 'grouped'.
+                            Defaults to 'single'.
+  -i, --include <glob>      Only keep input files matching this pattern. Repeatable.
+  -j, --jobs <n>            Run up to n independent operations at once. Defaults to 1.
+      --log <file>          Append a timestamped record of every operation to this file.
+      --max-depth <n>       Limit recursive listings to this many levels. Implies -r.
+      --no-preserve <list>  Don't preserve the listed metadata when copying files:
+                            'mode', 'timestamps', 'xattr', or 'all'.
+      --normalize <form>    Normalize output filenames to 'nfc' or 'nfd' form.
+      --on-conflict <p>     What to do if an output file exists: 'error', 'skip',
+                            'overwrite', 'number', or 'ask'. Defaults to 'error'.
+  -O, --output-list <file>  Read the new filenames from this file instead of the editor.
+                            Use '-' for standard input.
+      --prefix <str>        Add this string to the start of every filename.
+      --preset <name>       Generate filenames from a predefined template: 'music' or
+                            'by-date'.
+      --progress <n>        Show a progress bar for batches of more than n operations.
+                            Defaults to 100.
+      --regex <expr>        Rename files using a 's/pattern/replacement/' expression
+                            instead of the editor. Repeatable.
+      --replace <old/new>   Replace every occurrence of 'old' in the filenames with 'new'
+                            before the editor opens. Repeatable.
+      --restrict-dir <dir>  Reject new filenames outside this directory. Implies
+                            --restrict.
+      --retries <n>         Retry operations that fail with transient errors up to n
+                            times. Defaults to 0.
+      --retry-delay <ms>    Delay before the first retry, doubling for each further
+                            retry. Defaults to 100.
+      --save-plan <file>    Write the mapping to this file instead of executing it.
+                            Uses JSON for '.json' files, TSV otherwise.
+      --script <file>       Generate filenames with a Rhai script, then open the
+                            editor to review them.
+      --sort <order>        Sort the input files by 'name', 'natural', 'mtime',
+                            'size', or 'none'. Defaults to 'none'.
+      --suffix <str>        Add this string to the end of every filename, before the
+                            extension.
+  -t, --template <tmpl>     Generate filenames from a template instead of the editor,
+                            e.g. '{n:03}-{stem}.{ext}'.
+      --trash-dir <dir>     Move deleted files into this trash directory instead of the
+                            default trash, e.g. '/media/usb/.Trash-1000'.
+      --validate-cmd <cmd>  Run this command on the planned changes and abort if it
+                            exits with a non-zero status.
+      --vcs <name>          Use a version control system to rename and delete tracked
+                            files: 'git', 'hg', 'jj', or 'auto' to detect it.
+
+Commands:
+  apply                     Execute a mapping saved with --save-plan.
+  completions               Print a shell completion script.
+  history                   List or display past sessions.
+  recover                   Finish or roll back an interrupted batch.
+  restore                   Restore files moved to the trash by Vimv.
+  undo                      Undo the most recent batch of operations.
 
 Flags:
+      --absolute            Show the input files as absolute paths in the editor.
+      --allow-missing-lines Leave files unchanged if their lines are removed.
+      --allow-reserved-names
+                            Don't reject output filenames that Windows reserves.
+      --annotate            Show each file's size, date, and type at the end of its line.
+  -b, --backup              Back up existing files before overwriting them.
+      --compare-overwrites  Report whether overwritten files duplicate their replacements.
+      --confirm-delete      List the files to be deleted and ask for confirmation.
+  -c, --copy                Copy files instead of renaming them.
+      --dedupe              Drop duplicate input filenames instead of exiting with an
+                            error.
+  -n, --dry-run             Print the planned operations without executing them.
+      --edit                Open the editor after applying a transformation.
   -f, --force               Allow overwriting existing files.
+      --force-if-duplicate  Only overwrite existing files with identical content.
+      --fsync               Flush each operation to disk before starting the next.
+  -g, --git                 Use git to rename and delete tracked files. Shorthand for
+                            --vcs git.
+      --glob                Expand glob patterns in the arguments. Automatic on Windows.
+  -k, --keep-going          Attempt every operation even if some fail.
+  -H, --hardlink            Create hard links instead of renaming files.
   -h, --help                Print this help text and exit.
+      --hidden              Match hidden files with glob wildcards.
+      --ignore-case         Match --replace strings case-insensitively.
+      --json                Print the plan and the executed operations as JSON.
+  -l, --leave-symlink       Leave a symlink to the new location of each renamed file.
+      --list-dirs           List directories as well as their contents. Implies -r.
+      --lower               Convert filenames to lowercase without opening the editor.
+      --no-fs-check         Don't check output filenames against their file systems.
+      --no-header           Don't write instructions at the top of the editor buffer.
+      --no-hidden           Skip hidden files when listing directories.
+      --no-ignore           Include files ignored by git in recursive listings.
+      --no-pager            Don't page long --dry-run or --review output.
+  -0, --null                Read NUL-separated filenames from standard input.
+      --pairs               Read tab-separated old and new filenames from standard input.
+      --pick                Choose which input files to edit in a fuzzy finder.
   -q, --quiet               Quiet mode -- only report errors.
+      --resume              Continue editing the most recently saved buffer.
+      --print               Print the completed operations as newline-separated pairs.
+      --print0              Print the completed operations as NUL-separated pairs.
+  -r, --recursive           Replace directories with the files they contain.
+      --relative            Show the input files as paths relative to the current
+                            directory in the editor.
+      --restrict            Reject new filenames outside the current directory.
+      --review              Review the changes and confirm before applying them.
+      --rm                  Delete files permanently instead of using the trash.
+      --skip-existing       Skip changes whose new filenames already exist.
   -s, --stdin               Read the list of input files from standard input.
+      --strict-rename       Don't fall back to copy-and-delete across file systems.
+      --title               Convert filenames to title case without opening the editor.
+      --tui                 Edit filenames in a built-in terminal interface.
+      --upper               Convert filenames to uppercase without opening the editor.
   -v, --version             Print the version number and exit.
+      --wait-lock           Wait for other runs on the same files to finish.
+  -y, --yes                 Don't ask for confirmation or prompt about conflicts.
+";
+
+
+const APPLY_HELPTEXT: &str = "
+Usage: vimv apply <plan>
+
+  Executes a mapping previously written to a file with the --save-plan
+  option, e.g.
+
+    $ vimv --save-plan renames.json *.mp3
+    $ vimv apply renames.json
+
+  The mapping is validated against the current state of the file system
+  before anything is executed, exactly as if it had been entered in the
+  editor. Relative filenames are resolved against the current working
+  directory. The plan file is read as JSON if it has a '.json' extension
+  and as TSV otherwise.
+
+Arguments:
+  <plan>                    Mapping file to execute.
+
+Options:
+  -j, --jobs <n>            Run up to n independent operations at once. Defaults to 1.
+      --log <file>          Append a timestamped record of every operation to this file.
+      --progress <n>        Show a progress bar for batches of more than n operations.
+                            Defaults to 100.
+      --retries <n>         Retry operations that fail with transient errors up to n
+                            times. Defaults to 0.
+      --retry-delay <ms>    Delay before the first retry, doubling for each further
+                            retry. Defaults to 100.
+
+Flags:
+  -n, --dry-run             Print the planned operations without executing them.
+  -f, --force               Allow overwriting existing files.
+      --fsync               Flush each operation to disk before starting the next.
+  -h, --help                Print this help text and exit.
+  -k, --keep-going          Attempt every operation even if some fail.
+  -q, --quiet               Quiet mode -- only report errors.
+      --rm                  Delete files permanently instead of using the trash.
+  -y, --yes                 Don't ask for confirmation.
+";
+
+
+const COMPLETIONS_HELPTEXT: &str = "
+Usage: vimv completions <shell>
+
+  Prints a completion script for the specified shell: 'bash', 'zsh', 'fish',
+  or 'powershell'. The script covers all of Vimv's flags, options, and
+  commands, e.g.
+
+    $ source <(vimv completions bash)
+    $ vimv completions zsh > ~/.zfunc/_vimv
+    $ vimv completions fish > ~/.config/fish/completions/vimv.fish
+
+Arguments:
+  <shell>                   Shell to generate the script for.
+
+Flags:
+  -h, --help                Print this help text and exit.
+";
+
+
+const HISTORY_HELPTEXT: &str = "
+Usage: vimv history [session]
+
+  Lists the sessions completed by previous Vimv runs, numbered, along with
+  the time each session completed, the directory it was run from, and the
+  number of files it renamed and deleted. Specify a session's number to
+  display the files it renamed and deleted, e.g.
+
+    $ vimv history
+    $ vimv history 3
+
+  History files are stored in the $XDG_DATA_HOME/vimv/history directory.
+
+Arguments:
+  [session]                 Number of the session to display.
+
+Flags:
+  -h, --help                Print this help text and exit.
+      --no-pager            Don't page long output.
+";
+
+
+const RECOVER_HELPTEXT: &str = "
+Usage: vimv recover
+
+  Lists the batches of operations that were interrupted before they
+  finished, e.g. by a crash, a power loss, or a failure part-way through,
+  showing which operations completed and which are still pending.
+
+  Use the --finish flag to execute the pending operations of the most
+  recent interrupted batch, or the --rollback flag to reverse its completed
+  operations, e.g.
+
+    $ vimv recover
+    $ vimv recover --finish
+    $ vimv recover --rollback
+
+  Either way, temporary files left behind by an interrupted cycle are
+  resolved. If Vimv stopped mid-operation, the file system is checked to
+  see whether that operation completed. Finished batches can be reversed
+  with `vimv undo` as usual.
+
+Flags:
+      --finish              Execute the pending operations.
+      --rollback            Reverse the completed operations.
+  -n, --dry-run             Print the planned operations without executing them.
+  -h, --help                Print this help text and exit.
+  -q, --quiet               Quiet mode -- only report errors.
+";
+
+
+const RESTORE_HELPTEXT: &str = "
+Usage: vimv restore [files]
+
+  Restores files moved to the trash by previous Vimv runs to their original
+  paths. Run without arguments to list the trashed files. Files can be
+  selected by their original path or by their number in the list, e.g.
+
+    $ vimv restore
+    $ vimv restore 1 3
+
+  Files are never restored over existing files. Restoring files from the
+  trash is supported on Linux and Windows but not on macOS.
+
+  The list of trashed files is stored in the $XDG_DATA_HOME/vimv directory.
+
+Arguments:
+  [files]                   List of files to restore.
+
+Flags:
+  -a, --all                 Restore all listed files.
+  -n, --dry-run             Print the planned operations without executing them.
+  -h, --help                Print this help text and exit.
+  -q, --quiet               Quiet mode -- only report errors.
+";
+
+
+const UNDO_HELPTEXT: &str = "
+Usage: vimv undo [session]
+
+  Reverses the most recent batch of operations recorded in the journal.
+  Renamed files are renamed back to their original names, directories
+  created by the batch are removed if empty, and deleted files are restored
+  from the system's trash/recycle bin where the platform supports it.
+
+  Use the --steps option to reverse several batches, newest first, or
+  specify a session's number from `vimv history` to reverse that session's
+  batch, e.g.
+
+    $ vimv undo --steps 3
+    $ vimv undo 12
+
+  Every batch is checked against the file system before anything is
+  changed. If a renamed file has since been moved or its original name has
+  been taken, Vimv exits with an error and leaves everything in place.
+
+  Journal files are stored in the $XDG_DATA_HOME/vimv/journal directory.
+
+Arguments:
+  [session]                 Number of the session to undo.
+
+Options:
+      --steps <n>           Number of batches to undo. Defaults to 1.
+
+Flags:
+  -n, --dry-run             Print the planned operations without executing them.
+  -h, --help                Print this help text and exit.
+  -q, --quiet               Quiet mode -- only report errors.
 ";
 
 
@@ -55,261 +781,2982 @@ fn main() {
     let mut parser = ArgParser::new()
         .helptext(HELPTEXT)
         .version(env!("CARGO_PKG_VERSION"))
+        .flag("absolute")
+        .flag("allow-missing-lines")
+        .flag("allow-reserved-names")
+        .flag("annotate")
+        .flag("backup b")
+        .flag("compare-overwrites")
+        .flag("confirm-delete")
+        .flag("copy c")
+        .flag("dedupe")
+        .flag("dry-run n")
+        .flag("edit")
         .flag("force f")
+        .flag("force-if-duplicate")
+        .flag("fsync")
+        .flag("git g")
+        .flag("glob")
+        .flag("hardlink H")
+        .flag("hidden")
+        .flag("ignore-case")
+        .flag("json")
+        .flag("keep-going k")
+        .flag("leave-symlink l")
+        .flag("list-dirs")
+        .flag("lower")
+        .flag("no-fs-check")
+        .flag("no-header")
+        .flag("no-hidden")
+        .flag("no-ignore")
+        .flag("no-pager")
+        .flag("null 0")
+        .flag("pairs")
+        .flag("pick")
+        .flag("print")
+        .flag("print0")
         .flag("quiet q")
+        .flag("recursive r")
+        .flag("relative")
+        .flag("restrict")
+        .flag("resume")
+        .flag("review")
+        .flag("rm")
+        .flag("skip-existing")
         .flag("stdin s")
-        .option("editor e", "");
+        .flag("strict-rename")
+        .flag("title")
+        .flag("tui")
+        .flag("upper")
+        .flag("wait-lock")
+        .flag("yes y")
+        .option("archive-dir", "")
+        .option("backup-dir", "")
+        .option("backup-suffix", "~")
+        .option("buffer-ext", "vimv")
+        .option("color", "auto")
+        .option("delete-prefix", "#")
+        .option("delete-threshold", "20")
+        .option("editor e", "")
+        .option("exclude x", "")
+        .option("exec", "")
+        .option("format", "single")
+        .option("include i", "")
+        .option("jobs j", "1")
+        .option("log", "")
+        .option("max-depth", "")
+        .option("no-preserve", "")
+        .option("normalize", "")
+        .option("on-conflict", "error")
+        .option("output-list O", "")
+        .option("prefix", "")
+        .option("preset", "")
+        .option("progress", "100")
+        .option("regex", "")
+        .option("replace", "")
+        .option("restrict-dir", "")
+        .option("retries", "0")
+        .option("retry-delay", "100")
+        .option("save-plan", "")
+        .option("script", "")
+        .option("sort", "none")
+        .option("suffix", "")
+        .option("template t", "")
+        .option("trash-dir", "")
+        .option("validate-cmd", "")
+        .option("vcs", "")
+        .command("apply", ArgParser::new()
+            .helptext(APPLY_HELPTEXT)
+            .flag("dry-run n")
+            .flag("force f")
+            .flag("fsync")
+            .flag("keep-going k")
+            .flag("quiet q")
+            .flag("rm")
+            .flag("yes y")
+            .option("jobs j", "1")
+            .option("log", "")
+            .option("progress", "100")
+            .option("retries", "0")
+            .option("retry-delay", "100")
+        )
+        .command("completions", ArgParser::new()
+            .helptext(COMPLETIONS_HELPTEXT)
+        )
+        .command("history", ArgParser::new()
+            .helptext(HISTORY_HELPTEXT)
+            .flag("no-pager")
+        )
+        .command("recover", ArgParser::new()
+            .helptext(RECOVER_HELPTEXT)
+            .flag("dry-run n")
+            .flag("finish")
+            .flag("quiet q")
+            .flag("rollback")
+        )
+        .command("restore", ArgParser::new()
+            .helptext(RESTORE_HELPTEXT)
+            .flag("all a")
+            .flag("dry-run n")
+            .flag("quiet q")
+        )
+        .command("undo", ArgParser::new()
+            .helptext(UNDO_HELPTEXT)
+            .flag("dry-run n")
+            .flag("quiet q")
+            .option("steps", "1")
+        );
 
     // Parse the command line arguments.
     if let Err(err) = parser.parse() {
         err.exit();
     }
 
-    // Use the --editor option if present to set $VISUAL.
-    if parser.found("editor") {
-        env::set_var("VISUAL", parser.value("editor"));
+    // In JSON mode we switch off the text output.
+    if parser.found("json") {
+        JSON_OUTPUT.store(true, Ordering::Relaxed);
     }
 
-    // Assemble the list of input filenames.
-    let mut input_files: Vec<String> = parser.args.clone();
+    // Decide whether to colorize the output. By default, we only use color if standard output
+    // is a terminal and the $NO_COLOR environment variable isn't set.
+    let color = match parser.value("color").as_str() {
+        "always" => true,
+        "never" => false,
+        "auto" => {
+            std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+        other => exit_with_error(format!("invalid color mode '{}', expected 'auto', 'always', or 'never'", other)),
+    };
+    colored::control::set_override(color);
+    let print_results = parser.found("print") || parser.found("print0");
+    let quiet = (parser.found("quiet") || print_results) && !parser.found("json");
 
-    // If no input files have been specified, use the content of the current directory.
-    if input_files.is_empty() && !parser.found("stdin") {
-        let current_dir = env::current_dir().unwrap_or_else(|err| {
-            eprintln!("error: failed to locate current directory: {}", err);
-            exit(1);
-        });
-        let current_dir_iterator = fs::read_dir(current_dir).unwrap_or_else(|err| {
-            eprintln!("error: failed to read current directory: {}", err);
-            exit(1);
-        });
-        for entry in current_dir_iterator {
-            let entry = entry.unwrap_or_else(|err| {
-                eprintln!("error: failed to read current directory entry: {}", err);
-                exit(1);
-            });
-            let entry_as_string = entry.file_name().into_string().unwrap_or_else(|err| {
-                eprintln!("error: failed to decode current directory entry name: {:?}", err);
-                exit(1);
-            });
-            input_files.push(entry_as_string);
+    // Dispatch to a command if present.
+    if let (Some(cmd_name), Some(cmd_parser)) = (&parser.cmd_name, &parser.cmd_parser) {
+        if cmd_name == "apply" {
+            apply(cmd_parser);
+        } else if cmd_name == "completions" {
+            print_completions(&cmd_parser.args);
+        } else if cmd_name == "history" {
+            show_history(&cmd_parser.args, !cmd_parser.found("no-pager"));
+        } else if cmd_name == "recover" {
+            recover(
+                cmd_parser.found("finish"),
+                cmd_parser.found("rollback"),
+                cmd_parser.found("dry-run"),
+                cmd_parser.found("quiet"),
+            );
+        } else if cmd_name == "restore" {
+            restore(&cmd_parser.args, cmd_parser.found("all"), cmd_parser.found("dry-run"), cmd_parser.found("quiet"));
+        } else {
+            if !cmd_parser.args.is_empty() && cmd_parser.found("steps") {
+                exit_with_error("the --steps option cannot be used with a session number");
+            }
+            undo(&cmd_parser.args, &cmd_parser.value("steps"), cmd_parser.found("dry-run"), cmd_parser.found("quiet"));
         }
-        input_files.sort();
+        return;
     }
 
-    // If the --stdin flag has been set, try reading from standard input.
-    if parser.found("stdin") {
-        let mut buffer = String::new();
-        if let Err(err) = std::io::stdin().read_to_string(&mut buffer) {
-            eprintln!("error: failed to read filenames from standard input: {}", err);
-            exit(1);
-        }
-        if !buffer.trim().is_empty() {
-            input_files.extend(buffer.lines().map(|s| s.to_string()));
+    // Sanity check - verify that the --vcs option has a valid value and that the current directory
+    // is inside a matching repository. The --git flag is shorthand for --vcs git.
+    if parser.found("git") && parser.found("vcs") && parser.value("vcs") != "git" {
+        exit_with_error("the --git flag cannot be combined with --vcs");
+    }
+    let vcs = if parser.found("git") {
+        Some(Vcs::Git)
+    } else if parser.value("vcs") == "auto" {
+        Some(Vcs::detect().unwrap_or_else(|| {
+            exit_with_error(
+                "the --vcs auto option requires the current directory to be inside a git, Mercurial, \
+                or Jujutsu repository",
+            )
+        }))
+    } else if parser.found("vcs") {
+        Some(Vcs::from_name(&parser.value("vcs")).unwrap_or_else(|| {
+            exit_with_error(format!(
+                "invalid version control system '{}', expected 'git', 'hg', 'jj', or 'auto'",
+                parser.value("vcs")
+            ))
+        }))
+    } else {
+        None
+    };
+    if let Some(vcs) = vcs {
+        if !vcs.is_active() {
+            let arg = if parser.found("git") {
+                "--git flag".to_string()
+            } else {
+                format!("--vcs {} option", parser.value("vcs"))
+            };
+            exit_with_error(format!(
+                "the {} requires the current directory to be inside a {} repository",
+                arg,
+                vcs.name()
+            ));
         }
     }
 
-    // Bail if we have no input filenames to process.
-    if input_files.is_empty() {
-        exit(0);
+    // Sanity check - the --print and --print0 flags replace the normal output.
+    if print_results && parser.found("json") {
+        exit_with_error("the --print and --print0 flags cannot be used with --json");
     }
 
-    // Sanity check - verify that no input filename begins with '#'.
-    for input_file in &input_files {
-        if input_file.starts_with("#") {
-            eprintln!("error: input filenames cannot begin with '#'");
-            exit(1);
-        }
+    // Sanity check - the --copy and --hardlink flags are mutually exclusive.
+    if parser.found("copy") && parser.found("hardlink") {
+        exit_with_error("the --copy and --hardlink flags cannot be used together");
     }
 
-    // Sanity check - verify that all the input files exist.
-    for input_file in &input_files {
-        if !Path::new(input_file).exists() {
-            eprintln!("error: the input file '{}' does not exist", input_file);
-            exit(1);
-        }
+    // Sanity check - the --leave-symlink flag only makes sense when renaming files.
+    if parser.found("leave-symlink") && (parser.found("copy") || parser.found("hardlink")) {
+        exit_with_error("the --leave-symlink flag cannot be used with --copy or --hardlink");
     }
 
-    // Sanity check - verify that the input filenames are unique.
-    let mut input_set = HashSet::new();
-    for input_file in &input_files {
-        if input_set.contains(input_file) {
-            eprintln!("error: the filename '{}' appears in the input list multiple times", input_file);
-            exit(1);
+    // Sanity check - verify that the --normalize option has a valid value.
+    let normalize = if parser.found("normalize") {
+        match Normalization::from_name(&parser.value("normalize")) {
+            Some(form) => Some(form),
+            None => exit_with_error(format!(
+                "invalid normalization form '{}', expected 'nfc' or 'nfd'",
+                parser.value("normalize")
+            )),
         }
-        input_set.insert(input_file);
+    } else {
+        None
+    };
+
+    // Sanity check - verify that the --sort option has a valid value.
+    let sort_order = SortOrder::from_name(&parser.value("sort")).unwrap_or_else(|| {
+        exit_with_error(format!(
+            "invalid sort order '{}', expected 'name', 'natural', 'mtime', 'size', or 'none'",
+            parser.value("sort")
+        ));
+    });
+
+    // Sanity check - verify that the --format option has a valid value.
+    let buffer_format = BufferFormat::from_name(&parser.value("format")).unwrap_or_else(|| {
+        exit_with_error(format!(
+            "invalid buffer format '{}', expected 'single', 'dual', 'numbered', or 'grouped'",
+            parser.value("format")
+        ));
+    });
+
+    // Sanity check - verify that the --on-conflict option has a valid value. The 'ask' policy is
+    // handled here rather than by the planner.
+    let ask_on_conflict = parser.value("on-conflict") == "ask";
+    if ask_on_conflict && parser.found("yes") {
+        exit_with_error("the --yes flag cannot be combined with --on-conflict ask");
+    }
+    let on_conflict = if ask_on_conflict {
+        Conflict::Error
+    } else {
+        Conflict::from_name(&parser.value("on-conflict")).unwrap_or_else(|| {
+            exit_with_error(format!(
+                "invalid conflict policy '{}', expected 'error', 'skip', 'overwrite', 'number', or 'ask'",
+                parser.value("on-conflict")
+            ));
+        })
+    };
+    if parser.found("force") && parser.found("on-conflict") && on_conflict != Conflict::Overwrite {
+        exit_with_error("the --force flag can only be combined with --on-conflict overwrite");
     }
 
-    // Fetch the output filenames from the editor.
-    let editor_input = input_files.join("\n") + "\n";
-    let editor_output = match edit::edit(editor_input) {
-        Ok(edited) => edited.trim().to_string(),
-        Err(err) => {
-            eprintln!("error: {}", err);
-            exit(1);
+    // The --skip-existing flag is shorthand for --on-conflict skip.
+    let on_conflict = if parser.found("skip-existing") {
+        if parser.found("force") || (parser.found("on-conflict") && parser.value("on-conflict") != "skip") {
+            exit_with_error("the --skip-existing flag cannot be combined with --force or another --on-conflict policy");
         }
+        Conflict::Skip
+    } else {
+        on_conflict
     };
-    let output_files: Vec<String> = editor_output.lines().map(|s| s.to_string()).collect();
 
-    // Sanity check - verify that we have equal numbers of input and output filenames.
-    if output_files.len() != input_files.len() {
-        eprintln!(
-            "error: the number of input filenames ({}) does not match the number of output filenames ({})",
-            input_files.len(),
-            output_files.len()
-        );
-        exit(1);
+    // If no conflict policy has been specified and we're running interactively, we ask about each
+    // existing file instead of aborting the whole batch. (Not in dry-run mode, where nothing is
+    // going to be overwritten anyway, or if the --yes flag has been set.)
+    let policy_specified = ["on-conflict", "skip-existing", "force", "force-if-duplicate"]
+        .into_iter()
+        .any(|name| parser.found(name));
+    let interactive = std::io::stdin().is_terminal()
+        && std::io::stderr().is_terminal()
+        && !parser.found("json")
+        && !parser.found("yes");
+    let ask_on_conflict = ask_on_conflict
+        || (!policy_specified && interactive && !parser.found("dry-run") && !parser.found("save-plan"));
+
+    // Sanity check - verify that the --no-preserve option has a valid value.
+    let preserve = Preserve::all().without(&parser.value("no-preserve")).unwrap_or_else(|| {
+        exit_with_error(format!(
+            "invalid --no-preserve list '{}', expected 'mode', 'timestamps', 'xattr', or 'all'",
+            parser.value("no-preserve")
+        ));
+    });
+
+    // Sanity check - the --lower, --upper, and --title flags are mutually exclusive.
+    let case_flags: Vec<&str> = ["lower", "upper", "title"].into_iter().filter(|flag| parser.found(flag)).collect();
+    if case_flags.len() > 1 {
+        exit_with_error("the --lower, --upper, and --title flags cannot be used together");
     }
 
-    // Sanity check - verify that the output filenames are unique.
-    let mut case_sensitive_output_set = HashSet::new();
-    for output_file in output_files.iter().filter(|s| !s.starts_with("#")) {
-        if case_sensitive_output_set.contains(output_file) {
-            eprintln!("error: the filename '{}' appears in the output list multiple times", output_file);
-            exit(1);
-        }
-        case_sensitive_output_set.insert(output_file);
+    // Sanity check - the --tui and --pick flags need a terminal to draw on.
+    if parser.found("tui") && !std::io::stdout().is_terminal() {
+        exit_with_error("the --tui flag requires standard output to be a terminal");
+    }
+    if parser.found("pick") && !std::io::stdout().is_terminal() {
+        exit_with_error("the --pick flag requires standard output to be a terminal");
     }
 
-    // Sanity check - verify that the output filenames are case-insensitively unique.
-    let mut case_insensitive_output_set = HashSet::new();
-    for output_file in output_files.iter().filter(|s| !s.starts_with("#")).map(|s| s.to_lowercase()) {
-        if case_insensitive_output_set.contains(&output_file) {
-            eprintln!(
-                "error: the filename '{}' appears multiple times in the output list (case \
-                insensitively); this may be intentional but Vimv always treats this situation \
-                as an error to avoid accidentally overwriting files on case-insensitive \
-                file systems",
-                output_file
-            );
-            exit(1);
+    // Assemble the list of transformations. Any template is applied first, then substitutions in
+    // order, then any case transformation, then any prefix and suffix.
+    let mut transforms: Vec<Transform> = Vec::new();
+    if parser.found("template") && parser.found("preset") {
+        exit_with_error("the --template and --preset options cannot be used together");
+    }
+    let template = if parser.found("preset") {
+        match vimv::transform::preset(&parser.value("preset")) {
+            Some(template) => Some(template.to_string()),
+            None => exit_with_error(format!(
+                "invalid preset '{}', expected 'music' or 'by-date'",
+                parser.value("preset")
+            )),
+        }
+    } else if parser.found("template") {
+        Some(parser.value("template"))
+    } else {
+        None
+    };
+    if let Some(template) = template {
+        match Template::parse(&template) {
+            Ok(template) => transforms.push(Transform::Template(template)),
+            Err(err) => exit_with_error(err.to_string()),
+        }
+    }
+    for expr in parser.values("regex") {
+        match Substitution::parse(&expr) {
+            Ok(substitution) => transforms.push(Transform::Substitute(substitution)),
+            Err(err) => exit_with_error(err.to_string()),
+        }
+    }
+    if let Some(flag) = case_flags.first() {
+        transforms.push(Transform::Case(match *flag {
+            "lower" => Case::Lower,
+            "upper" => Case::Upper,
+            _ => Case::Title,
+        }));
+    }
+    let affix = |name: &str| {
+        let value = parser.value(name);
+        if value.contains(['/', std::path::MAIN_SEPARATOR]) {
+            exit_with_error(format!("the --{} option cannot contain a path separator", name));
         }
-        case_insensitive_output_set.insert(output_file);
+        value
+    };
+    if parser.found("prefix") {
+        transforms.push(Transform::Prefix(affix("prefix")));
+    }
+    if parser.found("suffix") {
+        transforms.push(Transform::Suffix(affix("suffix")));
     }
 
-    // List of files to delete.
-    let mut delete_list: Vec<&str> = Vec::new();
+    // The --script option runs a script over the whole input list at once, so it can't be chained
+    // with the other transformations. Unlike them, it always opens the editor for review.
+    let script = if parser.found("script") {
+        if !transforms.is_empty() {
+            exit_with_error(
+                "the --script option cannot be combined with --template, --preset, --regex, --prefix, --suffix, \
+                --lower, --upper, or --title",
+            );
+        }
+        Some(Script::load(&parser.value("script")).unwrap_or_else(|err| exit_with_error(err.to_string())))
+    } else {
+        None
+    };
 
-    // List of rename operations as (src, dst) tuples.
-    let mut rename_list: Vec<(String, String)> = Vec::new();
+    // The --replace option is a pre-pass applied after any transformation or script. On its own it
+    // doesn't skip the editor: the replaced filenames are filled in for review.
+    let replacements: Vec<Transform> = parser
+        .values("replace")
+        .iter()
+        .map(|expr| match Replacement::parse(expr, parser.found("ignore-case")) {
+            Ok(replacement) => Transform::Replace(replacement),
+            Err(err) => exit_with_error(err.to_string()),
+        })
+        .collect();
+    if parser.found("ignore-case") && replacements.is_empty() {
+        exit_with_error("the --ignore-case flag requires --replace");
+    }
 
-    // Set of input files to be renamed. Used to check for cycles.
-    let mut rename_set: HashSet<String> = HashSet::new();
+    // Sanity check - the --edit flag only makes sense with a transformation.
+    if parser.found("edit") && transforms.is_empty() && script.is_none() && replacements.is_empty() {
+        exit_with_error(
+            "the --edit flag requires --template, --preset, --regex, --replace, --script, --prefix, --suffix, \
+            --lower, --upper, or --title",
+        );
+    }
 
-    // Populate the task lists.
-    for (input_file, output_file) in input_files.iter().zip(output_files.iter()) {
-        if input_file == output_file {
-            continue;
+    // Sanity check - the --output-list option replaces the editor and any transformation, and can
+    // only read standard input if the input files aren't being read from it.
+    if parser.found("output-list") {
+        if !transforms.is_empty() || script.is_some() || !replacements.is_empty() || parser.found("tui") {
+            exit_with_error(
+                "the --output-list option cannot be combined with --tui, --template, --preset, --regex, --replace, \
+                --script, --prefix, --suffix, --lower, --upper, or --title",
+            );
         }
+        if parser.value("output-list") == "-" && (parser.found("stdin") || parser.found("null")) {
+            exit_with_error("the --output-list option cannot read standard input when the --stdin flag is set");
+        }
+    }
 
-        if Path::new(output_file).is_dir() {
-            if input_files.contains(output_file) {
-                rename_list.push((input_file.to_string(), output_file.to_string()));
-                rename_set.insert(input_file.to_string());
-                continue;
-            }
-            eprintln!("error: cannot overwrite the existing directory '{}'", output_file);
-            exit(1);
+    // Deleted files are archived in a directory named for the start of the run so repeated runs
+    // don't mix.
+    let archive_dir = if parser.found("archive-dir") {
+        if parser.found("rm") {
+            exit_with_error("the --archive-dir option cannot be used with the --rm flag");
+        }
+        if parser.found("trash-dir") {
+            exit_with_error("the --archive-dir and --trash-dir options cannot be used together");
         }
+        let dir = PathBuf::from(parser.value("archive-dir"));
+        Some(dir.join(Local::now().format("%Y-%m-%d-%H%M%S").to_string()))
+    } else {
+        None
+    };
 
-        if output_file.starts_with("#") {
-            delete_list.push(input_file);
-            continue;
+    // The --trash-dir option replaces the default trash.
+    let trash_dir = if parser.found("trash-dir") {
+        if parser.found("rm") {
+            exit_with_error("the --trash-dir option cannot be used with the --rm flag");
         }
+        Some(PathBuf::from(parser.value("trash-dir")))
+    } else {
+        None
+    };
 
-        if Path::new(output_file).is_file() {
-            if input_files.contains(output_file) {
-                rename_list.push((input_file.to_string(), output_file.to_string()));
-                rename_set.insert(input_file.to_string());
-                continue;
-            }
+    // The --backup-dir and --backup-suffix options imply --backup.
+    let backup = if parser.found("backup-dir") {
+        Some(Backup::Dir(parser.value("backup-dir").into()))
+    } else if parser.found("backup") || parser.found("backup-suffix") {
+        Some(Backup::Suffix(parser.value("backup-suffix")))
+    } else {
+        None
+    };
 
-           if parser.found("force") {
-                rename_list.push((input_file.to_string(), output_file.to_string()));
-                rename_set.insert(input_file.to_string());
-                continue;
-            }
+    // The --exec command is split into words once, here, so the placeholders are substituted into
+    // individual arguments and filenames never pass through a shell.
+    let hook = if parser.found("exec") {
+        match editor::split_command(&parser.value("exec")) {
+            Some(words) if !words.is_empty() => Some(words),
+            _ => exit_with_error(format!("invalid --exec command '{}'", parser.value("exec"))),
+        }
+    } else {
+        None
+    };
 
-            eprintln!(
-                "error: the output file '{}' already exists, use --force to overwrite it",
-                output_file
-            );
-            exit(1);
+    // The --validate-cmd command is split into words here so an unparsable command fails before the
+    // editor opens.
+    let validator = if parser.found("validate-cmd") {
+        match editor::split_command(&parser.value("validate-cmd")) {
+            Some(words) if !words.is_empty() => Some(words),
+            _ => exit_with_error(format!("invalid --validate-cmd command '{}'", parser.value("validate-cmd"))),
         }
+    } else {
+        None
+    };
 
-        rename_list.push((input_file.to_string(), output_file.to_string()));
-        rename_set.insert(input_file.to_string());
-    }
+    // The --editor option overrides $VISUAL and $EDITOR.
+    let editor = if parser.found("editor") { Some(parser.value("editor")) } else { None };
 
-    // Check for cycles. If we find [src] being renamed to [dst] where [dst] is an input file that
-    // hasn't yet been deleted or renamed, we rename [src] to [tmp] instead and later rename [tmp]
-    // to [dst].
-    for i in 0..rename_list.len() {
-        if rename_set.contains(&rename_list[i].1) {
-            let temp_file = get_temp_filename(&rename_list[i].0);
-            rename_list.push((temp_file.clone(), rename_list[i].1.clone()));
-            rename_list[i].1 = temp_file
-        }
-        rename_set.remove(&rename_list[i].0);
+    // Sanity check - verify that the --buffer-ext option has a valid value.
+    let buffer_ext = parser.value("buffer-ext").trim_start_matches('.').to_string();
+    if buffer_ext.is_empty() || buffer_ext.contains(['/', '\\']) {
+        exit_with_error(format!("invalid buffer extension '{}'", parser.value("buffer-ext")));
     }
 
-    // Deletion loop. We haven't made any changes to the file system up to this point.
-    for input_file in delete_list {
-        delete_file(input_file, parser.found("quiet"));
+    // Sanity check - verify that the deletion prefix can't be mistaken for a comment or for the
+    // other line prefixes.
+    let delete_prefix = parser.value("delete-prefix");
+    let reserved = [
+        vimv::buffer::COMMENT_PREFIX,
+        vimv::buffer::COPY_PREFIX,
+        vimv::buffer::CREATE_PREFIX,
+        vimv::buffer::PURGE_PREFIX,
+        "./",
+    ];
+    if delete_prefix.trim().is_empty() || reserved.iter().any(|prefix| delete_prefix.starts_with(prefix)) {
+        exit_with_error(format!("invalid deletion prefix '{}'", delete_prefix));
     }
 
-    // Rename loop.
-    for (input_file, output_file) in rename_list {
-        move_file(&input_file, &output_file, parser.found("quiet"));
+    // Sanity check - verify that the --max-depth option has a valid value.
+    let max_depth = if parser.found("max-depth") {
+        match parser.value("max-depth").parse::<usize>() {
+            Ok(depth) if depth > 0 => Some(depth),
+            _ => exit_with_error(format!(
+                "invalid maximum depth '{}', expected a positive integer",
+                parser.value("max-depth")
+            )),
+        }
+    } else {
+        None
+    };
+
+    // Sanity check - verify that the --jobs option has a valid value.
+    let jobs = parse_jobs(&parser.value("jobs"));
+
+    // Sanity check - verify that the --delete-threshold option has a valid value.
+    let delete_threshold = parser.value("delete-threshold");
+    let delete_threshold = delete_threshold.parse::<usize>().unwrap_or_else(|_| {
+        exit_with_error(format!("invalid delete threshold '{}', expected a number", delete_threshold));
+    });
+
+    // Sanity check - verify that the --retries and --retry-delay options have valid values.
+    let (retries, retry_delay) = parse_retries(&parser.value("retries"), &parser.value("retry-delay"));
+
+    // Sanity check - the --absolute and --relative flags are mutually exclusive.
+    if parser.found("absolute") && parser.found("relative") {
+        exit_with_error("the --absolute and --relative flags cannot be used together");
     }
-}
 
+    // Sanity check - the --hidden and --no-hidden flags are mutually exclusive.
+    if parser.found("hidden") && parser.found("no-hidden") {
+        exit_with_error("the --hidden and --no-hidden flags cannot be used together");
+    }
 
-// Generate a unique temporary filename.
-fn get_temp_filename(base: &str) -> String {
-    let mut rng = rand::thread_rng();
-    for _ in 0..10 {
-        let candidate = format!("{}.vimv_temp_{:04}", base, rng.gen_range(0..10_000));
-        if !Path::new(&candidate).exists() {
-            return candidate;
+    // Sanity check - the --pairs flag supplies both the input and output filenames so it can't be
+    // combined with anything that collects, reorders, or renames input files.
+    if parser.found("pairs") {
+        let conflicting = [
+            "stdin", "glob", "recursive", "max-depth", "list-dirs", "include", "exclude", "sort", "output-list",
+            "template", "preset", "regex", "replace", "script", "prefix", "suffix", "lower", "upper", "title", "edit",
+            "tui", "pick",
+        ];
+        if !parser.args.is_empty() || conflicting.iter().any(|name| parser.found(name)) {
+            exit_with_error("the --pairs flag cannot be combined with input files or options that select them");
         }
     }
-    eprintln!(
-        "error: failed to generate a unique temporary filename of the form '{}.vimv_temp_XXXX'",
-        base
-    );
-    exit(1);
-}
 
+    // Sanity check - the --resume flag reloads the input files and edits from a saved buffer so it
+    // can't be combined with anything that collects or renames input files.
+    if parser.found("resume") {
+        let conflicting = [
+            "stdin", "null", "pairs", "glob", "recursive", "max-depth", "list-dirs", "include", "exclude", "sort",
+            "output-list", "format", "template", "preset", "regex", "replace", "script", "prefix", "suffix", "lower",
+            "upper", "title", "edit", "tui", "absolute", "relative", "pick",
+        ];
+        if !parser.args.is_empty() || conflicting.iter().any(|name| parser.found(name)) {
+            exit_with_error("the --resume flag cannot be combined with input files or options that select them");
+        }
+    }
 
-// Move the specified file to the system's trash/recycle bin.
-fn delete_file(input_file: &str, quiet: bool) {
-    if !quiet {
-        println!("{} {}", "Deleting".green().bold(), input_file);
+    // Assemble the list of input filenames. In pairs mode, the input and output filenames are read
+    // together from standard input. In resume mode, they're reloaded from the saved buffer.
+    let mut input_files: Vec<String> = parser.args.clone();
+    let mut pair_outputs = None;
+    if parser.found("pairs") {
+        let (pair_inputs, outputs) = read_pairs(parser.found("null"));
+        input_files = pair_inputs;
+        pair_outputs = Some(outputs);
     }
-    if let Err(err) = trash::delete(input_file) {
-        eprintln!("error: cannot delete the file '{}': {}", input_file, err);
-        exit(1);
+    let resumed = if parser.found("resume") { Some(load_saved_buffer()) } else { None };
+    let mut resume_outputs = None;
+    let mut resume_text = None;
+    if let Some(saved) = &resumed {
+        let (inputs, outputs, text) = resume_saved_buffer(saved, &delete_prefix, quiet);
+        input_files = inputs;
+        resume_outputs = outputs;
+        resume_text = text;
     }
-}
+    let buffer_format = resumed.as_ref().map_or(buffer_format, |saved| saved.format);
 
-
-// Rename `input_file` to `output_file`.
-fn move_file(input_file: &str, output_file: &str, quiet: bool) {
-    if !quiet {
-        println!("{} {}", "Renaming".green().bold(), input_file);
-        println!("      {}  {}", "⮑".green().bold(), output_file);
-    }
-    if let Some(parent_path) = Path::new(output_file).parent() {
-        if !parent_path.is_dir() {
-            if let Err(err) = std::fs::create_dir_all(parent_path) {
-                eprintln!("error: cannot create the required directory '{}': {}", parent_path.display(), err);
-                exit(1);
+    // If the --glob flag has been set, expand glob patterns in the arguments ourselves. On Windows,
+    // where the shell doesn't expand globs, we do this automatically for any pattern that isn't
+    // the literal name of an existing file.
+    if parser.found("glob") || cfg!(windows) {
+        let mut expanded_files = Vec::new();
+        for arg in input_files {
+            if vimv::input::is_glob_pattern(&arg) && (parser.found("glob") || !Path::new(&arg).exists()) {
+                match vimv::input::expand_glob(&arg, !parser.found("hidden")) {
+                    Ok(files) => expanded_files.extend(files),
+                    Err(err) => exit_with_error(err.to_string()),
+                }
+            } else {
+                expanded_files.push(arg);
             }
         }
+        input_files = expanded_files;
     }
-    if let Err(err) = std::fs::rename(input_file, output_file) {
-        eprintln!("error: cannot rename the file '{}' to '{}': {}", input_file, output_file, err);
-        exit(1);
+
+    // If no input files have been specified, use the content of the current directory.
+    let read_stdin = !parser.found("pairs") && (parser.found("stdin") || parser.found("null"));
+    if input_files.is_empty() && !read_stdin && !parser.found("pairs") && !parser.found("resume") {
+        input_files = vimv::input::list_current_dir(parser.found("no-hidden")).unwrap_or_else(|err| {
+            exit_with_error(err.to_string());
+        });
     }
-}
+
+    // If the --stdin flag has been set, try reading from standard input. If the --null flag has
+    // been set, filenames are separated by NUL characters instead of newlines.
+    if read_stdin {
+        let mut buffer = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut buffer) {
+            exit_with_error(format!("failed to read filenames from standard input: {}", err));
+        }
+        if parser.found("null") {
+            input_files.extend(buffer.split('\0').filter(|s| !s.is_empty()).map(|s| s.to_string()));
+        } else if !buffer.trim().is_empty() {
+            input_files.extend(buffer.lines().map(|s| s.to_string()));
+        }
+    }
+
+    // If the --recursive flag has been set, replace directories with their contents. The
+    // --max-depth option and --list-dirs flag imply --recursive.
+    if parser.found("recursive") || parser.found("max-depth") || parser.found("list-dirs") {
+        let expand_options = ExpandOptions {
+            max_depth,
+            list_dirs: parser.found("list-dirs"),
+            skip_hidden: parser.found("no-hidden"),
+            skip_ignored: !parser.found("no-ignore"),
+        };
+        let mut expanded_files = Vec::new();
+        for input_file in input_files {
+            if Path::new(&input_file).is_dir() && !Path::new(&input_file).is_symlink() {
+                if expand_options.list_dirs {
+                    expanded_files.push(input_file.clone());
+                }
+                if let Err(err) = vimv::input::expand_directory(&input_file, &expand_options, &mut expanded_files) {
+                    exit_with_error(err.to_string());
+                }
+            } else {
+                expanded_files.push(input_file);
+            }
+        }
+        input_files = expanded_files;
+    }
+
+    // Filter the input filenames if the --include or --exclude options have been set.
+    vimv::input::filter_files(&mut input_files, &parser.values("include"), &parser.values("exclude"));
+
+    // Drop duplicate input filenames if the --dedupe flag has been set. In pairs mode, each input
+    // filename comes with its own output filename so duplicates can't be dropped silently.
+    if parser.found("dedupe") {
+        if parser.found("pairs") {
+            exit_with_error("the --dedupe flag cannot be used with the --pairs flag");
+        }
+        vimv::input::dedupe_files(&mut input_files);
+    }
+
+    // Rewrite the input filenames as absolute or relative paths if the --absolute or --relative
+    // flag has been set. The buffer shows them in this form and relative new filenames are still
+    // resolved against the current directory.
+    if parser.found("absolute") {
+        vimv::input::absolute_files(&mut input_files);
+    } else if parser.found("relative") {
+        vimv::input::relative_files(&mut input_files);
+    }
+
+    // Sort the input filenames if the --sort option has been set.
+    vimv::input::sort_files(&mut input_files, sort_order);
+
+    // If the --pick flag has been set, let the user choose which of the files to edit.
+    if parser.found("pick") && !input_files.is_empty() {
+        match picker::pick(&input_files) {
+            Ok(Some(files)) => input_files = files,
+            Ok(None) => exit(0),
+            Err(err) => exit_with_error(err),
+        }
+    }
+
+    // Bail if we have no input filenames to process.
+    if input_files.is_empty() {
+        exit(0);
+    }
+
+    // Sanity check - verify that the input filenames are valid before opening the editor.
+    if let Err(err) = vimv::validate_inputs(&input_files) {
+        exit_with_error(err.to_string());
+    }
+
+    // Lock the directories containing the input files so concurrent runs can't interleave their
+    // operations. The lock is held until the program exits.
+    let _lock = if parser.found("dry-run") || parser.found("save-plan") {
+        None
+    } else {
+        match vimv::lock::acquire(&input_files, parser.found("wait-lock")) {
+            Ok(lock) => Some(lock),
+            Err(err) => exit_with_error(err.to_string()),
+        }
+    };
+
+    // Apply any script or transformations, then any replacements, to the input filenames. In resume
+    // mode, we start from the saved edits instead.
+    let initial_files: Vec<String> = match resume_outputs {
+        Some(outputs) => outputs,
+        None => {
+            let generated = match &script {
+                Some(script) => script.run(&input_files).unwrap_or_else(|err| exit_with_error(err.to_string())),
+                None => input_files.clone(),
+            };
+            generated
+                .iter()
+                .enumerate()
+                .map(|(index, path)| {
+                    let mut steps = transforms.iter().chain(&replacements);
+                    steps.try_fold(path.to_string(), |path, transform| transform.apply(&path, index))
+                })
+                .collect::<vimv::Result<Vec<String>>>()
+                .unwrap_or_else(|err| exit_with_error(err.to_string()))
+        }
+    };
+
+    // Fetch the output filenames from the editor. If a transformation has been specified, the
+    // editor is skipped unless the --edit or --tui flag has been set. The --pairs flag and the
+    // --output-list option skip the editor entirely.
+    let mut edited_buffer: Option<EditedBuffer> = None;
+    let mut created_files: Vec<String> = Vec::new();
+    let session = EditorSession {
+        input_files: &input_files,
+        editor: editor.as_deref(),
+        extension: &buffer_ext,
+        allow_missing_lines: parser.found("allow-missing-lines"),
+        delete_prefix: &delete_prefix,
+        reopen: interactive,
+        quiet,
+    };
+    let mut output_files = if let Some(mut output_files) = pair_outputs {
+        vimv::buffer::normalize_deletions(&input_files, &mut output_files, &delete_prefix);
+        output_files
+    } else if parser.found("output-list") {
+        let mut output_files = read_output_list(&parser.value("output-list"), parser.found("null"));
+        vimv::buffer::normalize_deletions(&input_files, &mut output_files, &delete_prefix);
+        output_files
+    } else if !transforms.is_empty() && !parser.found("edit") && !parser.found("tui") {
+        initial_files
+    } else if parser.found("tui") {
+        match tui::edit(&input_files, &initial_files) {
+            Ok(Some(output_files)) => {
+                // The interface has no buffer of its own so we keep its edits in the single format.
+                edited_buffer = vimv::buffer::render(&input_files, &output_files, BufferFormat::Single, &[], false, &[])
+                    .ok()
+                    .map(|text| EditedBuffer { text, format: BufferFormat::Single, escaped: false });
+                output_files
+            }
+            Ok(None) => exit(0),
+            Err(err) => exit_with_error(err),
+        }
+    } else {
+        let header = if parser.found("no-header") { Vec::new() } else { buffer_header(&parser, buffer_format) };
+        let escaped = resumed.as_ref().map_or(parser.found("null"), |saved| saved.escaped);
+        // If the --annotate flag has been set, each line ends with a description of its file. The
+        // annotations are removed as soon as the editor closes.
+        let annotate = parser.found("annotate") && resume_text.is_none();
+        let annotations: Vec<String> = if annotate {
+            input_files.iter().map(|input_file| vimv::describe(Path::new(input_file))).collect()
+        } else {
+            Vec::new()
+        };
+        // Directories are shown with a trailing '/' which is removed again when the buffer is read.
+        let shown_files = vimv::buffer::mark_directories(&input_files, &initial_files);
+        let editor_input = match resume_text {
+            Some(text) => text,
+            None => vimv::buffer::render(&input_files, &shown_files, buffer_format, &header, escaped, &annotations)
+                .unwrap_or_else(|err| {
+                    exit_with_error(err.to_string());
+                }),
+        };
+        let mut text = session.edit(&editor_input);
+        if annotate {
+            text = vimv::buffer::strip_annotations(&text);
+        }
+        let buffer = EditedBuffer { text, format: buffer_format, escaped };
+        let (buffer, output_files, created) = session.parse(buffer);
+        edited_buffer = Some(buffer);
+        created_files = created;
+        output_files
+    };
+
+    // Validate the output filenames and assemble the list of operations. The --force-if-duplicate
+    // flag implies --compare-overwrites.
+    let compare_overwrites = parser.found("compare-overwrites") || parser.found("force-if-duplicate");
+    let use_pager = !parser.found("no-pager") && !parser.found("yes");
+    // The --restrict flag keeps the output filenames inside the current directory. The
+    // --restrict-dir option implies --restrict and sets a different root.
+    let restrict = if parser.found("restrict") || parser.found("restrict-dir") {
+        let root = if parser.found("restrict-dir") { parser.value("restrict-dir") } else { ".".to_string() };
+        match fs::canonicalize(&root) {
+            Ok(root) => Some(root),
+            Err(err) => exit_with_error(format!("cannot resolve the directory '{}': {}", root, err)),
+        }
+    } else {
+        None
+    };
+
+    let mut plan_options = PlanOptions {
+        force: parser.found("force"),
+        force_if_duplicate: parser.found("force-if-duplicate"),
+        compare_overwrites,
+        copy: parser.found("copy"),
+        link: parser.found("hardlink"),
+        check_windows_names: cfg!(windows) && !parser.found("allow-reserved-names"),
+        check_filesystems: !parser.found("no-fs-check"),
+        normalize,
+        on_conflict,
+        conflicts: HashMap::new(),
+        restrict,
+        create: created_files,
+    };
+
+    // In ask mode, we prompt for what to do with each existing file the plan would overwrite and
+    // plan again with the answer until no conflicts remain.
+    let plan = loop {
+        let err = match vimv::plan(&input_files, &output_files, &plan_options) {
+            Ok(plan) => break plan,
+            Err(err) => err,
+        };
+        if let Some(output_file) = conflicting_file(&err).filter(|_| ask_on_conflict) {
+            let output_file = output_file.to_string();
+            match ask_conflict(&output_file) {
+                Resolution::Policy(conflict) => {
+                    plan_options.conflicts.insert(output_file, conflict);
+                }
+                Resolution::Rename(new_name) => {
+                    // The planner may have normalized the filename, so we compare in NFC form.
+                    let normalized: String = output_file.nfc().collect();
+                    if let Some(index) = output_files.iter().position(|s| s.nfc().collect::<String>() == normalized) {
+                        output_files[index] = new_name;
+                    }
+                }
+            }
+            continue;
+        }
+
+        // The interface's buffer is never shown so its line numbers would mean nothing, and it
+        // can't be reopened in the editor.
+        let shown_buffer = edited_buffer.as_ref().filter(|_| !parser.found("tui"));
+        let message = locate_plan_error(&err, &input_files, &output_files, shown_buffer);
+        let reopened = shown_buffer.and_then(|buffer| {
+            session.reopen(buffer, &message, |reopened| {
+                locate_plan_error(&err, &input_files, &output_files, Some(reopened))
+            })
+        });
+        match reopened {
+            Some(buffer) => {
+                let (buffer, reparsed, created) = session.parse(buffer);
+                output_files = reparsed;
+                plan_options.create = created;
+                edited_buffer = Some(buffer);
+            }
+            None => exit_with_error(save_edited_buffer(message, &input_files, edited_buffer.as_ref())),
+        }
+    };
+
+    // Warn about changes skipped because their output filenames already exist.
+    for (input_file, output_file) in &plan.skipped {
+        print_json(&[("event", "skip"), ("src", input_file), ("dst", output_file)]);
+        if !quiet && !JSON_OUTPUT.load(Ordering::Relaxed) {
+            eprintln!("warning: skipping '{}', '{}' already exists", input_file, output_file);
+        }
+    }
+
+    // Warn about changes skipped because their input and output filenames already name the same
+    // file, e.g. hard links to the same inode.
+    for (input_file, output_file) in &plan.same_files {
+        print_json(&[("event", "skip"), ("src", input_file), ("dst", output_file)]);
+        if !quiet && !JSON_OUTPUT.load(Ordering::Relaxed) {
+            eprintln!("warning: skipping '{}', '{}' is already a link to the same file", input_file, output_file);
+        }
+    }
+
+    // If the --validate-cmd option has been set, the command can veto the plan before it's saved,
+    // previewed, or executed.
+    if let Some(words) = validator.as_ref().filter(|_| !plan.is_empty()) {
+        let mapping = vimv::mapping::format_tsv(&plan.logical_operations());
+        if let Err(message) = run_validator(words, &mapping) {
+            exit_with_error(save_edited_buffer(message, &input_files, edited_buffer.as_ref()));
+        }
+    }
+
+    // If the --save-plan option has been set, we write the mapping to a file for later review or
+    // application and exit without touching the file system.
+    if parser.found("save-plan") {
+        if !plan_options.create.is_empty() {
+            exit_with_error("new files can't be saved in a mapping, remove the lines beginning with '+'");
+        }
+        let is_copy = |(input_file, output_file): (&String, &String)| {
+            input_file != output_file && vimv::buffer::copy_destination(output_file).is_some()
+        };
+        if input_files.iter().zip(&output_files).any(is_copy) {
+            exit_with_error("copies can't be saved in a mapping, remove the '>' prefixes");
+        }
+        if plan.operations.iter().any(|op| matches!(op, Operation::Purge(_))) {
+            exit_with_error("permanent deletions can't be saved in a mapping, use '#' and 'vimv apply --rm' instead");
+        }
+        let path = PathBuf::from(parser.value("save-plan"));
+        let format = MappingFormat::from_path(&path);
+        if let Err(err) = vimv::mapping::write(&path, &input_files, &output_files, format) {
+            exit_with_error(err.to_string());
+        }
+        return;
+    }
+
+    // In JSON mode we print the full plan before executing it.
+    for op in &plan.operations {
+        match op {
+            Operation::Delete(input_file) => {
+                print_json(&[("event", "plan"), ("op", "delete"), ("path", input_file)]);
+            }
+            Operation::Purge(input_file) => {
+                print_json(&[("event", "plan"), ("op", "purge"), ("path", input_file)]);
+            }
+            Operation::Rename(input_file, output_file) => {
+                print_json(&[("event", "plan"), ("op", "rename"), ("src", input_file), ("dst", output_file)]);
+            }
+            Operation::Copy(input_file, output_file) => {
+                print_json(&[("event", "plan"), ("op", "copy"), ("src", input_file), ("dst", output_file)]);
+            }
+            Operation::Link(input_file, output_file) => {
+                print_json(&[("event", "plan"), ("op", "link"), ("src", input_file), ("dst", output_file)]);
+            }
+            Operation::Swap(input_file, output_file) => {
+                print_json(&[("event", "plan"), ("op", "swap"), ("src", input_file), ("dst", output_file)]);
+            }
+            Operation::Create(path) => {
+                print_json(&[("event", "plan"), ("op", "create"), ("path", path)]);
+            }
+        }
+    }
+
+    // In dry-run mode we print the planned operations and exit without touching the file system.
+    if parser.found("dry-run") {
+        if !JSON_OUTPUT.load(Ordering::Relaxed) {
+            let preview: String = format_preview(&plan);
+            pager::page(&preview, false, use_pager);
+            if compare_overwrites {
+                eprint!("{}", format_overwrites(&plan));
+            }
+        }
+        return;
+    }
+
+    // Bail if there's nothing to do.
+    if plan.is_empty() {
+        return;
+    }
+
+    // In review mode we print a summary of the changes and ask for confirmation. The --yes flag
+    // answers for the user.
+    if parser.found("review") {
+        let operations = plan.logical_operations();
+        let mut review: String = operations.iter().map(format_review).collect();
+        if compare_overwrites && !JSON_OUTPUT.load(Ordering::Relaxed) {
+            review.push_str(&format_overwrites(&plan));
+        }
+        pager::page(&review, true, use_pager);
+        let prompt = format!("Apply {} operation(s)? [y/N] ", operations.len());
+        if !parser.found("yes") && !confirm(&prompt) {
+            let message = "aborted, no changes have been made".to_string();
+            exit_with_error(save_edited_buffer(message, &input_files, edited_buffer.as_ref()));
+        }
+    }
+
+    // Permanent deletion can't be undone so we ask for confirmation unless --force or --yes is
+    // set. Files marked with a '!' are listed and need confirmation even with --force as they
+    // bypass the trash without a flag. (The review prompt counts as confirmation.)
+    let delete_count = plan.operations.iter().filter(|op| matches!(op, Operation::Delete(_))).count();
+    let purges: Vec<&String> = plan
+        .operations
+        .iter()
+        .filter_map(|op| match op {
+            Operation::Purge(input_file) => Some(input_file),
+            _ => None,
+        })
+        .collect();
+    let reviewed = parser.found("yes") || parser.found("review");
+    let confirm_rm = parser.found("rm") && delete_count > 0 && !parser.found("force");
+    if !reviewed && (confirm_rm || !purges.is_empty()) {
+        if !purges.is_empty() {
+            eprintln!("These files will be deleted permanently:");
+            for input_file in &purges {
+                eprintln!("  {} {}", "!".red().bold(), input_file);
+            }
+        }
+        let count = purges.len() + if parser.found("rm") { delete_count } else { 0 };
+        let prompt = format!("Permanently delete {} file(s)? [y/N] ", count);
+        if !confirm(&prompt) {
+            let message = "aborted, no changes have been made".to_string();
+            exit_with_error(save_edited_buffer(message, &input_files, edited_buffer.as_ref()));
+        }
+    }
+
+    // Deletions get their own confirmation, independent of the review, if the --confirm-delete flag
+    // is set or more files are marked than the --delete-threshold allows. Permanent deletions have
+    // already been confirmed above.
+    let confirm_delete = parser.found("confirm-delete") || delete_count > delete_threshold;
+    if !parser.found("rm") && !parser.found("yes") && confirm_delete {
+        let deletions: Vec<&String> = plan
+            .operations
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Delete(input_file) => Some(input_file),
+                _ => None,
+            })
+            .collect();
+        if !deletions.is_empty() {
+            eprintln!("These files will be deleted:");
+            for input_file in &deletions {
+                eprintln!("  {} {}", delete_prefix.red().bold(), input_file);
+            }
+            let prompt = format!("Delete {} file(s)? [y/N] ", deletions.len());
+            if !confirm(&prompt) {
+                let message = "aborted, no changes have been made".to_string();
+                exit_with_error(save_edited_buffer(message, &input_files, edited_buffer.as_ref()));
+            }
+        }
+    }
+
+    // Open the log file, if specified, so we fail before touching the file system if it can't be
+    // written.
+    let log = if parser.found("log") {
+        Log::open(Path::new(&parser.value("log"))).unwrap_or_else(|err| exit_with_error(err.to_string()))
+    } else {
+        Log::disabled()
+    };
+
+    // Execute the plan. We haven't made any changes to the file system up to this point.
+    let exec_options = ExecOptions {
+        vcs,
+        strict_rename: parser.found("strict-rename"),
+        backup,
+        permanent_delete: parser.found("rm"),
+        archive_dir,
+        trash_dir,
+        preserve,
+        leave_symlink: parser.found("leave-symlink"),
+        keep_going: parser.found("keep-going"),
+        fsync: parser.found("fsync"),
+        jobs,
+        retries,
+        retry_delay,
+    };
+    let progress = progress_bar(&plan, &parser.value("progress"), quiet);
+    let journal = new_journal(&plan, &exec_options);
+    let (reporter, result) = execute_plan(&plan, &exec_options, journal, log, hook, quiet, progress);
+
+    // If the --print or --print0 flag has been set, print the operations that were completed,
+    // even if execution failed partway through.
+    if print_results {
+        print_results_list(&reporter.results, if parser.found("print0") { '\0' } else { '\n' });
+    }
+
+    if let Err(err) = result {
+        exit_with_error(save_edited_buffer(err.to_string(), &input_files, edited_buffer.as_ref()));
+    }
+
+    // A resumed buffer has now been applied in full.
+    if let Some(saved) = &resumed {
+        if let Err(err) = vimv::recovery::remove(&saved.buffer_file) {
+            eprintln!("warning: {}", err);
+        }
+    }
+}
+
+
+// Creates a journal for a new batch so the batch can be undone, and writes the plan to it before
+// anything is executed so the batch can be recovered if it's interrupted.
+fn new_journal(plan: &RenamePlan, exec_options: &ExecOptions) -> Journal {
+    let mut journal = Journal::new().unwrap_or_else(|err| {
+        eprintln!("warning: {}", err);
+        Journal::disabled()
+    });
+    journal.set_fsync(exec_options.fsync);
+    if let Err(err) = journal.write_plan(plan, exec_options.permanent_delete) {
+        eprintln!("warning: {}", err);
+    }
+    journal
+}
+
+
+// Parses the value of the --jobs option, the number of operations to run concurrently.
+fn parse_jobs(value: &str) -> usize {
+    match value.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => jobs,
+        _ => exit_with_error(format!("invalid number of jobs '{}', expected a positive integer", value)),
+    }
+}
+
+
+// Parses the values of the --retries and --retry-delay options, the number of times to retry a
+// transient failure and the delay in milliseconds before the first retry.
+fn parse_retries(retries: &str, delay: &str) -> (u32, std::time::Duration) {
+    let retries = retries.parse::<u32>().unwrap_or_else(|_| {
+        exit_with_error(format!("invalid number of retries '{}', expected a number", retries));
+    });
+    let delay = delay.parse::<u64>().unwrap_or_else(|_| {
+        exit_with_error(format!("invalid retry delay '{}', expected a number of milliseconds", delay));
+    });
+    (retries, std::time::Duration::from_millis(delay))
+}
+
+
+// Returns a progress bar to show in place of the per-operation output if the plan has more than
+// `threshold` operations or copies any files. Progress bars are only shown in a terminal and never
+// in quiet or JSON mode.
+fn progress_bar(plan: &RenamePlan, threshold: &str, quiet: bool) -> Option<ProgressBar> {
+    let threshold = threshold.parse::<usize>().unwrap_or_else(|_| {
+        exit_with_error(format!("invalid progress threshold '{}', expected a number", threshold));
+    });
+    if quiet || JSON_OUTPUT.load(Ordering::Relaxed) || !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let copies = plan.operations.iter().any(|op| matches!(op, Operation::Copy(..)));
+    if plan.operations.len() > threshold || copies {
+        return Some(ProgressBar::new(plan.operations.len()));
+    }
+    None
+}
+
+
+// Executes the plan, printing the operations unless `quiet` is true or a progress bar is shown,
+// and recording them in the journal, the log, and the history, then prints a summary of the run.
+// Returns the reporter, which lists the completed and failed operations, along with the result.
+fn execute_plan<'a>(
+    plan: &'a RenamePlan,
+    exec_options: &ExecOptions,
+    journal: Journal,
+    log: Log,
+    hook: Option<Vec<String>>,
+    quiet: bool,
+    progress: Option<ProgressBar>,
+) -> (CliReporter<'a>, vimv::Result<()>) {
+    let mut reporter = CliReporter {
+        quiet,
+        permanent_delete: exec_options.permanent_delete,
+        plan,
+        journal,
+        log,
+        hook,
+        progress,
+        width: column_width(&plan.operations),
+        copy_sources: HashMap::new(),
+        temp_sources: HashMap::new(),
+        archived: None,
+        trashed: None,
+        results: Vec::new(),
+        failures: Vec::new(),
+        tally: Tally::default(),
+    };
+    let started = Instant::now();
+    let result = vimv::execute(plan, exec_options, &mut reporter);
+    if let Some(progress) = &mut reporter.progress {
+        progress.finish();
+    }
+
+    // In quiet mode the summary is only printed if every operation succeeded. In keep-going mode
+    // it's followed by the list of failures.
+    if !JSON_OUTPUT.load(Ordering::Relaxed) && (!quiet || reporter.failures.is_empty()) {
+        eprintln!("{}", format_run_summary(&reporter, started.elapsed()));
+        if exec_options.keep_going {
+            for failure in &reporter.failures {
+                eprintln!("  {} {}", "✗".red().bold(), failure);
+            }
+        }
+    }
+
+    // Record the completed operations in the history, even if execution failed partway through.
+    if !reporter.results.is_empty() {
+        let (deleted, renamed): (Pairs, Pairs) =
+            reporter.results.iter().cloned().partition(|(_, output_file)| output_file.is_empty());
+        let deletions: Vec<String> = deleted.into_iter().map(|(input_file, _)| input_file).collect();
+        let recorded = std::env::current_dir()
+            .map_err(|err| vimv::Error::Operation(format!("cannot determine the working directory: {}", err)))
+            .and_then(|cwd| history::record(&cwd, reporter.journal.path(), &renamed, &deletions));
+        if let Err(err) = recorded {
+            eprintln!("warning: {}", err);
+        }
+    }
+
+    // The batch has finished unless it failed part-way through, in which case we keep its plan
+    // so it can be finished or rolled back later.
+    if result.is_ok() || reporter.journal.steps() == 0 {
+        reporter.journal.finish();
+    } else if reporter.journal.pending_path().is_some() && !JSON_OUTPUT.load(Ordering::Relaxed) {
+        eprintln!("note: the batch is incomplete, run `vimv recover` to finish it or roll it back");
+    }
+
+    (reporter, result)
+}
+
+
+// Runs the --validate-cmd command with the planned changes as a TSV mapping on standard input.
+// Returns an error describing the rejection, including the command's output, if it fails to run or
+// exits with a non-zero status.
+fn run_validator(words: &[String], mapping: &str) -> Result<(), String> {
+    let mut child = std::process::Command::new(&words[0])
+        .args(&words[1..])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("cannot run the --validate-cmd command '{}': {}", words[0], err))?;
+
+    // The mapping is written from a separate thread so a command that writes a lot of output
+    // before reading its input can't deadlock. A command that exits without reading all of its
+    // input isn't an error in itself.
+    let mut stdin = child.stdin.take();
+    let mapping = mapping.to_string();
+    let writer = std::thread::spawn(move || {
+        if let Some(stdin) = &mut stdin {
+            let _ = stdin.write_all(mapping.as_bytes());
+        }
+    });
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("cannot run the --validate-cmd command '{}': {}", words[0], err))?;
+    let _ = writer.join();
+
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut message = String::from_utf8_lossy(&output.stdout).to_string();
+    message.push_str(&String::from_utf8_lossy(&output.stderr));
+    match message.trim() {
+        "" => Err(format!("the changes were rejected by the --validate-cmd command ({})", output.status)),
+        message => Err(format!("the changes were rejected by the --validate-cmd command:\n{}", message)),
+    }
+}
+
+
+// Prints operations as they're executed and records them in the journal and log.
+struct CliReporter<'a> {
+    quiet: bool,
+    permanent_delete: bool,
+    plan: &'a RenamePlan,
+    journal: Journal,
+    log: Log,
+
+    // The words of the --exec command, run after each completed operation.
+    hook: Option<Vec<String>>,
+
+    // Replaces the per-operation output for large batches.
+    progress: Option<ProgressBar>,
+
+    // The width of the column of old filenames in the per-operation output.
+    width: usize,
+
+    // Maps temporary files created in copy or link mode to the files they were copied from.
+    copy_sources: HashMap<String, String>,
+
+    // Maps temporary files created in any mode to the original input files and the operations
+    // that moved them to the temporary files.
+    temp_sources: HashMap<String, (String, Operation)>,
+
+    // The archive path of the file deleted by the current operation, if it was archived rather
+    // than moved to the trash.
+    archived: Option<String>,
+
+    // The path inside the trash directory of the file deleted by the current operation, if it was
+    // moved into a trash directory set by the --trash-dir option.
+    trashed: Option<String>,
+
+    // The completed operations as (input, output) pairs, with detours via temporary files
+    // collapsed. The output is empty for deleted files.
+    results: Vec<(String, String)>,
+
+    // Descriptions of the operations that failed in keep-going mode.
+    failures: Vec<String>,
+
+    // Counts of the completed operations, with detours via temporary files collapsed.
+    tally: Tally,
+}
+
+
+// Counts the completed operations of each kind for the summary printed after execution.
+#[derive(Default)]
+struct Tally {
+    renamed: usize,
+    copied: usize,
+    linked: usize,
+    swapped: usize,
+    deleted: usize,
+    created_files: usize,
+    created_dirs: usize,
+}
+
+
+impl Tally {
+    fn count(&mut self, op: &Operation) {
+        match op {
+            Operation::Delete(..) | Operation::Purge(..) => self.deleted += 1,
+            Operation::Rename(..) => self.renamed += 1,
+            Operation::Copy(..) => self.copied += 1,
+            Operation::Link(..) => self.linked += 1,
+            Operation::Swap(..) => self.swapped += 1,
+            Operation::Create(path) if is_dir_path(path) => self.created_dirs += 1,
+            Operation::Create(_) => self.created_files += 1,
+        }
+    }
+}
+
+
+impl CliReporter<'_> {
+    fn record_result(&mut self, op: &Operation) {
+        match op {
+            Operation::Delete(input_file) | Operation::Purge(input_file) => {
+                self.tally.count(op);
+                self.results.push((input_file.to_string(), String::new()));
+            }
+            Operation::Rename(input_file, output_file)
+            | Operation::Copy(input_file, output_file)
+            | Operation::Link(input_file, output_file) => {
+                // A detour via a temporary file counts as the kind of operation that began it.
+                let (input_file, first_op) = self
+                    .temp_sources
+                    .remove(input_file)
+                    .unwrap_or_else(|| (input_file.to_string(), op.clone()));
+                if self.plan.is_temp_file(output_file) {
+                    self.temp_sources.insert(output_file.to_string(), (input_file, first_op));
+                } else {
+                    self.tally.count(&first_op);
+                    self.results.push((input_file, output_file.to_string()));
+                }
+            }
+            Operation::Create(_) => self.tally.count(op),
+            Operation::Swap(input_file, output_file) => {
+                self.tally.count(op);
+                self.results.push((input_file.to_string(), output_file.to_string()));
+                self.results.push((output_file.to_string(), input_file.to_string()));
+            }
+        }
+    }
+
+    // Runs the --exec command for a completed operation, replacing '{src}' and '{dst}' in each
+    // word. For deletions, a word that is exactly '{dst}' is dropped. The command's standard output
+    // is redirected to standard error so it can't mix with --json or --print output.
+    fn run_hook(&self, input_file: &str, output_file: &str) {
+        let Some(words) = &self.hook else {
+            return;
+        };
+        let args: Vec<String> = words[1..]
+            .iter()
+            .filter(|word| !(output_file.is_empty() && word.as_str() == "{dst}"))
+            .map(|word| word.replace("{src}", input_file).replace("{dst}", output_file))
+            .collect();
+        let status = std::process::Command::new(&words[0])
+            .args(&args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::io::stderr())
+            .status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("warning: the --exec command failed for '{}' ({})", input_file, status),
+            Err(err) => eprintln!("warning: cannot run the --exec command '{}': {}", words[0], err),
+        }
+    }
+}
+
+
+impl Reporter for CliReporter<'_> {
+    fn started(&mut self, op: &Operation) {
+        self.journal.record_start(op);
+    }
+
+    fn before(&mut self, op: &Operation) {
+        if let Some(progress) = &mut self.progress {
+            progress.start(operation_name(op));
+        } else if !self.quiet {
+            print_operation(op, self.width);
+        }
+    }
+
+    fn created_dir(&mut self, dir: &Path) {
+        self.tally.created_dirs += 1;
+        print_json(&[("event", "mkdir"), ("path", &dir.to_string_lossy())]);
+        self.journal.record_create_dir(dir);
+        self.log.record_create_dir(dir);
+    }
+
+    fn backed_up(&mut self, path: &str, backup: &str) {
+        if !self.quiet && self.progress.is_none() {
+            print_backup(path, backup, self.width);
+        }
+        print_json(&[("event", "backup"), ("path", path), ("backup", backup)]);
+        self.journal.record_rename(path, backup);
+        self.log.record_backup(path, backup);
+    }
+
+    fn archived(&mut self, path: &str, archive: &str) {
+        if !self.quiet && self.progress.is_none() {
+            print_archive(path, archive, self.width);
+        }
+        print_json(&[("event", "archive"), ("path", path), ("archive", archive)]);
+        self.archived = Some(archive.to_string());
+    }
+
+    fn trashed(&mut self, path: &str, trashed: &str) {
+        print_json(&[("event", "trash"), ("path", path), ("trash", trashed)]);
+        self.trashed = Some(trashed.to_string());
+    }
+
+    fn created_symlink(&mut self, path: &str, target: &Path) {
+        if !self.quiet && self.progress.is_none() {
+            print_symlink(path, &target.to_string_lossy(), self.width);
+        }
+        print_json(&[("event", "symlink"), ("path", path), ("target", &target.to_string_lossy())]);
+        self.journal.record_symlink(path);
+        self.log.record_symlink(path, target);
+    }
+
+    fn failed(&mut self, op: &Operation, err: &vimv::Error) {
+        let description = match op {
+            Operation::Delete(input_file) => format!("delete '{}'", input_file),
+            Operation::Purge(input_file) => format!("permanently delete '{}'", input_file),
+            Operation::Rename(input_file, output_file) => format!("rename '{}' to '{}'", input_file, output_file),
+            Operation::Copy(input_file, output_file) => format!("copy '{}' to '{}'", input_file, output_file),
+            Operation::Link(input_file, output_file) => format!("link '{}' to '{}'", input_file, output_file),
+            Operation::Swap(input_file, output_file) => format!("swap '{}' and '{}'", input_file, output_file),
+            Operation::Create(path) => format!("create '{}'", path),
+        };
+        let message = format!("{}: {}", description, err);
+        print_json(&[("event", "error"), ("message", &message)]);
+        if !JSON_OUTPUT.load(Ordering::Relaxed) {
+            if let Some(progress) = &mut self.progress {
+                progress.clear();
+            }
+            eprintln!("error: {}", message);
+        }
+        if let Some(progress) = &mut self.progress {
+            progress.inc();
+        }
+        self.failures.push(message);
+    }
+
+    // We record the logical result of each operation in the journal -- in copy mode, a copy via a
+    // temporary file is recorded as a single copy to the final destination. Hard links are
+    // recorded as copies as undoing either means removing the new file. The log records every
+    // operation as executed, including detours via temporary files.
+    fn after(&mut self, op: &Operation, overwrote: bool) {
+        if let Some(progress) = &mut self.progress {
+            progress.inc();
+        }
+        let completed = self.results.len();
+        self.record_result(op);
+        self.journal.record_step(op);
+        match op {
+            Operation::Delete(input_file) => match &self.archived {
+                Some(archive) => self.log.record_archive(input_file, archive),
+                None => self.log.record_delete(input_file, self.permanent_delete),
+            },
+            Operation::Purge(input_file) => self.log.record_delete(input_file, true),
+            Operation::Rename(input_file, output_file) => self.log.record_rename(input_file, output_file),
+            Operation::Copy(input_file, output_file) => self.log.record_copy(input_file, output_file),
+            Operation::Link(input_file, output_file) => self.log.record_link(input_file, output_file),
+            Operation::Swap(input_file, output_file) => self.log.record_swap(input_file, output_file),
+            Operation::Create(path) if is_dir_path(path) => self.log.record_create_dir(Path::new(path)),
+            Operation::Create(path) => self.log.record_create(path),
+        }
+        match op {
+            Operation::Delete(input_file) => {
+                print_json(&[("event", "delete"), ("path", input_file)]);
+                if let Some(archive) = self.archived.take() {
+                    // Undoing an archived deletion moves the file back.
+                    self.journal.record_rename(input_file, &archive);
+                } else if let Some(trashed) = self.trashed.take() {
+                    self.journal.record_trash(input_file, &trashed);
+                } else if self.permanent_delete {
+                    self.journal.record_purge(input_file);
+                } else {
+                    self.journal.record_delete(input_file);
+                    if let Err(err) = manifest::record(input_file) {
+                        eprintln!("warning: {}", err);
+                    }
+                }
+            }
+            Operation::Purge(input_file) => {
+                print_json(&[("event", "purge"), ("path", input_file)]);
+                self.journal.record_purge(input_file);
+            }
+            Operation::Copy(input_file, output_file) | Operation::Link(input_file, output_file) => {
+                let event = if matches!(op, Operation::Link(..)) { "link" } else { "copy" };
+                print_json(&[("event", event), ("src", input_file), ("dst", output_file)]);
+                if self.plan.is_temp_file(output_file) {
+                    self.copy_sources.insert(output_file.to_string(), input_file.to_string());
+                } else if overwrote {
+                    self.journal.record_overwrite(output_file);
+                } else {
+                    self.journal.record_copy(input_file, output_file);
+                }
+            }
+            Operation::Rename(input_file, output_file) => {
+                print_json(&[("event", "rename"), ("src", input_file), ("dst", output_file)]);
+                if let Some(copy_source) = self.copy_sources.get(input_file) {
+                    if overwrote {
+                        self.journal.record_overwrite(output_file);
+                    } else {
+                        self.journal.record_copy(copy_source, output_file);
+                    }
+                } else {
+                    if overwrote {
+                        self.journal.record_overwrite(output_file);
+                    }
+                    self.journal.record_rename(input_file, output_file);
+                }
+            }
+            Operation::Swap(input_file, output_file) => {
+                print_json(&[("event", "swap"), ("src", input_file), ("dst", output_file)]);
+                self.journal.record_swap(input_file, output_file);
+            }
+            Operation::Create(path) if is_dir_path(path) => {
+                print_json(&[("event", "mkdir"), ("path", path)]);
+                self.journal.record_create_dir(Path::new(path));
+            }
+            Operation::Create(path) => {
+                print_json(&[("event", "create"), ("path", path)]);
+                self.journal.record_create(path);
+            }
+        }
+
+        // The hook runs once the operation has been journaled, and only when a file reaches its
+        // final name, not for the steps of a detour via a temporary file.
+        for (input_file, output_file) in &self.results[completed..] {
+            self.run_hook(input_file, output_file);
+        }
+    }
+}
+
+
+// Reverse recorded batches of operations: the most recent `steps` batches, newest first, or the
+// batch recorded for the specified session in the history. Every batch is checked against the
+// file system before anything is changed.
+fn undo(args: &[String], steps: &str, dry_run: bool, quiet: bool) {
+    let journal_files = if args.is_empty() {
+        let steps = match steps.parse::<usize>() {
+            Ok(steps) if steps > 0 => steps,
+            _ => exit_with_error(format!("invalid number of steps '{}', expected a positive integer", steps)),
+        };
+        let journals = journal::journals();
+        if journals.is_empty() {
+            exit_with_error("no journal found, there is nothing to undo");
+        }
+        if steps > journals.len() {
+            exit_with_error(format!("cannot undo {} batches, only {} can be undone", steps, journals.len()));
+        }
+        journals.into_iter().rev().take(steps).collect()
+    } else {
+        if args.len() > 1 {
+            exit_with_error("expected a single session number");
+        }
+        vec![session_journal(&args[0])]
+    };
+
+    let batches: Vec<(PathBuf, Vec<Entry>)> = journal_files
+        .into_iter()
+        .map(|journal_file| {
+            let entries = journal::read_journal(&journal_file).unwrap_or_else(|err| {
+                exit_with_error(err.to_string());
+            });
+            (journal_file, entries)
+        })
+        .collect();
+
+    if let Err(message) = check_undo(&batches) {
+        exit_with_error(format!("cannot undo, the file system has changed: {}", message));
+    }
+    for (journal_file, entries) in &batches {
+        undo_batch(journal_file, entries, dry_run, quiet);
+    }
+}
+
+
+// Returns the journal file for the session with the specified number in the history.
+fn session_journal(arg: &str) -> PathBuf {
+    let sessions = history::read().unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+    let number = match arg.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= sessions.len() => number,
+        _ => exit_with_error(format!("'{}' is not the number of a recorded session", arg)),
+    };
+    match &sessions[number - 1].journal {
+        Some(journal_file) if journal_file.exists() => journal_file.clone(),
+        Some(_) => exit_with_error(format!("session {} has already been undone", number)),
+        None => exit_with_error(format!("session {} has no journal and cannot be undone", number)),
+    }
+}
+
+
+// Checks that the batches can be undone in order by simulating their reversal against the current
+// state of the file system. Each renamed file must still exist at its new path and its original
+// path must be free at the point it's renamed back. Returns a description of the first problem.
+fn check_undo(batches: &[(PathBuf, Vec<Entry>)]) -> Result<(), String> {
+    // Paths whose existence has changed in the simulation so far.
+    let mut simulated: HashMap<PathBuf, bool> = HashMap::new();
+    let exists = |simulated: &HashMap<PathBuf, bool>, path: &Path| {
+        simulated.get(path).copied().unwrap_or_else(|| path.symlink_metadata().is_ok())
+    };
+    for (_, entries) in batches {
+        for entry in entries.iter().rev() {
+            match entry {
+                Entry::Rename(input_file, output_file) => {
+                    if !exists(&simulated, output_file) {
+                        return Err(format!("'{}' no longer exists", output_file.display()));
+                    }
+                    if exists(&simulated, input_file) {
+                        return Err(format!("'{}' already exists", input_file.display()));
+                    }
+                    simulated.insert(output_file.clone(), false);
+                    simulated.insert(input_file.clone(), true);
+                }
+                Entry::Delete(input_file) => {
+                    if exists(&simulated, input_file) {
+                        return Err(format!("'{}' already exists", input_file.display()));
+                    }
+                    simulated.insert(input_file.clone(), true);
+                }
+                Entry::Trash(input_file, trashed) => {
+                    if !exists(&simulated, trashed) {
+                        return Err(format!("'{}' is no longer in the trash", trashed.display()));
+                    }
+                    if exists(&simulated, input_file) {
+                        return Err(format!("'{}' already exists", input_file.display()));
+                    }
+                    simulated.insert(trashed.clone(), false);
+                    simulated.insert(input_file.clone(), true);
+                }
+                Entry::Swap(a, b) => {
+                    for path in [a, b] {
+                        if !exists(&simulated, path) {
+                            return Err(format!("'{}' no longer exists", path.display()));
+                        }
+                    }
+                }
+                Entry::Copy(_, path) | Entry::Symlink(path) | Entry::Create(path) => {
+                    simulated.insert(path.clone(), false);
+                }
+                Entry::Purge(_) | Entry::Overwrite(_) | Entry::CreateDir(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// Reverse a single batch of operations recorded in a journal, then remove the journal file.
+fn undo_batch(journal_file: &Path, entries: &[Entry], dry_run: bool, quiet: bool) {
+    let names: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Rename(_, path) | Entry::Swap(_, path) => Some(path.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect();
+    let width = name_width(names.iter().map(|name| name.as_str()));
+    for entry in entries.iter().rev() {
+        match entry {
+            Entry::Rename(input_file, output_file) => {
+                if !output_file.exists() && output_file.symlink_metadata().is_err() {
+                    exit_with_error(format!("cannot undo the rename, '{}' no longer exists", output_file.display()));
+                }
+                if input_file.exists() {
+                    exit_with_error(format!("cannot undo the rename, '{}' already exists", input_file.display()));
+                }
+                let (output_file, input_file) = (output_file.to_string_lossy(), input_file.to_string_lossy());
+                let op = Operation::Rename(output_file.to_string(), input_file.to_string());
+                if !quiet || dry_run {
+                    print_operation(&op, width);
+                }
+                if !dry_run {
+                    if let Err(err) = vimv::execute_operation(&op, &ExecOptions::default(), &mut vimv::NullReporter) {
+                        exit_with_error(err.to_string());
+                    }
+                }
+            }
+            Entry::Swap(a, b) => {
+                for path in [a, b] {
+                    if path.symlink_metadata().is_err() {
+                        exit_with_error(format!("cannot undo the swap, '{}' no longer exists", path.display()));
+                    }
+                }
+                let op = Operation::Swap(b.to_string_lossy().to_string(), a.to_string_lossy().to_string());
+                if !quiet || dry_run {
+                    print_operation(&op, width);
+                }
+                if !dry_run {
+                    if let Err(err) = vimv::execute_operation(&op, &ExecOptions::default(), &mut vimv::NullReporter) {
+                        exit_with_error(err.to_string());
+                    }
+                }
+            }
+            Entry::Delete(input_file) => {
+                if !quiet || dry_run {
+                    print_restore(&input_file.to_string_lossy());
+                }
+                if !dry_run {
+                    match vimv::restore_from_trash(input_file) {
+                        Ok(true) => {
+                            if let Err(err) = manifest::remove(input_file) {
+                                eprintln!("warning: {}", err);
+                            }
+                        }
+                        Ok(false) => {
+                            eprintln!("warning: cannot find the file '{}' in the trash", input_file.display());
+                        }
+                        Err(err) => exit_with_error(err.to_string()),
+                    }
+                }
+            }
+            Entry::Trash(input_file, trashed) => {
+                if trashed.symlink_metadata().is_err() {
+                    exit_with_error(format!("cannot restore the file, '{}' no longer exists", trashed.display()));
+                }
+                if input_file.symlink_metadata().is_ok() {
+                    exit_with_error(format!("cannot restore the file, '{}' already exists", input_file.display()));
+                }
+                if !quiet || dry_run {
+                    print_restore(&input_file.to_string_lossy());
+                }
+                if !dry_run {
+                    if let Err(err) = vimv::restore_from_trash_dir(input_file, trashed) {
+                        exit_with_error(err.to_string());
+                    }
+                }
+            }
+            Entry::Copy(input_file, output_file) => {
+                if output_file.symlink_metadata().is_err() {
+                    eprintln!(
+                        "warning: the copy of '{}' at '{}' no longer exists",
+                        input_file.display(),
+                        output_file.display()
+                    );
+                    continue;
+                }
+                if !quiet || dry_run {
+                    print_delete(&output_file.to_string_lossy());
+                }
+                if !dry_run {
+                    if let Err(err) = vimv::remove_recursive(output_file) {
+                        exit_with_error(format!("cannot remove the file '{}': {}", output_file.display(), err));
+                    }
+                }
+            }
+            Entry::Purge(input_file) => {
+                eprintln!(
+                    "warning: '{}' was permanently deleted and cannot be restored",
+                    input_file.display()
+                );
+            }
+            Entry::Overwrite(output_file) => {
+                eprintln!(
+                    "warning: the original content of '{}' was overwritten and cannot be restored",
+                    output_file.display()
+                );
+            }
+            Entry::Symlink(path) => {
+                if !path.is_symlink() {
+                    eprintln!("warning: the symlink '{}' no longer exists", path.display());
+                    continue;
+                }
+                if !quiet || dry_run {
+                    print_delete(&path.to_string_lossy());
+                }
+                if !dry_run {
+                    if let Err(err) = fs::remove_file(path) {
+                        exit_with_error(format!("cannot remove the symlink '{}': {}", path.display(), err));
+                    }
+                }
+            }
+            Entry::CreateDir(dir) => {
+                if !dry_run {
+                    let _ = fs::remove_dir(dir);
+                }
+            }
+            Entry::Create(path) => {
+                // A new file that has since been written to is left alone.
+                match path.symlink_metadata() {
+                    Err(_) => {
+                        eprintln!("warning: the new file '{}' no longer exists", path.display());
+                        continue;
+                    }
+                    Ok(metadata) if metadata.len() > 0 => {
+                        eprintln!("warning: the new file '{}' is no longer empty, leaving it in place", path.display());
+                        continue;
+                    }
+                    Ok(_) => {}
+                }
+                if !quiet || dry_run {
+                    print_delete(&path.to_string_lossy());
+                }
+                if !dry_run {
+                    if let Err(err) = fs::remove_file(path) {
+                        exit_with_error(format!("cannot remove the file '{}': {}", path.display(), err));
+                    }
+                }
+            }
+        }
+    }
+
+    if !dry_run {
+        if let Err(err) = fs::remove_file(journal_file) {
+            exit_with_error(format!("failed to remove the journal file '{}': {}", journal_file.display(), err));
+        }
+        let pending_file = journal_file.with_extension("pending");
+        if pending_file.exists() {
+            if let Err(err) = fs::remove_file(&pending_file) {
+                eprintln!("warning: failed to remove the journal file '{}': {}", pending_file.display(), err);
+            }
+        }
+    }
+}
+
+
+// List the batches that were interrupted before they finished, or finish or roll back the most
+// recent one.
+fn recover(finish: bool, rollback: bool, dry_run: bool, quiet: bool) {
+    if finish && rollback {
+        exit_with_error("the --finish and --rollback flags cannot be combined");
+    }
+    let pending_files = journal::pending_batches();
+    let batches: Vec<PendingBatch> = pending_files
+        .iter()
+        .map(|pending_file| {
+            journal::read_pending(pending_file).unwrap_or_else(|err| {
+                exit_with_error(err.to_string());
+            })
+        })
+        .collect();
+
+    if !finish && !rollback {
+        if batches.is_empty() {
+            println!("No interrupted batches found.");
+        }
+        for (index, batch) in batches.iter().enumerate() {
+            print_pending_batch(index + 1, batch);
+        }
+        return;
+    }
+
+    let mut batch = match batches.into_iter().last() {
+        Some(batch) => batch,
+        None => exit_with_error("no interrupted batch found, there is nothing to recover"),
+    };
+    if let Err(err) = std::env::set_current_dir(&batch.cwd) {
+        exit_with_error(format!("cannot change to the directory '{}': {}", batch.cwd.display(), err));
+    }
+
+    // The operations in progress when the batch was interrupted may have completed without being
+    // recorded.
+    let interrupted = interrupted_steps(&batch);
+    for &index in &interrupted {
+        batch.completed[index] = true;
+    }
+
+    if finish {
+        finish_batch(&batch, &interrupted, dry_run, quiet);
+    } else {
+        rollback_batch(&batch, &interrupted, dry_run, quiet);
+    }
+}
+
+
+// Returns the indices of the operations that were in progress when the batch was interrupted and
+// that the file system shows completed. Every operation is recorded as it starts, so with several
+// jobs any operation recorded as started but not as completed may have finished. Batches recorded
+// by older versions of Vimv have no start records; they ran one operation at a time, so only the
+// first operation after the last recorded one can have completed without being recorded.
+fn interrupted_steps(batch: &PendingBatch) -> Vec<usize> {
+    let in_progress: Vec<usize> = if batch.started.iter().any(|&started| started) {
+        (0..batch.completed.len()).filter(|&index| batch.started[index] && !batch.completed[index]).collect()
+    } else {
+        let index = batch.completed.iter().rposition(|&done| done).map_or(0, |index| index + 1);
+        (index..batch.completed.len()).take(1).collect()
+    };
+    let exists = |path: &str| batch.cwd.join(path).symlink_metadata().is_ok();
+    let is_input = |path: &str| batch.plan.operations.iter().any(|op| operation_inputs(op).contains(&path));
+    let is_complete = |op: &Operation| match op {
+        Operation::Delete(input_file) | Operation::Purge(input_file) => !exists(input_file),
+        Operation::Rename(input_file, output_file) => !exists(input_file) && exists(output_file),
+        Operation::Copy(_, output_file) | Operation::Link(_, output_file) => {
+            exists(output_file) && !batch.plan.is_overwrite(output_file) && !is_input(output_file)
+        }
+        // A swap leaves both files in place so there's no way to tell if it completed.
+        Operation::Swap(..) => false,
+        Operation::Create(path) => exists(path),
+    };
+    in_progress.into_iter().filter(|&index| is_complete(&batch.plan.operations[index])).collect()
+}
+
+
+// Execute the pending operations of an interrupted batch, recording them in its journal.
+fn finish_batch(batch: &PendingBatch, interrupted: &[usize], dry_run: bool, quiet: bool) {
+    let plan = RenamePlan {
+        operations: batch
+            .plan
+            .operations
+            .iter()
+            .zip(&batch.completed)
+            .filter(|(_, &done)| !done)
+            .map(|(op, _)| op.clone())
+            .collect(),
+        temp_files: batch.plan.temp_files.clone(),
+        overwrites: batch.plan.overwrites.clone(),
+        ..RenamePlan::default()
+    };
+    if dry_run {
+        let preview: String = format_preview(&plan);
+        pager::page(&preview, false, true);
+        return;
+    }
+
+    let inputs: Vec<String> =
+        plan.operations.iter().flat_map(operation_inputs).map(|input_file| input_file.to_string()).collect();
+    let _lock = vimv::lock::acquire(&inputs, false).unwrap_or_else(|err| exit_with_error(err.to_string()));
+
+    let mut journal = Journal::resume(batch).unwrap_or_else(|err| exit_with_error(err.to_string()));
+    for &index in interrupted {
+        let op = &batch.plan.operations[index];
+        journal.record_step(op);
+        match op {
+            Operation::Delete(input_file) if batch.permanent_delete => journal.record_purge(input_file),
+            Operation::Delete(input_file) => journal.record_delete(input_file),
+            Operation::Purge(input_file) => journal.record_purge(input_file),
+            Operation::Rename(input_file, output_file) => journal.record_rename(input_file, output_file),
+            Operation::Copy(input_file, output_file) | Operation::Link(input_file, output_file) => {
+                journal.record_copy(input_file, output_file)
+            }
+            Operation::Swap(input_file, output_file) => journal.record_swap(input_file, output_file),
+            Operation::Create(path) if is_dir_path(path) => journal.record_create_dir(Path::new(path)),
+            Operation::Create(path) => journal.record_create(path),
+        }
+    }
+    let exec_options = ExecOptions { permanent_delete: batch.permanent_delete, ..ExecOptions::default() };
+    let (_, result) = execute_plan(&plan, &exec_options, journal, Log::disabled(), None, quiet, None);
+    if let Err(err) = result {
+        exit_with_error(err.to_string());
+    }
+}
+
+
+// Returns the existing files an operation reads, renames, or deletes.
+fn operation_inputs(op: &Operation) -> Vec<&str> {
+    match op {
+        Operation::Delete(input_file)
+        | Operation::Purge(input_file)
+        | Operation::Rename(input_file, _)
+        | Operation::Copy(input_file, _)
+        | Operation::Link(input_file, _) => vec![input_file],
+        Operation::Swap(a, b) => vec![a, b],
+        Operation::Create(_) => Vec::new(),
+    }
+}
+
+
+// Returns the filename an operation creates or acts on first, e.g. for the progress bar.
+fn operation_name(op: &Operation) -> &str {
+    match op {
+        Operation::Create(path) => path,
+        _ => operation_inputs(op)[0],
+    }
+}
+
+
+// Returns true if a filename to create names a directory, i.e. ends with a path separator.
+fn is_dir_path(path: &str) -> bool {
+    path.ends_with(['/', std::path::MAIN_SEPARATOR])
+}
+
+
+// Reverse the completed operations of an interrupted batch using its journal.
+fn rollback_batch(batch: &PendingBatch, interrupted: &[usize], dry_run: bool, quiet: bool) {
+    let mut entries =
+        journal::read_journal(&batch.journal_file).unwrap_or_else(|err| exit_with_error(err.to_string()));
+    for &index in interrupted {
+        let entry = match &batch.plan.operations[index] {
+            Operation::Delete(input_file) if batch.permanent_delete => Entry::Purge(PathBuf::from(input_file)),
+            Operation::Delete(input_file) => Entry::Delete(PathBuf::from(input_file)),
+            Operation::Purge(input_file) => Entry::Purge(PathBuf::from(input_file)),
+            Operation::Rename(input_file, output_file) => {
+                Entry::Rename(PathBuf::from(input_file), PathBuf::from(output_file))
+            }
+            Operation::Copy(input_file, output_file) | Operation::Link(input_file, output_file) => {
+                Entry::Copy(PathBuf::from(input_file), PathBuf::from(output_file))
+            }
+            Operation::Swap(input_file, output_file) => {
+                Entry::Swap(PathBuf::from(input_file), PathBuf::from(output_file))
+            }
+            Operation::Create(path) if is_dir_path(path) => Entry::CreateDir(PathBuf::from(path)),
+            Operation::Create(path) => Entry::Create(PathBuf::from(path)),
+        };
+        entries.push(entry);
+    }
+
+    let batches = vec![(batch.journal_file.clone(), entries)];
+    if let Err(message) = check_undo(&batches) {
+        exit_with_error(format!("cannot roll back, the file system has changed: {}", message));
+    }
+    undo_batch(&batch.journal_file, &batches[0].1, dry_run, quiet);
+}
+
+
+// List the files moved to the trash by previous runs or restore the selected files to their
+// original paths. Files can be selected by number or by original path.
+fn restore(args: &[String], all: bool, dry_run: bool, quiet: bool) {
+    let trashed_files = manifest::read().unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+
+    if args.is_empty() && !all {
+        if trashed_files.is_empty() {
+            println!("No files have been moved to the trash by Vimv.");
+        }
+        for (index, file) in trashed_files.iter().enumerate() {
+            print_trashed_file(index + 1, file);
+        }
+        return;
+    }
+
+    // Resolve the arguments to entries in the manifest before touching the file system.
+    let mut selected: Vec<&TrashedFile> = Vec::new();
+    if all {
+        selected.extend(trashed_files.iter());
+    }
+    for arg in args {
+        let file = match arg.parse::<usize>() {
+            Ok(number) if number >= 1 && number <= trashed_files.len() => &trashed_files[number - 1],
+            _ => manifest::find(&trashed_files, arg).unwrap_or_else(|| {
+                exit_with_error(format!("'{}' is not in the list of files moved to the trash by Vimv", arg));
+            }),
+        };
+        if !selected.contains(&file) {
+            selected.push(file);
+        }
+    }
+
+    for file in selected {
+        if file.path.symlink_metadata().is_ok() {
+            exit_with_error(format!("cannot restore the file, '{}' already exists", file.path.display()));
+        }
+        if !quiet || dry_run {
+            print_restore(&file.path.to_string_lossy());
+        }
+        if !dry_run {
+            match vimv::restore_from_trash(&file.path) {
+                Ok(true) => {}
+                Ok(false) => {
+                    eprintln!("warning: cannot find the file '{}' in the trash", file.path.display());
+                }
+                Err(err) => exit_with_error(err.to_string()),
+            }
+            if let Err(err) = manifest::remove(&file.path) {
+                exit_with_error(err.to_string());
+            }
+        }
+    }
+}
+
+
+// Execute a mapping saved with --save-plan. The mapping is validated against the current state of
+// the file system and planned exactly as if it had been entered in the editor.
+fn apply(cmd_parser: &ArgParser) {
+    if cmd_parser.args.len() != 1 {
+        exit_with_error("expected a single plan file");
+    }
+    let (input_files, output_files) = vimv::mapping::read(Path::new(&cmd_parser.args[0])).unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+    if input_files.is_empty() {
+        return;
+    }
+    if let Err(err) = vimv::validate_inputs(&input_files) {
+        exit_with_error(err.to_string());
+    }
+
+    let dry_run = cmd_parser.found("dry-run");
+    let _lock = if dry_run {
+        None
+    } else {
+        match vimv::lock::acquire(&input_files, false) {
+            Ok(lock) => Some(lock),
+            Err(err) => exit_with_error(err.to_string()),
+        }
+    };
+
+    let plan_options = PlanOptions {
+        force: cmd_parser.found("force"),
+        check_windows_names: cfg!(windows),
+        check_filesystems: true,
+        ..PlanOptions::default()
+    };
+    let plan = vimv::plan(&input_files, &output_files, &plan_options).unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+
+    if dry_run {
+        let preview: String = format_preview(&plan);
+        pager::page(&preview, false, true);
+        return;
+    }
+    if plan.is_empty() {
+        return;
+    }
+
+    // As in the main command, permanent deletion requires confirmation unless --force or --yes is
+    // set.
+    let delete_count = plan.operations.iter().filter(|op| matches!(op, Operation::Delete(_))).count();
+    if cmd_parser.found("rm") && delete_count > 0 && !cmd_parser.found("force") && !cmd_parser.found("yes") {
+        let prompt = format!("Permanently delete {} file(s)? [y/N] ", delete_count);
+        if !confirm(&prompt) {
+            exit_with_error("aborted, no changes have been made");
+        }
+    }
+
+    let log = if cmd_parser.found("log") {
+        Log::open(Path::new(&cmd_parser.value("log"))).unwrap_or_else(|err| exit_with_error(err.to_string()))
+    } else {
+        Log::disabled()
+    };
+    let (retries, retry_delay) = parse_retries(&cmd_parser.value("retries"), &cmd_parser.value("retry-delay"));
+    let exec_options = ExecOptions {
+        permanent_delete: cmd_parser.found("rm"),
+        keep_going: cmd_parser.found("keep-going"),
+        fsync: cmd_parser.found("fsync"),
+        jobs: parse_jobs(&cmd_parser.value("jobs")),
+        retries,
+        retry_delay,
+        ..ExecOptions::default()
+    };
+    let quiet = cmd_parser.found("quiet");
+    let progress = progress_bar(&plan, &cmd_parser.value("progress"), quiet);
+    let journal = new_journal(&plan, &exec_options);
+    let (_, result) = execute_plan(&plan, &exec_options, journal, log, None, quiet, progress);
+    if let Err(err) = result {
+        exit_with_error(err.to_string());
+    }
+}
+
+
+// Print the completion script for the specified shell.
+fn print_completions(args: &[String]) {
+    if args.len() != 1 {
+        exit_with_error(format!("expected a single shell: {}", completions::SHELLS.join(", ")));
+    }
+    let commands = [
+        ("apply", APPLY_HELPTEXT),
+        ("completions", COMPLETIONS_HELPTEXT),
+        ("history", HISTORY_HELPTEXT),
+        ("recover", RECOVER_HELPTEXT),
+        ("restore", RESTORE_HELPTEXT),
+        ("undo", UNDO_HELPTEXT),
+    ];
+    match completions::generate(&args[0], HELPTEXT, &commands) {
+        Some(script) => print!("{}", script),
+        None => exit_with_error(format!(
+            "unsupported shell '{}', expected one of: {}",
+            args[0],
+            completions::SHELLS.join(", ")
+        )),
+    }
+}
+
+
+// List the sessions recorded in the history or display the selected session.
+fn show_history(args: &[String], use_pager: bool) {
+    let sessions = history::read().unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+
+    if args.is_empty() {
+        if sessions.is_empty() {
+            println!("No sessions have been recorded.");
+        }
+        let text: String = sessions
+            .iter()
+            .enumerate()
+            .map(|(index, session)| format_session_summary(index + 1, session))
+            .collect();
+        pager::page(&text, false, use_pager);
+        return;
+    }
+
+    if args.len() > 1 {
+        exit_with_error("expected a single session number");
+    }
+    let number = match args[0].parse::<usize>() {
+        Ok(number) if number >= 1 && number <= sessions.len() => number,
+        _ => exit_with_error(format!("'{}' is not the number of a recorded session", args[0])),
+    };
+    let session = &sessions[number - 1];
+    let mut text = format_session_summary(number, session);
+    let width = name_width(session.mapping.iter().map(|(input_file, _)| input_file.as_str()));
+    for (input_file, output_file) in &session.mapping {
+        text.push_str(&format_columns("Renamed", "→", input_file, output_file, width));
+    }
+    for input_file in &session.deletions {
+        text.push_str(&format!("{} {}\n", format_verb("Deleted"), input_file));
+    }
+    pager::page(&text, false, use_pager);
+}
+
+
+// An edited buffer, kept so it can be saved if the edits fail validation or aren't applied.
+struct EditedBuffer {
+    text: String,
+    format: BufferFormat,
+    escaped: bool,
+}
+
+
+// Returns the message for an error in the plan, pointing to the lines of the edited buffer that
+// caused it if possible, e.g. "line 37 and line 122 both name 'x.txt'".
+fn locate_plan_error(
+    err: &vimv::Error,
+    input_files: &[String],
+    output_files: &[String],
+    buffer: Option<&EditedBuffer>,
+) -> String {
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => return err.to_string(),
+    };
+    let numbers = vimv::buffer::line_numbers(input_files, &buffer.text, buffer.format, buffer.escaped);
+
+    // Returns the line numbers and output filenames of the lines naming `name`. The planner
+    // compares filenames in NFC form, and in lowercase if `fold_case` is true.
+    let lines_naming = |name: &str, fold_case: bool| -> Vec<(usize, &String)> {
+        output_files
+            .iter()
+            .zip(&numbers)
+            .filter(|(output_file, _)| !output_file.starts_with(vimv::buffer::DELETE_PREFIX))
+            .filter(|(output_file, _)| {
+                let output_file = vimv::buffer::copy_destination(output_file).unwrap_or(output_file);
+                let normalized: String = output_file.nfc().collect();
+                if fold_case { normalized.to_lowercase() == name } else { normalized == name }
+            })
+            .filter_map(|(output_file, number)| number.map(|number| (number, output_file)))
+            .collect()
+    };
+    let line_of_input = |input_file: &str| input_files.iter().position(|s| s == input_file).and_then(|i| numbers[i]);
+
+    let line = match err {
+        vimv::Error::Multiple(problems) => {
+            let mut message = format!("found {} problems with the changes:", problems.len());
+            for problem in problems {
+                message.push_str("\n  ");
+                message.push_str(&locate_plan_error(problem, input_files, output_files, Some(buffer)));
+            }
+            return message;
+        }
+        vimv::Error::DuplicateOutput(name) => {
+            let lines = lines_naming(name, false);
+            if lines.len() > 1 {
+                let items: Vec<String> = lines.iter().map(|(number, _)| number.to_string()).collect();
+                let quantifier = if lines.len() == 2 { "both" } else { "all" };
+                return format!("{} {} name '{}'", format_line_list(&items), quantifier, name);
+            }
+            None
+        }
+        vimv::Error::DuplicateOutputCaseInsensitive(name) => {
+            let lines = lines_naming(name, true);
+            if lines.len() > 1 {
+                let items: Vec<String> =
+                    lines.iter().map(|(number, output_file)| format!("{} ('{}')", number, output_file)).collect();
+                return format!(
+                    "{} only differ in case; this may be intentional but Vimv always treats this situation as an \
+                    error to avoid accidentally overwriting files on case-insensitive file systems",
+                    format_line_list(&items)
+                );
+            }
+            None
+        }
+        vimv::Error::InvalidOutput(output_file, _)
+        | vimv::Error::OutsideRoot(output_file, _)
+        | vimv::Error::DirectoryExists(output_file)
+        | vimv::Error::FileExists(output_file)
+        | vimv::Error::OverwriteUnique(output_file, _) => {
+            let normalized: String = output_file.nfc().collect();
+            lines_naming(&normalized, false).first().map(|(number, _)| *number)
+        }
+        vimv::Error::LinkDirectory(input_file) | vimv::Error::LinkCrossesDevices(input_file, _) => {
+            line_of_input(input_file)
+        }
+        _ => None,
+    };
+    match line {
+        Some(number) => format!("line {}: {}", number, err),
+        None => err.to_string(),
+    }
+}
+
+
+// Returns the existing output file to ask the user about if the only problems with a plan are
+// existing output files, i.e. if they could all be resolved by conflict prompts.
+fn conflicting_file(err: &vimv::Error) -> Option<&str> {
+    match err {
+        vimv::Error::FileExists(output_file) => Some(output_file.as_str()),
+        vimv::Error::Multiple(problems) if problems.iter().all(|problem| conflicting_file(problem).is_some()) => {
+            conflicting_file(&problems[0])
+        }
+        _ => None,
+    }
+}
+
+
+// Formats a list of line numbers, each optionally followed by a description, e.g. 'line 3 and
+// line 7' or 'lines 3, 7, and 12'.
+fn format_line_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => format!("line {}", item),
+        [first, second] => format!("line {} and line {}", first, second),
+        [init @ .., last] => format!("lines {}, and {}", init.join(", "), last),
+    }
+}
+
+
+// The settings for editing the buffer in the editor.
+struct EditorSession<'a> {
+    input_files: &'a [String],
+    editor: Option<&'a str>,
+    extension: &'a str,
+    allow_missing_lines: bool,
+    delete_prefix: &'a str,
+
+    // True if we can offer to reopen the editor when the edits fail validation.
+    reopen: bool,
+
+    quiet: bool,
+}
+
+
+impl EditorSession<'_> {
+    // Opens the text in the editor and returns the edited text. Exits if the editor fails.
+    fn edit(&self, text: &str) -> String {
+        match editor::edit(text, self.editor, self.extension) {
+            Ok(edited) => {
+                // If we had to fall back to another editor, say which one we used.
+                if !edited.notes.is_empty() && !self.quiet {
+                    for note in &edited.notes {
+                        eprintln!("warning: {}", note);
+                    }
+                    eprintln!("warning: used the fallback editor '{}'", edited.editor);
+                }
+                edited.text
+            }
+            Err(err) => {
+                exit_with_error(err);
+            }
+        }
+    }
+
+    // Parses the edited buffer and returns it along with the output filenames and the new files to
+    // create. If the buffer is malformed, we offer to reopen it in the editor until it parses, and
+    // exit if the user declines.
+    fn parse(&self, mut buffer: EditedBuffer) -> (EditedBuffer, Vec<String>, Vec<String>) {
+        loop {
+            let parse = |buffer: &EditedBuffer| {
+                let EditedBuffer { text, format, escaped } = buffer;
+                let mut output_files = vimv::buffer::parse(
+                    self.input_files,
+                    text,
+                    *format,
+                    *escaped,
+                    self.allow_missing_lines,
+                    self.delete_prefix,
+                )?;
+                vimv::buffer::unmark_directories(self.input_files, &mut output_files);
+                let created_files = vimv::buffer::created_files(self.input_files, text, *format, *escaped)?;
+                Ok::<_, vimv::Error>((output_files, created_files))
+            };
+            let message = match parse(&buffer) {
+                Ok((output_files, created_files)) => return (buffer, output_files, created_files),
+                Err(err) => err.to_string(),
+            };
+            let describe = |reopened: &EditedBuffer| {
+                parse(reopened).err().map_or(message.clone(), |err| err.to_string())
+            };
+            match self.reopen(&buffer, &message, describe) {
+                Some(reopened) => buffer = reopened,
+                None => exit_with_error(save_edited_buffer(message, self.input_files, Some(&buffer))),
+            }
+        }
+    }
+
+    // Prints the error message for a buffer that failed validation and offers to reopen it in the
+    // editor with the error as a comment at the top. The error in the comment is described by
+    // `describe` so any line numbers it mentions are counted in the reopened buffer. Returns the
+    // re-edited buffer, or `None` if the user declines.
+    fn reopen(
+        &self,
+        buffer: &EditedBuffer,
+        message: &str,
+        describe: impl Fn(&EditedBuffer) -> String,
+    ) -> Option<EditedBuffer> {
+        if !self.reopen {
+            return None;
+        }
+        eprintln!("error: {}", message);
+        let answer = read_answer("Reopen the editor to fix it? [Y/n] ")?;
+        if answer.to_lowercase().starts_with('n') {
+            return None;
+        }
+
+        // We replace the comment left by an earlier attempt rather than stacking them up. Each
+        // line of the error gets a comment line of its own, so a placeholder with the same number
+        // of lines lets `describe` count the comment.
+        let error_prefix = format!("{} error: ", vimv::buffer::COMMENT_PREFIX);
+        let mut text = buffer.text.as_str();
+        while let Some((_, rest)) = text.split_once('\n').filter(|(first, _)| first.starts_with(&error_prefix)) {
+            text = rest;
+        }
+        let placeholder = EditedBuffer {
+            text: format!("{}\n", error_prefix).repeat(message.lines().count()) + text,
+            format: buffer.format,
+            escaped: buffer.escaped,
+        };
+        let comment: String =
+            describe(&placeholder).lines().map(|line| format!("{}{}\n", error_prefix, line.trim())).collect();
+        let edited = self.edit(&(comment + text));
+        Some(EditedBuffer { text: edited, format: buffer.format, escaped: buffer.escaped })
+    }
+}
+
+
+// Saves an edited buffer whose edits failed validation or weren't applied so they aren't lost, and
+// returns the error message with a note saying where the buffer was saved.
+fn save_edited_buffer(message: String, input_files: &[String], buffer: Option<&EditedBuffer>) -> String {
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => return message,
+    };
+    let saved = std::env::current_dir()
+        .map_err(|err| vimv::Error::Operation(format!("cannot determine the working directory: {}", err)))
+        .and_then(|cwd| vimv::recovery::save(&cwd, input_files, buffer.format, buffer.escaped, &buffer.text));
+    match saved {
+        Ok(path) => {
+            format!("{} (your edits have been saved to '{}', use --resume to continue)", message, path.display())
+        }
+        Err(err) => {
+            eprintln!("warning: {}", err);
+            message
+        }
+    }
+}
+
+
+// Loads the most recently saved buffer for the --resume flag and switches to the directory it was
+// saved in so relative filenames resolve as they did originally.
+fn load_saved_buffer() -> vimv::recovery::SavedBuffer {
+    let buffer_file = vimv::recovery::latest().unwrap_or_else(|| {
+        exit_with_error("no saved buffer found, there is nothing to resume");
+    });
+    let saved = vimv::recovery::read(&buffer_file).unwrap_or_else(|err| {
+        exit_with_error(err.to_string());
+    });
+    if let Err(err) = std::env::set_current_dir(&saved.cwd) {
+        exit_with_error(format!("cannot change to the directory '{}': {}", saved.cwd.display(), err));
+    }
+    saved
+}
+
+
+// Re-validates a saved buffer against the current state of the file system. Returns the input
+// files along with either the output filenames to start editing from or the saved text to reopen
+// as-is. If the saved edits are valid and some of them were applied before a failure, i.e. an
+// input file has gone and its output file exists, those files are dropped from the session.
+fn resume_saved_buffer(
+    saved: &vimv::recovery::SavedBuffer,
+    delete_prefix: &str,
+    quiet: bool,
+) -> (Vec<String>, Option<Vec<String>>, Option<String>) {
+    let parsed = vimv::buffer::parse(&saved.inputs, &saved.text, saved.format, saved.escaped, false, delete_prefix);
+    let outputs = match parsed {
+        Ok(outputs) if outputs.len() == saved.inputs.len() => outputs,
+        _ => return (saved.inputs.clone(), None, Some(saved.text.clone())),
+    };
+    let (done, remaining): (Pairs, Pairs) =
+        saved.inputs.iter().cloned().zip(outputs).partition(|(input_file, output_file)| {
+            // The parser has already replaced a custom deletion prefix with the default one.
+            let input_gone = Path::new(input_file).symlink_metadata().is_err();
+            let deleted = output_file != input_file && output_file.starts_with(vimv::buffer::DELETE_PREFIX);
+            input_gone && (deleted || Path::new(output_file).symlink_metadata().is_ok())
+        });
+    if done.is_empty() {
+        return (saved.inputs.clone(), None, Some(saved.text.clone()));
+    }
+    if !quiet {
+        eprintln!("note: {} change(s) from the saved buffer were already applied", done.len());
+    }
+    let (inputs, outputs) = remaining.into_iter().unzip();
+    (inputs, Some(outputs), None)
+}
+
+
+// Reads the output filenames for the --output-list option from a file, or from standard input if
+// `path` is '-'. Filenames are separated by newlines, or by NUL characters if `null` is true.
+fn read_output_list(path: &str, null: bool) -> Vec<String> {
+    let result = if path == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        fs::read_to_string(path)
+    };
+    let text = result.unwrap_or_else(|err| {
+        exit_with_error(format!("failed to read the output list '{}': {}", path, err));
+    });
+    if null {
+        text.split('\0').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+    } else {
+        text.lines().map(|s| s.to_string()).collect()
+    }
+}
+
+
+// Reads (input, output) pairs of filenames for the --pairs flag from standard input. Each pair is
+// on its own line with the filenames separated by a tab, or in its own NUL-terminated record if
+// `null` is true. Returns the lists of input and output filenames.
+fn read_pairs(null: bool) -> (Vec<String>, Vec<String>) {
+    let mut text = String::new();
+    if let Err(err) = std::io::stdin().read_to_string(&mut text) {
+        exit_with_error(format!("failed to read filename pairs from standard input: {}", err));
+    }
+    let records: Vec<&str> = if null {
+        text.split('\0').filter(|s| !s.is_empty()).collect()
+    } else {
+        text.lines().filter(|line| !line.is_empty()).collect()
+    };
+    let mut input_files = Vec::new();
+    let mut output_files = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        match record.split_once('\t') {
+            Some((input_file, output_file)) if !input_file.is_empty() && !output_file.contains('\t') => {
+                input_files.push(input_file.to_string());
+                output_files.push(output_file.to_string());
+            }
+            _ => exit_with_error(format!("pair {} is not of the form 'old<TAB>new': {}", index + 1, record)),
+        }
+    }
+    (input_files, output_files)
+}
+
+
+// Assemble the instructions written at the top of the editor buffer.
+fn buffer_header(parser: &ArgParser, format: BufferFormat) -> Vec<String> {
+    let delete_prefix = parser.value("delete-prefix");
+    let mut header = Vec::new();
+    match format {
+        BufferFormat::Single => {
+            header.push("Edit the filenames below, then save and exit. Don't add or remove lines.".to_string());
+            header.push(format!("Prefix a filename with '{}' to delete the file.", delete_prefix));
+        }
+        BufferFormat::Dual => {
+            header.push("Edit the second column below, then save and exit.".to_string());
+            header.push(format!("Prefix a destination with '{}' to delete the file.", delete_prefix));
+            header.push("Removing a line leaves the file unchanged.".to_string());
+        }
+        BufferFormat::Numbered => {
+            header.push("Edit the filenames below, then save and exit. Don't change the numbers.".to_string());
+            header.push(format!("Prefix a filename with '{}' to delete the file.", delete_prefix));
+            header.push("Removing a line leaves the file unchanged.".to_string());
+        }
+        BufferFormat::Grouped => {
+            header.push("Edit the filenames below, then save and exit. Don't add or remove lines.".to_string());
+            header.push("Files are grouped under a '## dir/' header for each directory.".to_string());
+            header.push("Edit a header to move the files below it.".to_string());
+            header.push(format!("Prefix a filename with '{}' to delete the file.", delete_prefix));
+        }
+    }
+    header.push("Prefix a new filename with '>' to copy the file instead of renaming it.".to_string());
+    header.push("Add a line beginning with '+' to create a new file, or a directory if it ends with '/'.".to_string());
+    if parser.found("rm") {
+        header.push("Deleted files will be removed permanently.".to_string());
+    } else {
+        if parser.found("archive-dir") {
+            header.push(format!("Deleted files will be moved to '{}'.", parser.value("archive-dir")));
+        } else if parser.found("trash-dir") {
+            header.push(format!("Deleted files will be moved to '{}'.", parser.value("trash-dir")));
+        } else {
+            header.push("Deleted files will be moved to the trash.".to_string());
+        }
+        header.push("Prefix a filename with '!' to delete it permanently instead.".to_string());
+    }
+    header.push("Lines beginning with '##' are ignored unless they name one of the files.".to_string());
+    if parser.found("annotate") {
+        header.push("Annotations following a tab and '##' at the end of a line are ignored.".to_string());
+    }
+
+    let flags: Vec<&str> = [
+        "backup",
+        "confirm-delete",
+        "copy",
+        "force",
+        "force-if-duplicate",
+        "fsync",
+        "git",
+        "hardlink",
+        "review",
+        "rm",
+        "strict-rename",
+    ]
+    .into_iter()
+        .filter(|flag| parser.found(flag))
+        .collect();
+    if !flags.is_empty() {
+        header.push(String::new());
+        let flags: Vec<String> = flags.iter().map(|flag| format!("--{}", flag)).collect();
+        header.push(format!("Flags: {}", flags.join(" ")));
+    }
+    header.push(String::new());
+    header
+}
+
+
+// Formats the summary printed after execution, e.g. 'Renamed 42, deleted 3, skipped 5, created
+// 7 directories (1.2s)'. Kinds of operation that didn't occur are left out.
+fn format_run_summary(reporter: &CliReporter, elapsed: std::time::Duration) -> String {
+    let tally = &reporter.tally;
+    let mut parts = Vec::new();
+    for (verb, count) in [
+        ("renamed", tally.renamed),
+        ("copied", tally.copied),
+        ("linked", tally.linked),
+        ("swapped", tally.swapped),
+        ("deleted", tally.deleted),
+        ("skipped", reporter.plan.skipped.len() + reporter.plan.same_files.len()),
+    ] {
+        if count > 0 {
+            parts.push(format!("{} {}", verb, count));
+        }
+    }
+    match tally.created_files {
+        0 => {}
+        1 => parts.push("created 1 file".to_string()),
+        count => parts.push(format!("created {} files", count)),
+    }
+    match tally.created_dirs {
+        0 => {}
+        1 => parts.push("created 1 directory".to_string()),
+        count => parts.push(format!("created {} directories", count)),
+    }
+    if !reporter.failures.is_empty() {
+        parts.push(format!("failed {}", reporter.failures.len()));
+    }
+
+    let summary = if parts.is_empty() { "nothing changed".to_string() } else { parts.join(", ") };
+    format!("{}{} ({:.1}s)", summary[..1].to_uppercase(), &summary[1..], elapsed.as_secs_f64())
+}
+
+
+// Print each (input, output) pair as two filenames, each followed by the terminator.
+fn print_results_list(results: &[(String, String)], terminator: char) {
+    let mut stdout = std::io::stdout().lock();
+    for (input_file, output_file) in results {
+        let _ = write!(stdout, "{}{}{}{}", input_file, terminator, output_file, terminator);
+    }
+    let _ = stdout.flush();
+}
+
+
+// Print a yes/no prompt and read the user's answer. Returns true if the answer begins with 'y'.
+fn confirm(prompt: &str) -> bool {
+    read_answer(prompt).is_some_and(|answer| answer.to_lowercase().starts_with('y'))
+}
+
+
+// The user's answer to a conflict prompt.
+enum Resolution {
+    // Apply a conflict policy to the file.
+    Policy(Conflict),
+
+    // Use a different output filename.
+    Rename(String),
+}
+
+
+// Ask the user what to do about an output filename that already exists. Exits if the user
+// chooses to abort.
+fn ask_conflict(output_file: &str) -> Resolution {
+    let prompt = format!(
+        "'{}' already exists. [o]verwrite / [s]kip / [r]ename / [n]umber / [a]bort? ",
+        output_file
+    );
+    loop {
+        let answer = read_answer(&prompt).map(|answer| answer.to_lowercase());
+        match answer.as_deref().and_then(|answer| answer.chars().next()) {
+            Some('o') => return Resolution::Policy(Conflict::Overwrite),
+            Some('s') => return Resolution::Policy(Conflict::Skip),
+            Some('n') => return Resolution::Policy(Conflict::Number),
+            Some('r') => match read_answer("New filename: ") {
+                Some(new_name) if !new_name.is_empty() => return Resolution::Rename(new_name),
+                Some(_) => continue,
+                None => exit_with_error("aborted, no changes have been made"),
+            },
+            Some('a') | None => exit_with_error("aborted, no changes have been made"),
+            Some(_) => continue,
+        }
+    }
+}
+
+
+// Print a prompt and read a line of input from the terminal. (We can't rely on standard input as
+// it may have been used to supply the list of input files, but we fall back to it if there's no
+// terminal.) Returns the trimmed answer, or `None` if reading fails.
+fn read_answer(prompt: &str) -> Option<String> {
+    eprint!("{}", prompt);
+    let mut answer = String::new();
+    let result = match fs::File::open(if cfg!(windows) { "CONIN$" } else { "/dev/tty" }) {
+        Ok(tty) => std::io::BufReader::new(tty).read_line(&mut answer),
+        Err(_) => std::io::stdin().read_line(&mut answer),
+    };
+    if result.is_err() {
+        eprintln!();
+        return None;
+    }
+    Some(answer.trim().to_string())
+}
+
+
+// Print an error message and exit with a non-zero status code. In JSON mode, the error is also
+// reported on standard output.
+fn exit_with_error<S: AsRef<str>>(message: S) -> ! {
+    print_json(&[("event", "error"), ("message", message.as_ref())]);
+    eprintln!("error: {}", message.as_ref());
+    exit(1);
+}
+
+
+// Print a JSON object with the specified fields. Does nothing unless we're in JSON mode.
+fn print_json(fields: &[(&str, &str)]) {
+    if JSON_OUTPUT.load(Ordering::Relaxed) {
+        println!("{}", json::object(fields));
+    }
+}
+
+
+// Print an operation with the old filename padded to `width`. Does nothing in JSON mode.
+fn print_operation(op: &Operation, width: usize) {
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        print!("{}", format_operation(op, width));
+    }
+}
+
+
+// Returns the width of the column of old filenames for a list of operations.
+fn column_width(operations: &[Operation]) -> usize {
+    let columns = operations
+        .iter()
+        .filter(|op| !matches!(op, Operation::Delete(_) | Operation::Purge(_) | Operation::Create(_)));
+    name_width(columns.map(|op| operation_inputs(op)[0]))
+}
+
+
+// Returns the width of a column of filenames: the length of the longest filename, up to
+// MAX_NAME_WIDTH.
+fn name_width<'a>(names: impl Iterator<Item = &'a str>) -> usize {
+    names.map(|name| name.chars().count()).max().unwrap_or(0).min(MAX_NAME_WIDTH)
+}
+
+
+// Returns an operation formatted as a line of aligned columns: the verb, the old filename padded
+// to `width`, an arrow, and the new filename. Only the changed words of each filename are
+// highlighted.
+fn format_operation(op: &Operation, width: usize) -> String {
+    let (verb, arrow, old, new) = match op {
+        Operation::Delete(input_file) => return format!("{} {}\n", format_verb("Deleting"), input_file),
+        Operation::Purge(input_file) => return format!("{} {}\n", format_verb("Purging"), input_file),
+        Operation::Create(path) => return format!("{} {}\n", format_verb("Creating"), path),
+        Operation::Rename(input_file, output_file) => ("Renaming", "→", input_file, output_file),
+        Operation::Copy(input_file, output_file) => ("Copying", "→", input_file, output_file),
+        Operation::Link(input_file, output_file) => ("Linking", "→", input_file, output_file),
+        Operation::Swap(input_file, output_file) => ("Swapping", "⇄", input_file, output_file),
+    };
+    format_columns(verb, arrow, old, new, width)
+}
+
+
+// Returns a line of aligned columns with the changed words in the old and new filenames
+// highlighted. Old filenames longer than `width` overflow the column.
+fn format_columns(verb: &str, arrow: &str, old: &str, new: &str, width: usize) -> String {
+    let (old_text, new_text) = highlight_diff(old, new);
+    let padding = " ".repeat(width.saturating_sub(old.chars().count()));
+    format!("{} {}{}  {}  {}\n", format_verb(verb), old_text, padding, arrow.green().bold(), new_text)
+}
+
+
+// Returns a verb right-aligned in the verb column.
+fn format_verb(verb: &str) -> ColoredString {
+    format!("{:>width$}", verb, width = VERB_WIDTH).green().bold()
+}
+
+
+// Returns a note for each existing file that will be overwritten saying whether its content is
+// identical to the file replacing it.
+fn format_overwrites(plan: &RenamePlan) -> String {
+    let mut overwrites: Vec<&String> = plan.overwrites.iter().collect();
+    overwrites.sort();
+    let mut text = String::new();
+    for output_file in overwrites {
+        if plan.is_identical_overwrite(output_file) {
+            text.push_str(&format!("{} {} (identical content, safe)\n", "Overwriting".yellow().bold(), output_file));
+        } else {
+            text.push_str(&format!("{} {} (unique content, will be lost)\n", "Overwriting".red().bold(), output_file));
+        }
+    }
+    text
+}
+
+
+// Returns an operation formatted in diff style for review. Review output goes to standard error
+// alongside the confirmation prompt so it doesn't mix with JSON output.
+fn format_review(op: &Operation) -> String {
+    match op {
+        Operation::Delete(input_file) => {
+            format!("{} {}\n", "-".red().bold(), input_file.red())
+        }
+        Operation::Purge(input_file) => {
+            format!("{} {} {}\n", "-".red().bold(), input_file.red(), "(permanently)".red().bold())
+        }
+        Operation::Rename(input_file, output_file) => {
+            let (old, new) = highlight_diff(input_file, output_file);
+            format!("{} {}\n{} {}\n", "-".red().bold(), old, "+".green().bold(), new)
+        }
+        Operation::Copy(input_file, output_file) | Operation::Link(input_file, output_file) => {
+            let (_, new) = highlight_diff(input_file, output_file);
+            format!("  {}\n{} {}\n", input_file, "+".green().bold(), new)
+        }
+        Operation::Swap(a, b) => {
+            let forward = format_review(&Operation::Rename(a.clone(), b.clone()));
+            let backward = format_review(&Operation::Rename(b.clone(), a.clone()));
+            format!("{}{}", forward, backward)
+        }
+        Operation::Create(path) => {
+            format!("{} {}\n", "+".green().bold(), path.green())
+        }
+    }
+}
+
+
+// Returns the planned operations formatted for a dry run, one per line.
+fn format_preview(plan: &RenamePlan) -> String {
+    let width = column_width(&plan.operations);
+    plan.operations.iter().map(|op| format_operation(op, width)).collect()
+}
+
+
+// Returns the old and new filenames with the words removed from the old filename highlighted in
+// red and the words added to the new filename highlighted in green.
+fn highlight_diff(old: &str, new: &str) -> (String, String) {
+    let mut old_text = String::new();
+    let mut new_text = String::new();
+    for (kind, text) in diff::word_diff(old, new) {
+        match kind {
+            Kind::Same => {
+                old_text.push_str(&text);
+                new_text.push_str(&text);
+            }
+            Kind::Removed => old_text.push_str(&text.red().bold().underline().to_string()),
+            Kind::Added => new_text.push_str(&text.green().bold().underline().to_string()),
+        }
+    }
+    (old_text, new_text)
+}
+
+
+// Print a deletion operation. Does nothing in JSON mode.
+fn print_delete(input_file: &str) {
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        println!("{} {}", format_verb("Deleting"), input_file);
+    }
+}
+
+
+// Print a restore operation.
+fn print_restore(input_file: &str) {
+    println!("{} {}", format_verb("Restoring"), input_file);
+}
+
+
+// Print an interrupted batch with the status of each of its operations.
+fn print_pending_batch(number: usize, batch: &PendingBatch) {
+    let started = batch
+        .pending_file
+        .file_stem()
+        .and_then(|stem| stem.to_string_lossy().parse::<i64>().ok())
+        .and_then(|millis| Local.timestamp_millis_opt(millis).single())
+        .map_or("unknown".to_string(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+    let done = batch.completed.iter().filter(|&&done| done).count();
+    println!(
+        "{}  {}  {}  ({} of {} operations completed)",
+        format!("{:>4}", number).green().bold(),
+        started,
+        batch.cwd.display(),
+        done,
+        batch.completed.len()
+    );
+    for (index, op) in batch.plan.operations.iter().enumerate() {
+        let status = if batch.completed[index] {
+            "done   ".green().bold()
+        } else if batch.started[index] {
+            "started".yellow().bold()
+        } else {
+            "pending".yellow().bold()
+        };
+        let description = match op {
+            Operation::Delete(input_file) => format!("delete {}", input_file),
+            Operation::Purge(input_file) => format!("purge {}", input_file),
+            Operation::Rename(input_file, output_file) => format!("rename {} -> {}", input_file, output_file),
+            Operation::Copy(input_file, output_file) => format!("copy {} -> {}", input_file, output_file),
+            Operation::Link(input_file, output_file) => format!("link {} -> {}", input_file, output_file),
+            Operation::Swap(input_file, output_file) => format!("swap {} <-> {}", input_file, output_file),
+            Operation::Create(path) => format!("create {}", path),
+        };
+        println!("      {}  {}", status, description);
+    }
+}
+
+
+// Print an entry from the list of trashed files.
+fn print_trashed_file(number: usize, file: &TrashedFile) {
+    let time_deleted = match Local.timestamp_opt(file.time_deleted, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "unknown".to_string(),
+    };
+    println!("{}  {}  {}", format!("{:>4}", number).green().bold(), time_deleted, file.path.display());
+}
+
+
+// Returns a one-line summary of a session from the history.
+fn format_session_summary(number: usize, session: &Session) -> String {
+    let time = match Local.timestamp_opt(session.time, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "unknown".to_string(),
+    };
+    format!(
+        "{}  {}  {}  ({} renamed, {} deleted)\n",
+        format!("{:>4}", number).green().bold(),
+        time,
+        session.cwd.display(),
+        session.mapping.len(),
+        session.deletions.len()
+    )
+}
+
+
+// Print a backup operation. Does nothing in JSON mode.
+fn print_backup(path: &str, backup: &str, width: usize) {
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        print!("{}", format_columns("Backing up", "→", path, backup, width));
+    }
+}
+
+
+// Print the archiving of a deleted file. Does nothing in JSON mode.
+fn print_archive(path: &str, archive: &str, width: usize) {
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        print!("{}", format_columns("Archiving", "→", path, archive, width));
+    }
+}
+
+
+// Print a symlink creation. Does nothing in JSON mode.
+fn print_symlink(path: &str, target: &str, width: usize) {
+    if !JSON_OUTPUT.load(Ordering::Relaxed) {
+        print!("{}", format_columns("Linking", "→", path, target, width));
+    }
+}
+
@@ -0,0 +1,110 @@
+use crate::error::{Error, Result};
+use crate::journal::{absolute, data_dir, escape, unescape};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// A file moved to the trash by a previous run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedFile {
+    /// The file's original absolute path.
+    pub path: PathBuf,
+
+    /// The time the file was deleted, in seconds since the Unix epoch.
+    pub time_deleted: i64,
+}
+
+
+/// Returns the path to the manifest file listing the files moved to the trash by vimv.
+pub fn manifest_file() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("trash.manifest"))
+}
+
+
+/// Appends a file that has just been moved to the trash to the manifest.
+pub fn record(input_file: &str) -> Result<()> {
+    let path = manifest_file().ok_or_else(|| {
+        Error::Operation("failed to locate a data directory for the trash manifest, set $XDG_DATA_HOME".to_string())
+    })?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| {
+            Error::Operation(format!("cannot create the directory '{}': {}", dir.display(), err))
+        })?;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let line = format!("{}\t{}", timestamp, escape(&absolute(input_file)));
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|err| Error::Operation(format!("cannot write to the trash manifest '{}': {}", path.display(), err)))
+}
+
+
+/// Reads the list of trashed files from the manifest, oldest first. Returns an empty list if the
+/// manifest doesn't exist.
+pub fn read() -> Result<Vec<TrashedFile>> {
+    let path = match manifest_file() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(Vec::new()),
+    };
+    let content = fs::read_to_string(&path).map_err(|err| {
+        Error::Input(format!("failed to read the trash manifest '{}': {}", path.display(), err))
+    })?;
+    let mut files = Vec::new();
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let (timestamp, file) = line.split_once('\t').ok_or_else(|| {
+            Error::Input(format!("the trash manifest '{}' is corrupt: {}", path.display(), line))
+        })?;
+        let time_deleted = timestamp.parse().map_err(|_| {
+            Error::Input(format!("the trash manifest '{}' is corrupt: {}", path.display(), line))
+        })?;
+        files.push(TrashedFile { path: PathBuf::from(unescape(file)), time_deleted });
+    }
+    Ok(files)
+}
+
+
+/// Returns the most recent entry in `files` for the specified path. Relative paths are resolved
+/// against the current working directory.
+pub fn find<'a>(files: &'a [TrashedFile], path: &str) -> Option<&'a TrashedFile> {
+    let path = PathBuf::from(absolute(path));
+    files.iter().rev().find(|file| file.path == path)
+}
+
+
+/// Removes the most recent entry for the specified path from the manifest. Does nothing if the
+/// path isn't listed.
+pub fn remove(path: &Path) -> Result<()> {
+    let mut files = read()?;
+    match files.iter().rposition(|file| file.path == path) {
+        Some(index) => {
+            files.remove(index);
+            write(&files)
+        }
+        None => Ok(()),
+    }
+}
+
+
+// Overwrites the manifest with the specified list of files.
+fn write(files: &[TrashedFile]) -> Result<()> {
+    let path = match manifest_file() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let content: String = files
+        .iter()
+        .map(|file| format!("{}\t{}\n", file.time_deleted, escape(&file.path.to_string_lossy())))
+        .collect();
+    fs::write(&path, content).map_err(|err| {
+        Error::Operation(format!("cannot write to the trash manifest '{}': {}", path.display(), err))
+    })
+}
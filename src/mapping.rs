@@ -0,0 +1,144 @@
+use crate::error::{Error, Result};
+use crate::journal::{escape, unescape};
+use crate::Operation;
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+
+/// File formats for exported mappings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingFormat {
+    /// A JSON object with a `mapping` array of `{"src": ..., "dst": ...}` objects. The `dst` field
+    /// is null for deletions.
+    Json,
+
+    /// One entry per line with the source and destination separated by a tab. Deletions have no
+    /// destination. Tabs, newlines, and backslashes are escaped with backslashes.
+    Tsv,
+}
+
+
+impl MappingFormat {
+    /// Returns the format for a mapping file: JSON if the file has a '.json' extension, TSV
+    /// otherwise.
+    pub fn from_path(path: &Path) -> MappingFormat {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => MappingFormat::Json,
+            _ => MappingFormat::Tsv,
+        }
+    }
+}
+
+
+/// Writes the mapping from `inputs` to `outputs` to a file at `path` instead of executing it.
+/// Unchanged filenames are omitted. Output filenames beginning with '#' are recorded as
+/// deletions. Filenames are written as supplied; relative filenames are relative to the current
+/// working directory, which is recorded in JSON files for reference.
+pub fn write(path: &Path, inputs: &[String], outputs: &[String], format: MappingFormat) -> Result<()> {
+    let entries = inputs
+        .iter()
+        .zip(outputs)
+        .filter(|(input_file, output_file)| input_file != output_file)
+        .map(|(input_file, output_file)| {
+            let dst = if output_file.starts_with('#') { None } else { Some(output_file) };
+            (input_file, dst)
+        });
+
+    let content = match format {
+        MappingFormat::Json => {
+            let mapping: Vec<Value> = entries.map(|(src, dst)| json!({ "src": src, "dst": dst })).collect();
+            let cwd = env::current_dir().map(|cwd| cwd.to_string_lossy().to_string()).unwrap_or_default();
+            let value = json!({ "cwd": cwd, "mapping": mapping });
+            format!("{}\n", serde_json::to_string_pretty(&value).unwrap_or_default())
+        }
+        MappingFormat::Tsv => entries
+            .map(|(src, dst)| match dst {
+                Some(dst) => format!("{}\t{}\n", escape(src), escape(dst)),
+                None => format!("{}\n", escape(src)),
+            })
+            .collect(),
+    };
+
+    fs::write(path, content).map_err(|err| {
+        Error::Operation(format!("cannot write the mapping file '{}': {}", path.display(), err))
+    })
+}
+
+
+/// Formats a list of operations as a TSV mapping, one entry per line. Renames, copies, and links
+/// are written as their source and destination, a swap as two entries, and a deletion as its
+/// source alone. A created file is written with an empty source.
+pub fn format_tsv(operations: &[Operation]) -> String {
+    let mut content = String::new();
+    for op in operations {
+        match op {
+            Operation::Rename(src, dst) | Operation::Copy(src, dst) | Operation::Link(src, dst) => {
+                content.push_str(&format!("{}\t{}\n", escape(src), escape(dst)));
+            }
+            Operation::Swap(src, dst) => {
+                content.push_str(&format!("{}\t{}\n", escape(src), escape(dst)));
+                content.push_str(&format!("{}\t{}\n", escape(dst), escape(src)));
+            }
+            Operation::Delete(src) | Operation::Purge(src) => content.push_str(&format!("{}\n", escape(src))),
+            Operation::Create(dst) => content.push_str(&format!("\t{}\n", escape(dst))),
+        }
+    }
+    content
+}
+
+
+/// Reads a mapping written by `write()` from the file at `path` and returns it as lists of input
+/// and output filenames. Deletions are returned as output filenames beginning with '#', as in the
+/// editor buffer, so the lists can be passed directly to `plan()`.
+pub fn read(path: &Path) -> Result<(Vec<String>, Vec<String>)> {
+    let content = fs::read_to_string(path).map_err(|err| {
+        Error::Input(format!("failed to read the mapping file '{}': {}", path.display(), err))
+    })?;
+    let malformed = |reason: &str| {
+        Error::Input(format!("the mapping file '{}' is malformed: {}", path.display(), reason))
+    };
+
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    match MappingFormat::from_path(path) {
+        MappingFormat::Json => {
+            let value: Value = serde_json::from_str(&content).map_err(|err| malformed(&err.to_string()))?;
+            let mapping = value
+                .get("mapping")
+                .and_then(|mapping| mapping.as_array())
+                .ok_or_else(|| malformed("expected a 'mapping' array"))?;
+            for entry in mapping {
+                let src = entry
+                    .get("src")
+                    .and_then(|src| src.as_str())
+                    .ok_or_else(|| malformed(&format!("expected a 'src' string: {}", entry)))?;
+                let dst = match entry.get("dst") {
+                    Some(Value::String(dst)) => dst.to_string(),
+                    Some(Value::Null) | None => format!("#{}", src),
+                    Some(_) => return Err(malformed(&format!("expected a 'dst' string or null: {}", entry))),
+                };
+                inputs.push(src.to_string());
+                outputs.push(dst);
+            }
+        }
+        MappingFormat::Tsv => {
+            for line in content.lines().filter(|line| !line.is_empty()) {
+                let fields: Vec<String> = line.split('\t').map(unescape).collect();
+                match fields.as_slice() {
+                    [src] => {
+                        inputs.push(src.to_string());
+                        outputs.push(format!("#{}", src));
+                    }
+                    [src, dst] => {
+                        inputs.push(src.to_string());
+                        outputs.push(dst.to_string());
+                    }
+                    _ => return Err(malformed(&format!("expected one or two fields: {}", line))),
+                }
+            }
+        }
+    }
+    Ok((inputs, outputs))
+}
@@ -0,0 +1,212 @@
+use chrono::{DateTime, Local};
+use std::fs;
+use std::fs::{File, FileTimes};
+use std::io;
+use std::path::Path;
+
+
+/// Controls which metadata is preserved when a file is copied, either in copy mode or when a
+/// rename falls back to copy-and-delete across file systems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preserve {
+    /// Permission bits.
+    pub mode: bool,
+
+    /// Access and modification times.
+    pub timestamps: bool,
+
+    /// Extended attributes. (Unix only.)
+    pub xattrs: bool,
+}
+
+
+impl Default for Preserve {
+    fn default() -> Preserve {
+        Preserve::all()
+    }
+}
+
+
+impl Preserve {
+    /// Preserve all supported metadata.
+    pub fn all() -> Preserve {
+        Preserve { mode: true, timestamps: true, xattrs: true }
+    }
+
+    /// Don't preserve any metadata.
+    pub fn none() -> Preserve {
+        Preserve { mode: false, timestamps: false, xattrs: false }
+    }
+
+    /// Returns a copy with the attributes in `names`, a comma-separated list of 'mode',
+    /// 'timestamps', 'xattr', or 'all', switched off. Returns `None` if a name is invalid.
+    pub fn without(self, names: &str) -> Option<Preserve> {
+        let mut preserve = self;
+        for name in names.split(',').map(|name| name.trim()).filter(|name| !name.is_empty()) {
+            match name {
+                "mode" => preserve.mode = false,
+                "timestamps" => preserve.timestamps = false,
+                "xattr" => preserve.xattrs = false,
+                "all" => preserve = Preserve::none(),
+                _ => return None,
+            }
+        }
+        Some(preserve)
+    }
+}
+
+
+/// Copies the selected metadata from `src` to `dst`. Permissions are applied last as they may
+/// make `dst` read-only. Extended attributes that the destination file system doesn't support
+/// are skipped.
+pub fn copy_metadata(src: &Path, dst: &Path, preserve: &Preserve) -> io::Result<()> {
+    let metadata = fs::metadata(src)?;
+    if preserve.xattrs {
+        copy_xattrs(src, dst)?;
+    }
+    if preserve.timestamps {
+        let times = FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        open_for_times(dst)?.set_times(times)?;
+    }
+    if preserve.mode {
+        fs::set_permissions(dst, metadata.permissions())?;
+    }
+    Ok(())
+}
+
+
+// Opens a file or directory so its timestamps can be set. On Windows, directories can only be
+// opened with the backup semantics flag.
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+    fs::OpenOptions::new().write(true).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(path)
+}
+
+
+// Opens a file or directory so its timestamps can be set. The owner can set explicit timestamps
+// using a read-only handle.
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
+
+
+// Copies extended attributes from `src` to `dst`. Attributes in namespaces other than 'user'
+// generally require elevated privileges so we skip them if we're not allowed to set them.
+#[cfg(unix)]
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let names = match xattr::list(src) {
+        Ok(names) => names,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for name in names {
+        let value = match xattr::get(src, &name)? {
+            Some(value) => value,
+            None => continue,
+        };
+        match xattr::set(dst, &name, &value) {
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied
+                && !name.to_string_lossy().starts_with("user.") => continue,
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+
+// Extended attributes aren't supported on this platform.
+#[cfg(not(unix))]
+fn copy_xattrs(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+
+// Media types for common file extensions, compared case-insensitively.
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("heic", "image/heic"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("svg", "image/svg+xml"),
+    ("mp3", "audio/mpeg"),
+    ("flac", "audio/flac"),
+    ("ogg", "audio/ogg"),
+    ("m4a", "audio/mp4"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("json", "application/json"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xml", "text/xml"),
+];
+
+
+/// Returns a short description of the file at `path` for annotating the editor buffer: its size,
+/// its modification date, and its type, e.g. '2.3M  2024-01-03  image/jpeg'. Files are typed by
+/// extension. Returns an empty string if the file can't be read.
+pub fn describe(path: &Path) -> String {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return String::new(),
+    };
+    let kind = if metadata.is_symlink() {
+        "symlink"
+    } else if metadata.is_dir() {
+        "directory"
+    } else {
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+        MEDIA_TYPES
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map_or("application/octet-stream", |(_, media_type)| media_type)
+    };
+    let size = if metadata.is_file() { format_size(metadata.len()) } else { "-".to_string() };
+    let modified = match metadata.modified() {
+        Ok(modified) => DateTime::<Local>::from(modified).format("%Y-%m-%d").to_string(),
+        Err(_) => "-".to_string(),
+    };
+    format!("{:>5}  {}  {}", size, modified, kind)
+}
+
+
+// Formats a size in bytes using binary units in the style of `ls -h`, e.g. '512', '2.3M', '15G'.
+fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64;
+    let mut unit = "";
+    for next in ["K", "M", "G", "T"] {
+        size /= 1024.0;
+        unit = next;
+        if size < 1024.0 {
+            break;
+        }
+    }
+    if size < 10.0 {
+        format!("{:.1}{}", size, unit)
+    } else {
+        format!("{:.0}{}", size, unit)
+    }
+}
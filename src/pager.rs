@@ -0,0 +1,54 @@
+// Pipes long output through a pager.
+
+use crate::editor::split_command;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+
+// Writes `text` to standard output, or standard error if `stderr` is true. If `enabled` is true,
+// the output is a terminal, and the text is too long to fit on the screen, the text is piped
+// through the pager specified by $PAGER instead, defaulting to `less`. (Paging standard error
+// output requires standard output to be a terminal too as that's where the pager writes.)
+pub fn page(text: &str, stderr: bool, enabled: bool) {
+    let is_terminal = io::stdout().is_terminal() && (!stderr || io::stderr().is_terminal());
+    let too_long = terminal_height().is_some_and(|height| text.lines().count() >= height);
+    if enabled && is_terminal && too_long && run_pager(text).is_ok() {
+        return;
+    }
+    if stderr {
+        eprint!("{}", text);
+    } else {
+        print!("{}", text);
+    }
+}
+
+
+// Returns the height of the terminal in lines, if known.
+fn terminal_height() -> Option<usize> {
+    if let Some((_, terminal_size::Height(height))) = terminal_size::terminal_size() {
+        return Some(height as usize);
+    }
+    env::var("LINES").ok().and_then(|lines| lines.trim().parse().ok())
+}
+
+
+// Runs the pager with `text` as its input and waits for it to exit. If $LESS isn't set, we set
+// it to 'FRX' so less passes colors through and leaves the output on the screen when it exits.
+fn run_pager(text: &str) -> io::Result<()> {
+    let pager = env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less".to_string());
+    let words = split_command(&pager).unwrap_or_default();
+    let (program, args) = words.split_first().ok_or_else(|| io::Error::other("the pager command is empty"))?;
+    let mut cmd = Command::new(program);
+    cmd.args(args).stdin(Stdio::piped());
+    if env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    let mut child = cmd.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The write fails with a broken pipe if the user quits the pager early. That's fine.
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use crate::error::{Error, Result};
+use chrono::NaiveDateTime;
+use std::fs::File;
+use std::io::BufReader;
+
+
+/// EXIF metadata read from a photo.
+#[derive(Debug, Clone, Default)]
+pub struct PhotoInfo {
+    /// The date and time the photo was taken, falling back to the date and time it was last
+    /// modified by the camera or software.
+    pub date: Option<NaiveDateTime>,
+
+    /// The camera manufacturer, e.g. 'Canon'.
+    pub make: Option<String>,
+
+    /// The camera model, e.g. 'Canon EOS R5'.
+    pub model: Option<String>,
+}
+
+
+impl PhotoInfo {
+    /// Returns a description of the camera combining the make and model, e.g. 'Nikon D750'. The
+    /// make is omitted if the model already begins with it.
+    pub fn camera(&self) -> Option<String> {
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) => {
+                let brand = make.split_whitespace().next().unwrap_or("");
+                if model.to_lowercase().starts_with(&brand.to_lowercase()) {
+                    Some(model.clone())
+                } else {
+                    Some(format!("{} {}", brand, model))
+                }
+            }
+            (None, Some(model)) => Some(model.clone()),
+            (Some(make), None) => Some(make.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+
+/// Reads the EXIF metadata from the photo at `path`.
+pub fn read(path: &str) -> Result<PhotoInfo> {
+    let file = File::open(crate::longpath::extended(std::path::Path::new(path))).map_err(|err| {
+        Error::Transform(format!("cannot read EXIF data from '{}': {}", path, err))
+    })?;
+    let exif = exif::Reader::new().read_from_container(&mut BufReader::new(file)).map_err(|err| {
+        Error::Transform(format!("cannot read EXIF data from '{}': {}", path, err))
+    })?;
+
+    let date = [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .into_iter()
+        .filter_map(|tag| ascii_field(&exif, tag))
+        .find_map(|value| NaiveDateTime::parse_from_str(&value, "%Y:%m:%d %H:%M:%S").ok());
+
+    Ok(PhotoInfo {
+        date,
+        make: ascii_field(&exif, exif::Tag::Make),
+        model: ascii_field(&exif, exif::Tag::Model),
+    })
+}
+
+
+// Returns the value of an ASCII field in the primary image, if present and non-empty.
+fn ascii_field(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
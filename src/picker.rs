@@ -0,0 +1,260 @@
+// A built-in fuzzy finder for choosing which of the input files to edit, used when the --pick flag
+// is set.
+
+use crate::tui::display;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashSet;
+use std::io;
+
+// The number of rows moved by the PageUp and PageDown keys.
+const PAGE_SIZE: usize = 10;
+
+// The result of handling a keypress.
+enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+struct Picker<'a> {
+    files: &'a [String],
+
+    // The text typed so far. The cursor is always at the end.
+    query: String,
+
+    // Indices into `files` of the files matching the query, best match first.
+    matches: Vec<usize>,
+
+    // Indices into `files` of the files marked for inclusion.
+    marked: HashSet<usize>,
+
+    state: ListState,
+}
+
+// Opens the picker on `files` and returns the files chosen by the user in their original order,
+// or `None` if the user cancels. If no files have been marked when the user presses Enter, the
+// highlighted file is chosen.
+pub fn pick(files: &[String]) -> Result<Option<Vec<String>>, String> {
+    let mut terminal = ratatui::try_init().map_err(|err| format!("cannot initialize the terminal: {}", err))?;
+    let mut picker = Picker::new(files);
+    let result = picker.run(&mut terminal);
+    ratatui::restore();
+    let chosen = result.map_err(|err| format!("terminal error: {}", err))?;
+    Ok(chosen.map(|indices| indices.into_iter().map(|index| files[index].clone()).collect()))
+}
+
+impl<'a> Picker<'a> {
+    fn new(files: &'a [String]) -> Picker<'a> {
+        Picker {
+            files,
+            query: String::new(),
+            matches: (0..files.len()).collect(),
+            marked: HashSet::new(),
+            state: ListState::default().with_selected(Some(0)),
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<Vec<usize>>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match self.handle_key(key) {
+                Action::Continue => {}
+                Action::Accept => return Ok(Some(self.chosen())),
+                Action::Cancel => return Ok(None),
+            }
+        }
+    }
+
+    fn selected(&self) -> usize {
+        self.state.selected().unwrap_or(0).min(self.matches.len().saturating_sub(1))
+    }
+
+    fn select(&mut self, index: usize) {
+        self.state.select(Some(index.min(self.matches.len().saturating_sub(1))));
+    }
+
+    // Returns the indices of the chosen files in their original order.
+    fn chosen(&self) -> Vec<usize> {
+        if self.marked.is_empty() {
+            return self.matches.get(self.selected()).copied().into_iter().collect();
+        }
+        let mut chosen: Vec<usize> = self.marked.iter().copied().collect();
+        chosen.sort_unstable();
+        chosen
+    }
+
+    // Marks the highlighted file if it isn't marked, or unmarks it if it is.
+    fn toggle(&mut self) {
+        if let Some(&index) = self.matches.get(self.selected()) {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+        }
+    }
+
+    // Marks all the matching files, or unmarks them if they're all marked already.
+    fn toggle_all(&mut self) {
+        if self.matches.iter().all(|index| self.marked.contains(index)) {
+            for index in &self.matches {
+                self.marked.remove(index);
+            }
+        } else {
+            self.marked.extend(self.matches.iter().copied());
+        }
+    }
+
+    // Updates the list of matches after the query has changed.
+    fn filter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| fuzzy_score(&self.query, file).map(|score| (score, index)))
+            .collect();
+        scored.sort_by_key(|&(score, index)| (std::cmp::Reverse(score), index));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+        self.select(0);
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let index = self.selected();
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            match key.code {
+                KeyCode::Char('c') => return Action::Cancel,
+                KeyCode::Char('a') => self.toggle_all(),
+                KeyCode::Char('p') => self.select(index.saturating_sub(1)),
+                KeyCode::Char('n') => self.select(index + 1),
+                KeyCode::Char('u') => {
+                    self.query.clear();
+                    self.filter();
+                }
+                _ => {}
+            }
+            return Action::Continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Esc => return Action::Cancel,
+            KeyCode::Up => self.select(index.saturating_sub(1)),
+            KeyCode::Down => self.select(index + 1),
+            KeyCode::PageUp => self.select(index.saturating_sub(PAGE_SIZE)),
+            KeyCode::PageDown => self.select(index + PAGE_SIZE),
+            KeyCode::Tab => {
+                self.toggle();
+                self.select(index + 1);
+            }
+            KeyCode::BackTab => {
+                self.toggle();
+                self.select(index.saturating_sub(1));
+            }
+            KeyCode::Backspace if self.query.pop().is_some() => self.filter(),
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [query_area, list_area, status_area] =
+            Layout::vertical([Constraint::Length(3), Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        self.draw_query(frame, query_area);
+        self.draw_list(frame, list_area);
+        let text = "Type to filter  Tab: mark  Ctrl-A: mark all  Enter: edit the marked files  Esc: quit";
+        frame.render_widget(Paragraph::new(text).style(Style::new().add_modifier(Modifier::REVERSED)), status_area);
+    }
+
+    fn draw_query(&self, frame: &mut Frame, area: Rect) {
+        let (shown, total, marked) = (self.matches.len(), self.files.len(), self.marked.len());
+        let title = format!(" Pick files ({} of {}, {} marked) ", shown, total, marked);
+        let query = display(&self.query);
+        let width = query.chars().count() as u16;
+        frame.render_widget(Paragraph::new(query).block(Block::bordered().title(title)), area);
+        let x = (area.x + 1 + width).min(area.right().saturating_sub(2));
+        frame.set_cursor_position((x, area.y + 1));
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self
+            .matches
+            .iter()
+            .map(|&index| {
+                let file = display(&self.files[index]);
+                if self.marked.contains(&index) {
+                    ListItem::new(Line::from(vec![
+                        Span::styled("● ", Style::new().fg(Color::Green)),
+                        Span::styled(file, Style::new().fg(Color::Green)),
+                    ]))
+                } else {
+                    ListItem::new(Line::from(vec![Span::raw("  "), Span::raw(file)]))
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered())
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, area, &mut self.state);
+    }
+}
+
+// Returns a score for how well `candidate` matches the fuzzy `query`, or `None` if the characters
+// of the query don't all appear in the candidate in order. Matching is case-insensitive unless the
+// query contains an uppercase letter. Tighter matches score higher, as do matches that start at
+// the beginning of a path component or of the filename itself.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    let fold = |c: char| if query.chars().any(char::is_uppercase) { c } else { c.to_ascii_lowercase() };
+    let query: Vec<char> = query.chars().map(fold).collect();
+    let chars: Vec<char> = candidate.chars().map(fold).collect();
+
+    // Find the earliest position where the whole query has matched, then scan backwards from there
+    // for the latest start that still matches, which gives the tightest match ending there.
+    let mut next = 0;
+    let mut end = None;
+    for (index, c) in chars.iter().enumerate() {
+        if *c == query[next] {
+            next += 1;
+            if next == query.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    let mut remaining = query.len();
+    let mut start = end;
+    for index in (0..=end).rev() {
+        if chars[index] == query[remaining - 1] {
+            remaining -= 1;
+            if remaining == 0 {
+                start = index;
+                break;
+            }
+        }
+    }
+
+    let mut score = 1000 - (end - start + 1 - query.len()) as i64 * 10;
+    let is_separator = |c: &char| *c == '/' || *c == std::path::MAIN_SEPARATOR;
+    if start == 0 || is_separator(&chars[start - 1]) {
+        score += 50;
+    }
+    let filename_start = chars.iter().rposition(is_separator).map_or(0, |index| index + 1);
+    if start >= filename_start {
+        score += 25;
+    }
+    Some(score)
+}
@@ -0,0 +1,978 @@
+use crate::buffer::copy_destination;
+use crate::error::{Error, Result};
+use crate::exec::is_same_file;
+use crate::longpath::extended;
+use crate::validate;
+use crate::validate::Filesystem;
+use rand::Rng;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+
+/// A Unicode normalization form for output filenames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    Nfc,
+    Nfd,
+}
+
+
+impl Normalization {
+    /// Parses a normalization form name: 'nfc' or 'nfd'.
+    pub fn from_name(name: &str) -> Option<Normalization> {
+        match name {
+            "nfc" => Some(Normalization::Nfc),
+            "nfd" => Some(Normalization::Nfd),
+            _ => None,
+        }
+    }
+}
+
+
+/// What to do when an output filename already exists and isn't part of the batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Conflict {
+    /// Fail with an error.
+    #[default]
+    Error,
+
+    /// Leave the input file unchanged.
+    Skip,
+
+    /// Overwrite the existing file. Existing directories are never overwritten.
+    Overwrite,
+
+    /// Append the first free number to the output filename, e.g. 'name (1).txt'.
+    Number,
+}
+
+
+impl Conflict {
+    /// Parses a conflict policy name: 'error', 'skip', 'overwrite', or 'number'.
+    pub fn from_name(name: &str) -> Option<Conflict> {
+        match name {
+            "error" => Some(Conflict::Error),
+            "skip" => Some(Conflict::Skip),
+            "overwrite" => Some(Conflict::Overwrite),
+            "number" => Some(Conflict::Number),
+            _ => None,
+        }
+    }
+}
+
+
+/// Options controlling how a plan is constructed.
+#[derive(Debug, Clone, Default)]
+pub struct PlanOptions {
+    /// Allow overwriting existing files that aren't part of the batch.
+    pub force: bool,
+
+    /// Allow overwriting existing files that aren't part of the batch only if their content is
+    /// identical to the file replacing them.
+    pub force_if_duplicate: bool,
+
+    /// Compare the content of each existing file that will be overwritten with the file
+    /// replacing it. Identical files are listed in `RenamePlan::identical_overwrites`.
+    pub compare_overwrites: bool,
+
+    /// Copy files to their output filenames instead of renaming them. Individual files can be
+    /// copied by prefixing their output filenames with `buffer::COPY_PREFIX`.
+    pub copy: bool,
+
+    /// Create hard links at the output filenames instead of renaming the files.
+    pub link: bool,
+
+    /// Reject output filenames that Windows doesn't allow, e.g. reserved device names like 'CON'
+    /// or names ending with a dot or a space.
+    pub check_windows_names: bool,
+
+    /// Reject output filenames that the file system they'll be written to doesn't allow, e.g.
+    /// names containing ':' on FAT or names longer than 255 bytes on ext4.
+    pub check_filesystems: bool,
+
+    /// Convert output filenames to the specified normalization form.
+    pub normalize: Option<Normalization>,
+
+    /// What to do when an output filename already exists and isn't part of the batch. The
+    /// `force` option takes precedence.
+    pub on_conflict: Conflict,
+
+    /// Overrides `on_conflict` for individual output filenames.
+    pub conflicts: HashMap<String, Conflict>,
+
+    /// Reject output filenames and files to create that resolve to a path outside this
+    /// directory, which must be canonical.
+    pub restrict: Option<PathBuf>,
+
+    /// New, empty files to create once the other changes have been made. Filenames ending with a
+    /// path separator are created as directories. None of them may exist already.
+    pub create: Vec<String>,
+}
+
+
+/// A single file system operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Move the file to the system's trash/recycle bin.
+    Delete(String),
+
+    /// Delete the file permanently, bypassing the trash.
+    Purge(String),
+
+    /// Rename the file from `.0` to `.1`.
+    Rename(String, String),
+
+    /// Copy the file from `.0` to `.1`.
+    Copy(String, String),
+
+    /// Create a hard link to the file `.0` at `.1`.
+    Link(String, String),
+
+    /// Swap the files `.0` and `.1`, i.e. rename each to the other's name.
+    Swap(String, String),
+
+    /// Create a new, empty file at `.0`, or a directory if `.0` ends with a path separator.
+    Create(String),
+}
+
+
+/// An ordered list of operations which, executed in sequence, transforms the input filenames
+/// into the output filenames.
+#[derive(Debug, Clone, Default)]
+pub struct RenamePlan {
+    /// The operations in execution order. Deletions come first.
+    pub operations: Vec<Operation>,
+
+    /// Temporary filenames used to break cycles.
+    pub temp_files: HashSet<String>,
+
+    /// Output filenames that will overwrite existing files that aren't part of the batch.
+    pub overwrites: HashSet<String>,
+
+    /// The subset of `overwrites` whose content is identical to the file replacing them. Only
+    /// populated if overwrites have been compared.
+    pub identical_overwrites: HashSet<String>,
+
+    /// Changes skipped because their output filenames already exist, as (input, output) pairs.
+    pub skipped: Vec<(String, String)>,
+
+    /// Changes skipped because the input and output filenames are already names for the same
+    /// file, e.g. hard links to the same inode, as (input, output) pairs.
+    pub same_files: Vec<(String, String)>,
+}
+
+
+impl RenamePlan {
+    /// Returns true if the plan contains no operations.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Returns true if `path` is a temporary filename used to break a cycle.
+    pub fn is_temp_file(&self, path: &str) -> bool {
+        self.temp_files.contains(path)
+    }
+
+    /// Returns true if `path` is an existing file that will be overwritten by the plan.
+    pub fn is_overwrite(&self, path: &str) -> bool {
+        self.overwrites.contains(path)
+    }
+
+    /// Returns true if `path` is an existing file that will be overwritten by a file with
+    /// identical content.
+    pub fn is_identical_overwrite(&self, path: &str) -> bool {
+        self.identical_overwrites.contains(path)
+    }
+
+    /// Returns the operations with any detours via temporary files collapsed, i.e. the changes
+    /// the plan makes as the user would describe them.
+    pub fn logical_operations(&self) -> Vec<Operation> {
+        let mut temp_sources: HashMap<&str, &Operation> = HashMap::new();
+        let mut operations = Vec::new();
+        for op in &self.operations {
+            match op {
+                Operation::Rename(_, output_file) | Operation::Copy(_, output_file) | Operation::Link(_, output_file)
+                    if self.is_temp_file(output_file) =>
+                {
+                    temp_sources.insert(output_file.as_str(), op);
+                }
+                Operation::Rename(input_file, output_file) if self.is_temp_file(input_file) => {
+                    let op = match temp_sources.get(input_file.as_str()) {
+                        Some(Operation::Copy(src, _)) => Operation::Copy(src.clone(), output_file.clone()),
+                        Some(Operation::Rename(src, _)) => Operation::Rename(src.clone(), output_file.clone()),
+                        Some(Operation::Link(src, _)) => Operation::Link(src.clone(), output_file.clone()),
+                        _ => op.clone(),
+                    };
+                    operations.push(op);
+                }
+                _ => operations.push(op.clone()),
+            }
+        }
+        operations
+    }
+}
+
+
+/// Checks that the input filenames are valid: all exist and all are unique. Filenames that name
+/// the same path, e.g. 'a.txt' and './a.txt', are duplicates.
+pub fn validate_inputs(inputs: &[String]) -> Result<()> {
+    for input_file in inputs {
+        if !extended(Path::new(input_file)).exists() {
+            return Err(Error::InputNotFound(input_file.to_string()));
+        }
+    }
+
+    let mut input_set = HashSet::new();
+    for input_file in inputs {
+        if !input_set.insert(canonical_path(input_file)) {
+            return Err(Error::DuplicateInput(input_file.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Validates a list of input filenames and a matching list of output filenames and returns a
+/// plan for transforming one into the other. Output filenames beginning with '#' mark the
+/// corresponding input file for deletion, and output filenames beginning with '!' mark it for
+/// permanent deletion, unless they're identical to the input filename; to rename a file to a
+/// name beginning with '#' or '!', write it as './#name'. Output filenames beginning with '>'
+/// mark the input file for copying to the rest of the filename, leaving the original in place.
+/// Cycles (e.g. renaming A to B and B to A) are broken using temporary files. If the output
+/// filenames have several problems, e.g. duplicates and existing files, they're all reported
+/// together in an `Error::Multiple`.
+pub fn plan(inputs: &[String], outputs: &[String], options: &PlanOptions) -> Result<RenamePlan> {
+    validate_inputs(inputs)?;
+
+    // Sanity check - verify that we have equal numbers of input and output filenames.
+    if outputs.len() != inputs.len() {
+        return Err(Error::CountMismatch { inputs: inputs.len(), outputs: outputs.len() });
+    }
+
+    // Convert the output filenames and the filenames to create to the specified Unicode
+    // normalization form.
+    let normalize = |path: &String| match options.normalize {
+        Some(Normalization::Nfc) => path.nfc().collect(),
+        Some(Normalization::Nfd) => path.nfd().collect(),
+        None => path.to_string(),
+    };
+    let mut outputs: Vec<String> = outputs.iter().map(normalize).collect();
+    let mut created: Vec<String> = options.create.iter().map(normalize).collect();
+
+    // Input files marked for copying. Their output filenames are replaced by the destinations. An
+    // output filename that names the same path as its input filename, e.g. 'a.txt' spelled as
+    // './a.txt' or an escaped filename beginning with '#', leaves the file unchanged.
+    let mut copies: HashSet<String> = HashSet::new();
+    for (input_file, output_file) in inputs.iter().zip(outputs.iter_mut()) {
+        if same_path(input_file, output_file) {
+            *output_file = input_file.clone();
+        }
+        if input_file == output_file {
+            continue;
+        }
+        if let Some(destination) = copy_destination(output_file) {
+            copies.insert(input_file.to_string());
+            *output_file = destination.to_string();
+        }
+    }
+
+    // Editors and terminals may change the Unicode normalization form of filenames, e.g. macOS
+    // file systems store filenames in NFD while most editors produce NFC. If an output filename
+    // is canonically equivalent to an input filename, or only differs from it lexically, e.g.
+    // './b.txt' for 'b.txt', we replace it with the input filename's exact spelling so unchanged
+    // lines and cycles are detected correctly. (If a normalization form has been specified, a
+    // line that only differs from its own input in its normalization form is left alone so the
+    // file gets renamed to the normalized form. An escaped filename is never replaced with
+    // another input filename beginning with '#' or '!' as it would then mark a deletion.)
+    let normalized_inputs: HashMap<String, &String> = inputs
+        .iter()
+        .map(|input_file| (normalize_path(input_file).nfc().collect(), input_file))
+        .collect();
+    for (input_file, output_file) in inputs.iter().zip(outputs.iter_mut()) {
+        if is_deletion(output_file) {
+            continue;
+        }
+        let normalized_output: String = normalize_path(output_file).nfc().collect();
+        if let Some(&matching_input) = normalized_inputs.get(&normalized_output) {
+            if matching_input == input_file && options.normalize.is_some() {
+                continue;
+            }
+            if matching_input != input_file && is_deletion(matching_input) {
+                continue;
+            }
+            *output_file = matching_input.clone();
+        }
+    }
+
+    // Problems with the output filenames. We check the whole list before reporting them so they
+    // can all be fixed at once.
+    let mut problems: Vec<Error> = Vec::new();
+
+    // Sanity check - verify that the changed output filenames are valid on Windows.
+    if options.check_windows_names {
+        for (input_file, output_file) in inputs.iter().zip(outputs.iter()) {
+            if input_file != output_file && !is_deletion(output_file) {
+                problems.extend(validate::check_windows_name(output_file).err());
+            }
+        }
+    }
+
+    // Sanity check - verify that the changed output filenames are valid on the file systems
+    // they'll be written to. We detect the file system once per destination directory and note
+    // the output files on case-sensitive file systems for the uniqueness checks below.
+    let mut case_sensitive_outputs: HashSet<&String> = HashSet::new();
+    if options.check_filesystems {
+        let mut filesystems: HashMap<PathBuf, Option<Filesystem>> = HashMap::new();
+        for (input_file, output_file) in inputs.iter().zip(outputs.iter()) {
+            if is_deletion(output_file) {
+                continue;
+            }
+            let dir = Path::new(output_file).parent().unwrap_or(Path::new("")).to_path_buf();
+            let filesystem = filesystems.entry(dir).or_insert_with_key(|dir| Filesystem::detect(dir));
+            if let Some(filesystem) = filesystem {
+                if input_file != output_file {
+                    problems.extend(validate::check_filesystem_name(output_file, filesystem).err());
+                }
+                if filesystem.case_sensitive {
+                    case_sensitive_outputs.insert(output_file);
+                }
+            }
+        }
+    }
+
+    // Sanity check - verify that the changed output filenames and the files to create stay inside
+    // the restricted directory.
+    if let Some(root) = &options.restrict {
+        for (input_file, output_file) in inputs.iter().zip(outputs.iter()) {
+            if input_file != output_file && !is_deletion(output_file) {
+                problems.extend(validate::check_within(output_file, root).err());
+            }
+        }
+        for path in &created {
+            problems.extend(validate::check_within(path, root).err());
+        }
+    }
+
+    // Sanity check - verify that the files to create are valid and don't exist yet.
+    for path in &created {
+        if options.check_windows_names {
+            problems.extend(validate::check_windows_name(path).err());
+        }
+        if options.check_filesystems {
+            let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+            if let Some(filesystem) = Filesystem::detect(dir) {
+                problems.extend(validate::check_filesystem_name(path, &filesystem).err());
+            }
+        }
+        if extended(Path::new(path)).symlink_metadata().is_ok() {
+            problems.push(Error::CreateExists(path.to_string()));
+        }
+    }
+
+    // Sanity check - verify that the output filenames, including the files to create, are
+    // unique. Each duplicated filename is reported once.
+    let mut case_sensitive_output_set = HashSet::new();
+    let mut duplicates = HashSet::new();
+    let created_names = created.iter().map(|s| s.trim_end_matches(['/', std::path::MAIN_SEPARATOR]));
+    let normalized_outputs = outputs
+        .iter()
+        .filter(|s| !is_deletion(s))
+        .map(|s| s.as_str())
+        .chain(created_names.clone())
+        .map(|s| normalize_path(s).nfc().collect::<String>());
+    for output_file in normalized_outputs {
+        if case_sensitive_output_set.contains(&output_file) {
+            if duplicates.insert(output_file.to_lowercase()) {
+                problems.push(Error::DuplicateOutput(output_file));
+            }
+            continue;
+        }
+        case_sensitive_output_set.insert(output_file);
+    }
+
+    // Sanity check - verify that the output filenames are case-insensitively unique. (Files on
+    // file systems known to be case-sensitive are exempt, as are exact duplicates, which have
+    // already been reported.)
+    let mut case_insensitive_output_set = HashSet::new();
+    let normalized_outputs = outputs
+        .iter()
+        .filter(|s| !is_deletion(s) && !case_sensitive_outputs.contains(s))
+        .map(|s| s.as_str())
+        .chain(created_names)
+        .map(|s| normalize_path(s).nfc().collect::<String>());
+    for output_file in normalized_outputs.map(|s| s.to_lowercase()) {
+        if case_insensitive_output_set.contains(&output_file) {
+            if duplicates.insert(output_file.clone()) {
+                problems.push(Error::DuplicateOutputCaseInsensitive(output_file));
+            }
+            continue;
+        }
+        case_insensitive_output_set.insert(output_file);
+    }
+
+    // List of files to delete.
+    let mut delete_list: Vec<String> = Vec::new();
+
+    // List of files to delete permanently.
+    let mut purge_list: Vec<String> = Vec::new();
+
+    // List of rename operations as (src, dst) tuples.
+    let mut rename_list: Vec<(String, String)> = Vec::new();
+
+    // Set of existing files that aren't part of the batch which will be overwritten.
+    let mut overwrites: HashSet<String> = HashSet::new();
+
+    // Subset of overwritten files with content identical to the files replacing them.
+    let mut identical_overwrites: HashSet<String> = HashSet::new();
+
+    // Changes skipped because their output filenames already exist.
+    let mut skipped: Vec<(String, String)> = Vec::new();
+
+    // Changes skipped because the input and output filenames already name the same file.
+    let mut same_files: Vec<(String, String)> = Vec::new();
+
+    // Set of input files that will be moved out of the way. An output filename in this set belongs
+    // to a file that's part of the batch rather than a file that would be overwritten. Copied files
+    // stay in place so they count as existing files.
+    let input_set: HashSet<&String> = inputs.iter().filter(|input_file| !copies.contains(*input_file)).collect();
+
+    // Output filenames already claimed by the batch. Numbered filenames must avoid these.
+    let mut claimed: HashSet<String> = outputs.iter().filter(|s| !is_deletion(s)).cloned().collect();
+
+    // Populate the task lists.
+    for (input_file, output_file) in inputs.iter().zip(outputs.iter()) {
+        if input_file == output_file {
+            continue;
+        }
+
+        // Hard links and bind mounts give a file more than one name. Renaming a file to another
+        // of its own names silently does nothing and copying it would truncate it, so the change
+        // is skipped. (A case-only rename on a case-insensitive file system also finds the input
+        // file at the output filename and goes ahead.)
+        if !input_set.contains(output_file)
+            && !is_deletion(output_file)
+            && input_file.to_lowercase() != output_file.to_lowercase()
+            && is_same_file(&extended(Path::new(input_file)), &extended(Path::new(output_file)))
+        {
+            same_files.push((input_file.to_string(), output_file.to_string()));
+            continue;
+        }
+
+        if extended(Path::new(output_file)).is_dir() {
+            if input_set.contains(output_file) {
+                rename_list.push((input_file.to_string(), output_file.to_string()));
+                continue;
+            }
+            match options.conflicts.get(output_file).copied().unwrap_or(options.on_conflict) {
+                Conflict::Skip => {
+                    skipped.push((input_file.to_string(), output_file.to_string()));
+                    continue;
+                }
+                Conflict::Number => {
+                    let numbered = numbered_filename(output_file, &claimed)?;
+                    claimed.insert(numbered.clone());
+                    rename_list.push((input_file.to_string(), numbered));
+                    continue;
+                }
+                Conflict::Error | Conflict::Overwrite => {
+                    problems.push(Error::DirectoryExists(output_file.to_string()));
+                    continue;
+                }
+            }
+        }
+
+        if output_file.starts_with('!') {
+            purge_list.push(input_file.to_string());
+            continue;
+        }
+
+        if output_file.starts_with('#') {
+            delete_list.push(input_file.to_string());
+            continue;
+        }
+
+        if options.link && !copies.contains(input_file) && extended(Path::new(input_file)).is_dir() {
+            problems.push(Error::LinkDirectory(input_file.to_string()));
+            continue;
+        }
+
+        if extended(Path::new(output_file)).is_file() {
+            if input_set.contains(output_file) {
+                rename_list.push((input_file.to_string(), output_file.to_string()));
+                continue;
+            }
+
+            let compare = options.compare_overwrites || options.force_if_duplicate;
+            let identical = compare && same_content(input_file, output_file);
+            let conflict = options.conflicts.get(output_file).copied().unwrap_or(options.on_conflict);
+            if options.force || (options.force_if_duplicate && identical) || conflict == Conflict::Overwrite {
+                rename_list.push((input_file.to_string(), output_file.to_string()));
+                overwrites.insert(output_file.to_string());
+                if identical {
+                    identical_overwrites.insert(output_file.to_string());
+                }
+                continue;
+            }
+
+            if conflict == Conflict::Skip {
+                skipped.push((input_file.to_string(), output_file.to_string()));
+                continue;
+            }
+
+            if conflict == Conflict::Number {
+                let numbered = numbered_filename(output_file, &claimed)?;
+                claimed.insert(numbered.clone());
+                rename_list.push((input_file.to_string(), numbered));
+                continue;
+            }
+
+            if options.force_if_duplicate {
+                problems.push(Error::OverwriteUnique(output_file.to_string(), input_file.to_string()));
+            } else {
+                problems.push(Error::FileExists(output_file.to_string()));
+            }
+            continue;
+        }
+
+        rename_list.push((input_file.to_string(), output_file.to_string()));
+    }
+
+    // A single problem is reported as is.
+    if problems.len() == 1 {
+        return Err(problems.remove(0));
+    }
+    if !problems.is_empty() {
+        return Err(Error::Multiple(problems));
+    }
+
+    // A simple swap of two files, A to B and B to A, is planned as a single swap operation rather
+    // than a cycle through a temporary file so it can be executed atomically where the platform
+    // supports it. The swap takes the place of the first of the two renames. Files inside renamed
+    // directories are left to the general cycle handling as their paths may need rewriting.
+    let mut swaps: HashSet<String> = HashSet::new();
+    if !options.copy && !options.link {
+        let dir_inputs: Vec<&Path> = rename_list
+            .iter()
+            .map(|(input_file, _)| Path::new(input_file))
+            .filter(|path| path.is_dir())
+            .collect();
+        let is_nested = |path: &str| dir_inputs.iter().any(|dir| is_inside(path, dir));
+        let targets: HashMap<&String, &String> = rename_list.iter().map(|(src, dst)| (src, dst)).collect();
+        let mut second_halves: HashSet<String> = HashSet::new();
+        for (input_file, output_file) in &rename_list {
+            if second_halves.contains(input_file) || targets.get(output_file) != Some(&input_file) {
+                continue;
+            }
+            if copies.contains(input_file) || copies.contains(output_file) {
+                continue;
+            }
+            if extended(Path::new(input_file)).is_dir() || extended(Path::new(output_file)).is_dir() {
+                continue;
+            }
+            if is_nested(input_file) || is_nested(output_file) {
+                continue;
+            }
+            swaps.insert(input_file.to_string());
+            second_halves.insert(output_file.to_string());
+        }
+        rename_list.retain(|(input_file, _)| !second_halves.contains(input_file));
+    }
+
+    // Break cycles and put the renames in a workable order. In copy and link mode, temporary files
+    // are always renamed to their final destination rather than copied or linked.
+    let (rename_list, temp_files) = order_renames(rename_list, !options.copy && !options.link, &copies)?;
+
+    let mut operations: Vec<Operation> = delete_list.into_iter().map(Operation::Delete).collect();
+    operations.extend(purge_list.into_iter().map(Operation::Purge));
+    for (input_file, output_file) in rename_list {
+        if swaps.contains(&input_file) {
+            operations.push(Operation::Swap(input_file, output_file));
+        } else if (options.copy || copies.contains(&input_file)) && !temp_files.contains(&input_file) {
+            operations.push(Operation::Copy(input_file, output_file));
+        } else if options.link && !temp_files.contains(&input_file) {
+            operations.push(Operation::Link(input_file, output_file));
+        } else {
+            operations.push(Operation::Rename(input_file, output_file));
+        }
+    }
+
+    // New files are created last, with directories ahead of their contents.
+    created.sort_by_key(|path| Path::new(path).components().count());
+    operations.extend(created.into_iter().map(Operation::Create));
+
+    Ok(RenamePlan { operations, temp_files, overwrites, identical_overwrites, skipped, same_files })
+}
+
+
+// The ordered renames and the temporary files that break their cycles, as returned by
+// `order_renames()`.
+type OrderedRenames = (Vec<(String, String)>, HashSet<String>);
+
+
+// Orders a list of renames so that no rename replaces a file that's still waiting to be renamed,
+// breaking any cycles with temporary files. Returns the ordered renames and the temporary files.
+//
+// As inputs and outputs are unique, each rename's output is the input of at most one other
+// rename, so the renames form separate chains and cycles. In a chain, a rename must run after
+// the rename that moves its output out of the way. Each cycle is broken by redirecting its first
+// rename, A to B, to a temporary file and adding a final rename from the temporary file to B, so
+// a cycle of any length costs a single extra rename.
+//
+// If `nested` is true, renames of paths inside renamed directories are also ordered. (Directories
+// in `copies` stay in place so they're exempt.) For each directory rename D and each other rename
+// X:
+//
+// - If X's output is inside D's output, X must run after D as D creates the directory. If X's
+//   input is inside D's input, it's rewritten to the corresponding path inside D's output.
+// - Otherwise, if X's input is inside D's input, X must run before D while its input still
+//   exists. (If X's output is also inside D's input, X's file ends up inside D's output.)
+//
+// Renames are otherwise kept in their original order. Returns an error if the constraints
+// conflict.
+fn order_renames(
+    mut renames: Vec<(String, String)>,
+    nested: bool,
+    copies: &HashSet<String>,
+) -> Result<OrderedRenames> {
+    let mut is_dir_rename: Vec<bool> = renames
+        .iter()
+        .map(|(input_file, _)| nested && !copies.contains(input_file) && Path::new(input_file).is_dir())
+        .collect();
+
+    // Index the renames by input so each rename can find the rename its output depends on.
+    let indices: HashMap<String, usize> =
+        renames.iter().enumerate().map(|(i, (input_file, _))| (input_file.to_string(), i)).collect();
+
+    // Find the cycles by following each chain until it ends or reaches a rename already seen. As
+    // each rename has at most one predecessor, a chain can only loop back to where it started.
+    // Cycles are broken at their first rename, recorded as (first rename, final rename) pairs.
+    let mut temp_files: HashSet<String> = HashSet::new();
+    let mut detours: Vec<(usize, usize)> = Vec::new();
+    let mut visited = vec![false; renames.len()];
+    for start in 0..renames.len() {
+        let mut i = start;
+        let mut is_cycle = false;
+        while !visited[i] {
+            visited[i] = true;
+            match indices.get(&renames[i].1) {
+                Some(&next) => {
+                    is_cycle = next == start;
+                    i = next;
+                }
+                None => break,
+            }
+        }
+        if is_cycle {
+            let temp_file = get_temp_filename(&renames[start].0)?;
+            let output_file = std::mem::replace(&mut renames[start].1, temp_file.clone());
+            detours.push((start, renames.len()));
+            renames.push((temp_file.clone(), output_file));
+            is_dir_rename.push(is_dir_rename[start]);
+            temp_files.insert(temp_file);
+        }
+    }
+
+    // Build the dependency graph. `successors[i]` lists the renames that must run after rename i.
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); renames.len()];
+    let mut predecessor_counts: Vec<usize> = vec![0; renames.len()];
+    let mut add_edge = |before: usize, after: usize| {
+        successors[before].push(after);
+        predecessor_counts[after] += 1;
+    };
+    for (x, (_, output_file)) in renames.iter().enumerate() {
+        if let Some(&y) = indices.get(output_file) {
+            add_edge(y, x);
+        }
+    }
+    for &(first, last) in &detours {
+        add_edge(first, last);
+    }
+    for d in (0..renames.len()).filter(|&d| is_dir_rename[d]) {
+        let (dir_input, dir_output) = (Path::new(&renames[d].0), Path::new(&renames[d].1));
+        for (x, (input_file, output_file)) in renames.iter().enumerate() {
+            if x == d {
+                continue;
+            }
+            if is_inside(output_file, dir_output) {
+                add_edge(d, x);
+            } else if is_inside(input_file, dir_input) {
+                add_edge(x, d);
+            }
+        }
+    }
+
+    // Topologically sort the renames, always picking the earliest ready rename to keep the
+    // original order where possible.
+    let mut ready: BTreeSet<usize> = (0..renames.len()).filter(|&i| predecessor_counts[i] == 0).collect();
+    let mut order = Vec::new();
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for &j in &successors[i] {
+            predecessor_counts[j] -= 1;
+            if predecessor_counts[j] == 0 {
+                ready.insert(j);
+            }
+        }
+    }
+    if order.len() < renames.len() {
+        let stuck = (0..renames.len()).find(|&i| predecessor_counts[i] > 0).unwrap_or(0);
+        return Err(Error::NestedRenames(renames[stuck].0.clone()));
+    }
+
+    // Rewrite each input path to account for the directory renames that precede it.
+    let mut completed_dir_renames: Vec<(String, String)> = Vec::new();
+    let mut ordered = Vec::new();
+    for i in order {
+        let (mut input_file, output_file) = renames[i].clone();
+        for (dir_input, dir_output) in &completed_dir_renames {
+            if let Ok(relative) = Path::new(&input_file).strip_prefix(dir_input) {
+                input_file = Path::new(dir_output).join(relative).to_string_lossy().to_string();
+            }
+        }
+        if input_file != renames[i].0 && temp_files.contains(&renames[i].0) {
+            temp_files.insert(input_file.clone());
+        }
+        if is_dir_rename[i] {
+            completed_dir_renames.push((input_file.clone(), output_file.clone()));
+        }
+        ordered.push((input_file, output_file));
+    }
+    Ok((ordered, temp_files))
+}
+
+
+// Returns true if the output filename marks its input file for deletion, to the trash with '#' or
+// permanently with '!'.
+fn is_deletion(output_file: &str) -> bool {
+    output_file.starts_with('#') || output_file.starts_with('!')
+}
+
+
+// Returns `path` with '.' components and redundant separators removed, e.g. './a//b/' becomes
+// 'a/b'. '..' components are kept as the directory before them may be a symlink.
+fn normalize_path(path: &str) -> String {
+    let normalized: PathBuf = Path::new(path).components().filter(|c| *c != Component::CurDir).collect();
+    if normalized.as_os_str().is_empty() {
+        return ".".to_string();
+    }
+    normalized.to_string_lossy().to_string()
+}
+
+
+// Returns the path a filename refers to for comparisons: its canonical parent directory joined
+// with its final component, which isn't resolved so a symlink is compared by its own name. If the
+// parent directory can't be resolved, the lexically normalized filename is used.
+fn canonical_path(path: &str) -> PathBuf {
+    let normalized = PathBuf::from(normalize_path(path));
+    let parent = match normalized.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match (extended(parent).canonicalize(), normalized.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => normalized,
+    }
+}
+
+
+// Returns true if the input filename and the output filename name the same path, e.g. 'a.txt'
+// and './a.txt', or 'a.txt' and its absolute path. The file system is only consulted if the
+// final components match.
+fn same_path(input_file: &str, output_file: &str) -> bool {
+    if normalize_path(input_file) == normalize_path(output_file) {
+        return true;
+    }
+    Path::new(input_file).file_name().is_some()
+        && Path::new(input_file).file_name() == Path::new(output_file).file_name()
+        && canonical_path(input_file) == canonical_path(output_file)
+}
+
+
+// Returns true if `path` is strictly inside the directory `dir`.
+fn is_inside(path: &str, dir: &Path) -> bool {
+    let path = Path::new(path);
+    path != dir && path.starts_with(dir)
+}
+
+
+// Returns true if the files `a` and `b` have identical content. Returns false if either can't be
+// read, e.g. because it's a directory.
+fn same_content(a: &str, b: &str) -> bool {
+    let (file_a, file_b) = match (File::open(extended(Path::new(a))), File::open(extended(Path::new(b)))) {
+        (Ok(file_a), Ok(file_b)) => (file_a, file_b),
+        _ => return false,
+    };
+    match (file_a.metadata(), file_b.metadata()) {
+        (Ok(meta_a), Ok(meta_b)) if meta_a.is_file() && meta_b.is_file() && meta_a.len() == meta_b.len() => {}
+        _ => return false,
+    }
+    let mut reader_a = BufReader::new(file_a);
+    let mut reader_b = BufReader::new(file_b);
+    loop {
+        let (buf_a, buf_b) = match (reader_a.fill_buf(), reader_b.fill_buf()) {
+            (Ok(buf_a), Ok(buf_b)) => (buf_a, buf_b),
+            _ => return false,
+        };
+        if buf_a.is_empty() || buf_b.is_empty() {
+            return buf_a.is_empty() && buf_b.is_empty();
+        }
+        let len = buf_a.len().min(buf_b.len());
+        if buf_a[..len] != buf_b[..len] {
+            return false;
+        }
+        reader_a.consume(len);
+        reader_b.consume(len);
+    }
+}
+
+
+// Returns the output filename with the first number that gives a free filename inserted before
+// the extension, e.g. 'name (1).txt', 'name (2).txt', etc. Filenames in `claimed` are taken.
+fn numbered_filename(output_file: &str, claimed: &HashSet<String>) -> Result<String> {
+    let output_file = output_file.trim_end_matches(['/', '\\']);
+    let path = Path::new(output_file);
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let (stem, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => name.split_at(index),
+        _ => (name.as_str(), ""),
+    };
+    let prefix = &output_file[..output_file.len() - name.len()];
+    for number in 1..10_000 {
+        let candidate = format!("{}{} ({}){}", prefix, stem, number, ext);
+        if !claimed.contains(&candidate) && extended(Path::new(&candidate)).symlink_metadata().is_err() {
+            return Ok(candidate);
+        }
+    }
+    Err(Error::FileExists(output_file.to_string()))
+}
+
+
+// Generate a unique temporary filename.
+pub(crate) fn get_temp_filename(base: &str) -> Result<String> {
+    let mut rng = rand::thread_rng();
+    for _ in 0..10 {
+        let candidate = format!("{}.vimv_temp_{:04}", base, rng.gen_range(0..10_000));
+        if !Path::new(&candidate).exists() {
+            return Ok(candidate);
+        }
+    }
+    Err(Error::TempFilename(base.to_string()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renames(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(input_file, output_file)| (input_file.to_string(), output_file.to_string())).collect()
+    }
+
+    // Runs the ordered renames against a simulated set of files, checking that each rename's input
+    // exists and its output is free, then checks that every file ends up at its requested output.
+    fn check_order(requested: &[(String, String)], ordered: &[(String, String)]) {
+        let mut files: HashMap<String, String> =
+            requested.iter().map(|(input_file, _)| (input_file.clone(), input_file.clone())).collect();
+        for (input_file, output_file) in ordered {
+            assert!(!files.contains_key(output_file), "'{}' overwrites an existing file", output_file);
+            let original = files.remove(input_file).unwrap_or_else(|| panic!("'{}' doesn't exist", input_file));
+            files.insert(output_file.clone(), original);
+        }
+        for (input_file, output_file) in requested {
+            assert_eq!(files.get(output_file), Some(input_file));
+        }
+    }
+
+    #[test]
+    fn two_cycle() {
+        let requested = renames(&[("a", "b"), ("b", "a")]);
+        let (ordered, temp_files) = order_renames(requested.clone(), false, &HashSet::new()).unwrap();
+        assert_eq!(ordered.len(), 3);
+        assert_eq!(temp_files.len(), 1);
+        check_order(&requested, &ordered);
+    }
+
+    #[test]
+    fn three_cycle() {
+        let requested = renames(&[("a", "b"), ("b", "c"), ("c", "a")]);
+        let (ordered, temp_files) = order_renames(requested.clone(), false, &HashSet::new()).unwrap();
+        assert_eq!(ordered.len(), 4);
+        assert_eq!(temp_files.len(), 1);
+        check_order(&requested, &ordered);
+    }
+
+    #[test]
+    fn chain_alongside_cycle() {
+        // As inputs and outputs are unique, a chain can never join a cycle. The chain's renames are
+        // listed front to back so they have to be reversed, and only the cycle needs a temporary
+        // file.
+        let requested = renames(&[("x", "y"), ("y", "z"), ("a", "b"), ("b", "a"), ("z", "w")]);
+        let (ordered, temp_files) = order_renames(requested.clone(), false, &HashSet::new()).unwrap();
+        assert_eq!(ordered.len(), 6);
+        assert_eq!(temp_files.len(), 1);
+        check_order(&requested, &ordered);
+    }
+
+    #[test]
+    fn long_cycle() {
+        let requested: Vec<(String, String)> =
+            (0..1000).map(|index| (format!("f{}", index), format!("f{}", (index + 1) % 1000))).collect();
+        let (ordered, temp_files) = order_renames(requested.clone(), false, &HashSet::new()).unwrap();
+        assert_eq!(ordered.len(), 1001);
+        assert_eq!(temp_files.len(), 1);
+        check_order(&requested, &ordered);
+    }
+
+    #[test]
+    fn disjoint_cycles() {
+        let requested = renames(&[
+            ("a", "b"),
+            ("b", "a"),
+            ("c", "d"),
+            ("d", "e"),
+            ("e", "c"),
+            ("x", "y"),
+            ("y", "x"),
+            ("p", "q"),
+            ("q", "r"),
+            ("r", "s"),
+            ("s", "p"),
+        ]);
+        let (ordered, temp_files) = order_renames(requested.clone(), false, &HashSet::new()).unwrap();
+        assert_eq!(ordered.len(), requested.len() + 4);
+        assert_eq!(temp_files.len(), 4);
+        check_order(&requested, &ordered);
+    }
+
+    // Removes a temporary directory when dropped, even if the test panics.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn rename_inside_renamed_directory() {
+        let temp_dir = TempDir(std::env::temp_dir().join(format!("vimv-test-{}", std::process::id())));
+        let dir = &temp_dir.0;
+        std::fs::create_dir_all(dir.join("d")).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let (d, d_f, d_g, e, e_f, e_g) = (path("d"), path("d/f"), path("d/g"), path("e"), path("e/f"), path("e/g"));
+
+        // A rename within the old directory runs first, while its input still exists.
+        let requested = vec![(d.clone(), e.clone()), (d_f.clone(), d_g.clone())];
+        let (ordered, _) = order_renames(requested, true, &HashSet::new()).unwrap();
+        assert_eq!(ordered, vec![(d_f.clone(), d_g), (d.clone(), e.clone())]);
+
+        // A rename into the new directory runs after the directory has moved, from its new path.
+        let requested = vec![(d_f, e_g.clone()), (d.clone(), e.clone())];
+        let (ordered, _) = order_renames(requested, true, &HashSet::new()).unwrap();
+        assert_eq!(ordered, vec![(d, e), (e_f, e_g)]);
+    }
+}
@@ -0,0 +1,95 @@
+// Draws a progress bar for long-running batches.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+
+// The minimum interval between redraws. Batches of quick renames would otherwise spend more time
+// drawing the bar than renaming files.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+// The width of the bar itself, excluding the counts and the current filename.
+const BAR_WIDTH: usize = 30;
+
+
+// A progress bar drawn on a single line of standard error, showing the number of completed
+// operations out of the total and the file currently being processed.
+pub struct ProgressBar {
+    total: usize,
+    done: usize,
+    current: String,
+    last_draw: Option<Instant>,
+}
+
+
+impl ProgressBar {
+    pub fn new(total: usize) -> ProgressBar {
+        ProgressBar { total, done: 0, current: String::new(), last_draw: None }
+    }
+
+    // Sets the file currently being processed.
+    pub fn start(&mut self, current: &str) {
+        self.current = current.to_string();
+        self.draw(false);
+    }
+
+    // Counts an operation as processed, whether it succeeded or failed.
+    pub fn inc(&mut self) {
+        self.done += 1;
+        self.draw(false);
+    }
+
+    // Clears the bar so a message can be printed. The bar is redrawn on the next update.
+    pub fn clear(&mut self) {
+        eprint!("\r\x1b[K");
+        self.last_draw = None;
+    }
+
+    // Draws the final state of the bar and moves to the next line.
+    pub fn finish(&mut self) {
+        self.current.clear();
+        self.draw(true);
+        eprintln!();
+    }
+
+    // Redraws the bar, unless it was drawn less than REDRAW_INTERVAL ago and `force` is false.
+    fn draw(&mut self, force: bool) {
+        if !force && self.last_draw.is_some_and(|last| last.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let filled = self.done.min(self.total).saturating_mul(BAR_WIDTH).checked_div(self.total).unwrap_or(BAR_WIDTH);
+        let counts = format!("{}/{}", self.done, self.total);
+        let mut line = format!("[{}{}] {}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), counts);
+
+        // Show as much of the end of the current filename as fits on the line. We leave the last
+        // column free as some terminals wrap as soon as it's written.
+        let width = terminal_width();
+        let room = width.saturating_sub(line.chars().count() + 3);
+        if !self.current.is_empty() && room > 3 {
+            let count = self.current.chars().count();
+            line.push_str("  ");
+            if count > room {
+                let tail: String = self.current.chars().skip(count - (room - 3)).collect();
+                line.push_str("...");
+                line.push_str(&tail);
+            } else {
+                line.push_str(&self.current);
+            }
+        }
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[K{}", line);
+        let _ = stderr.flush();
+    }
+}
+
+
+// Returns the width of the terminal in columns, defaulting to 80.
+fn terminal_width() -> usize {
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), _)) => width as usize,
+        None => 80,
+    }
+}
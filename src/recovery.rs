@@ -0,0 +1,148 @@
+use crate::buffer::BufferFormat;
+use crate::error::{Error, Result};
+use crate::journal::{data_dir, escape, unescape};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// The number of saved buffers to keep. Older buffers are removed as new ones are saved.
+const MAX_SAVED: usize = 10;
+
+
+/// An edited buffer saved so the edits aren't lost if they fail validation or aren't applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedBuffer {
+    /// The path to the saved buffer. The buffer can be opened and edited directly.
+    pub buffer_file: PathBuf,
+
+    /// The working directory the buffer was edited in. Relative input filenames are relative to
+    /// this directory.
+    pub cwd: PathBuf,
+
+    /// The input filenames the buffer was created for.
+    pub inputs: Vec<String>,
+
+    /// The buffer's format.
+    pub format: BufferFormat,
+
+    /// True if filenames in the buffer are escaped, i.e. the buffer was created with --null.
+    pub escaped: bool,
+
+    /// The edited text of the buffer.
+    pub text: String,
+}
+
+
+/// Returns the directory used to store saved buffers.
+pub fn recovery_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("recovery"))
+}
+
+
+/// Saves the edited buffer `text`, created for the `inputs` in the specified format. The buffer
+/// is written to a '.vimv' file and the details needed to parse it to a '.session' file alongside
+/// it, both named by timestamp. Returns the path to the buffer file.
+pub fn save(cwd: &Path, inputs: &[String], format: BufferFormat, escaped: bool, text: &str) -> Result<PathBuf> {
+    let dir = recovery_dir().ok_or_else(|| {
+        Error::Operation("failed to locate a data directory for the saved buffer, set $XDG_DATA_HOME".to_string())
+    })?;
+    fs::create_dir_all(&dir).map_err(|err| {
+        Error::Operation(format!("cannot create the recovery directory '{}': {}", dir.display(), err))
+    })?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let buffer_file = dir.join(format!("{:020}.vimv", timestamp));
+    let session_file = buffer_file.with_extension("session");
+
+    let mut content = format!("cwd\t{}\nformat\t{}\n", escape(&cwd.to_string_lossy()), format.name());
+    if escaped {
+        content.push_str("escaped\n");
+    }
+    for input_file in inputs {
+        content.push_str(&format!("input\t{}\n", escape(input_file)));
+    }
+    fs::write(&buffer_file, text).map_err(|err| {
+        Error::Operation(format!("cannot write the saved buffer '{}': {}", buffer_file.display(), err))
+    })?;
+    fs::write(&session_file, content).map_err(|err| {
+        Error::Operation(format!("cannot write the saved buffer '{}': {}", session_file.display(), err))
+    })?;
+
+    prune(&dir);
+    Ok(buffer_file)
+}
+
+
+// Removes all but the most recent saved buffers.
+fn prune(dir: &Path) {
+    let paths = saved_buffers(dir);
+    if paths.len() > MAX_SAVED {
+        for path in &paths[..paths.len() - MAX_SAVED] {
+            let _ = fs::remove_file(path);
+            let _ = fs::remove_file(path.with_extension("session"));
+        }
+    }
+}
+
+
+// Returns the paths to the saved buffer files in `dir`, oldest first.
+fn saved_buffers(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vimv"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+
+/// Returns the path to the most recently saved buffer, if any.
+pub fn latest() -> Option<PathBuf> {
+    saved_buffers(&recovery_dir()?).pop()
+}
+
+
+/// Reads a saved buffer and the details needed to parse it from the '.session' file alongside
+/// `buffer_file`.
+pub fn read(buffer_file: &Path) -> Result<SavedBuffer> {
+    let session_file = buffer_file.with_extension("session");
+    let content = fs::read_to_string(&session_file).map_err(|err| {
+        Error::Input(format!("failed to read the saved session '{}': {}", session_file.display(), err))
+    })?;
+    let text = fs::read_to_string(buffer_file).map_err(|err| {
+        Error::Input(format!("failed to read the saved buffer '{}': {}", buffer_file.display(), err))
+    })?;
+    let mut saved = SavedBuffer {
+        buffer_file: buffer_file.to_path_buf(),
+        cwd: PathBuf::new(),
+        inputs: Vec::new(),
+        format: BufferFormat::Single,
+        escaped: false,
+        text,
+    };
+    for line in content.lines().filter(|line| !line.is_empty()) {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let corrupt = || Error::Input(format!("the saved session '{}' is corrupt: {}", session_file.display(), line));
+        match (fields[0].as_str(), fields.len()) {
+            ("cwd", 2) => saved.cwd = PathBuf::from(&fields[1]),
+            ("format", 2) => saved.format = BufferFormat::from_name(&fields[1]).ok_or_else(corrupt)?,
+            ("escaped", 1) => saved.escaped = true,
+            ("input", 2) => saved.inputs.push(fields[1].clone()),
+            _ => return Err(corrupt()),
+        }
+    }
+    Ok(saved)
+}
+
+
+/// Removes a saved buffer once it's no longer needed.
+pub fn remove(buffer_file: &Path) -> Result<()> {
+    fs::remove_file(buffer_file.with_extension("session"))
+        .and_then(|_| fs::remove_file(buffer_file))
+        .map_err(|err| Error::Operation(format!("cannot remove the saved buffer '{}': {}", buffer_file.display(), err)))
+}
@@ -0,0 +1,163 @@
+use crate::error::{Error, Result};
+use crate::longpath::extended;
+use crate::transform::{parent_name, split_extension, split_name};
+use chrono::{DateTime, Local};
+use regex::Regex;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Scope, AST};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+
+// The maximum number of operations a script can run for a single file, so a runaway loop fails
+// instead of hanging.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+
+// The maximum width `pad` will pad a number to, the longest filename most file systems allow.
+const MAX_PAD_WIDTH: i64 = 255;
+
+
+/// A Rhai script that generates output filenames, loaded with the --script option.
+///
+/// The script is run once for each input file and its value is the file's new path. It can read
+/// the following variables:
+///
+/// - `path`: the input path, as supplied.
+/// - `dir`: the directory part of the path, including its trailing separator, or an empty string.
+/// - `name`, `stem`, `ext`: the filename, the filename without its extension, and the extension
+///   without its leading dot.
+/// - `parent`: the name of the directory containing the file.
+/// - `index`, `count`: the file's zero-based position in the input list, and the list's length.
+/// - `paths`: an array of every input path, e.g. to find the largest number in the set.
+/// - `size`, `mtime`, `date`, `is_dir`: the file's size in bytes, its modification time as a Unix
+///   timestamp and as a 'YYYY-MM-DD' date, and whether it's a directory.
+///
+/// Scripts can also call `pad(number, width)` to zero-pad a number, `regex_match(text, pattern)`
+/// to get an array of a regex's capture groups (empty if it doesn't match), and
+/// `regex_replace(text, pattern, replacement)` to replace every match. Scripts have no access to
+/// the file system. Output from `print` and `debug` goes to standard error so it can't mix with
+/// Vimv's own output.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    path: String,
+}
+
+
+impl Script {
+    /// Reads and compiles the script at `path`. Returns an error if the file can't be read or the
+    /// script has a syntax error.
+    pub fn load(path: &str) -> Result<Script> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| Error::Transform(format!("cannot read the script '{}': {}", path, err)))?;
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| eprintln!("{}", text));
+        engine.on_debug(|text, _, _| eprintln!("{}", text));
+        engine.register_fn("pad", pad);
+        engine.register_fn("regex_match", regex_match);
+        engine.register_fn("regex_replace", regex_replace);
+        let ast = engine
+            .compile(&source)
+            .map_err(|err| Error::Transform(format!("syntax error in the script '{}': {}", path, err)))?;
+        Ok(Script { engine, ast, path: path.to_string() })
+    }
+
+    /// Runs the script for each of the `inputs` and returns the new paths in the same order. A
+    /// script that returns `()` leaves the file's path unchanged. Returns an error if the script
+    /// fails or returns anything other than a string for any file.
+    pub fn run(&self, inputs: &[String]) -> Result<Vec<String>> {
+        let paths: Array = inputs.iter().map(|path| Dynamic::from(path.clone())).collect();
+        let mut scope = Scope::new();
+        scope.push_constant("paths", paths);
+        scope.push_constant("count", inputs.len() as i64);
+
+        // Each run starts from the shared variables so variables defined by the script for one
+        // file can't leak into the next.
+        let shared = scope.len();
+        let mut outputs = Vec::with_capacity(inputs.len());
+        for (index, input_file) in inputs.iter().enumerate() {
+            scope.rewind(shared);
+            push_file_variables(&mut scope, input_file, index);
+            let value = self.engine.eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast).map_err(|err| {
+                Error::Transform(format!("the script '{}' failed for '{}': {}", self.path, input_file, err))
+            })?;
+            if value.is_unit() {
+                outputs.push(input_file.clone());
+                continue;
+            }
+            let type_name = value.type_name();
+            let output_file = value.into_string().map_err(|_| {
+                Error::Transform(format!(
+                    "the script '{}' returned a value of type '{}' for '{}', expected a string",
+                    self.path, type_name, input_file
+                ))
+            })?;
+            outputs.push(output_file);
+        }
+        Ok(outputs)
+    }
+}
+
+
+// Adds the variables describing the file at position `index` in the input list to `scope`.
+// Metadata that can't be read, e.g. for a broken symlink, is left as zero.
+fn push_file_variables(scope: &mut Scope, path: &str, index: usize) {
+    let (dir, name, _) = split_name(path);
+    let (stem, ext) = split_extension(name);
+    let metadata = fs::metadata(extended(Path::new(path))).ok();
+    let modified = metadata.as_ref().and_then(|meta| meta.modified().ok());
+    let mtime = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let date = modified
+        .map(|time| DateTime::<Local>::from(time).format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+
+    scope.push_constant("path", path.to_string());
+    scope.push_constant("dir", dir.to_string());
+    scope.push_constant("name", name.to_string());
+    scope.push_constant("stem", stem.to_string());
+    scope.push_constant("ext", ext.trim_start_matches('.').to_string());
+    scope.push_constant("parent", parent_name(path));
+    scope.push_constant("index", index as i64);
+    scope.push_constant("size", metadata.as_ref().map_or(0, |meta| meta.len() as i64));
+    scope.push_constant("mtime", mtime);
+    scope.push_constant("date", date);
+    scope.push_constant("is_dir", metadata.is_some_and(|meta| meta.is_dir()));
+}
+
+
+// Zero-pads `number` to `width` digits. The width is clamped to `MAX_PAD_WIDTH`.
+fn pad(number: i64, width: i64) -> String {
+    format!("{:0width$}", number, width = width.clamp(0, MAX_PAD_WIDTH) as usize)
+}
+
+
+// Returns the capture groups of the first match of `pattern` in `text`, starting with the whole
+// match. Groups that don't participate in the match are empty strings. Returns an empty array if
+// there's no match.
+fn regex_match(text: &str, pattern: &str) -> std::result::Result<Array, Box<EvalAltResult>> {
+    let regex = compile(pattern)?;
+    let captures = match regex.captures(text) {
+        Some(captures) => captures,
+        None => return Ok(Array::new()),
+    };
+    Ok(captures
+        .iter()
+        .map(|group| Dynamic::from(group.map_or(String::new(), |group| group.as_str().to_string())))
+        .collect())
+}
+
+
+// Replaces every match of `pattern` in `text`. The replacement can refer to capture groups as
+// '$1' or '${name}'.
+fn regex_replace(text: &str, pattern: &str, replacement: &str) -> std::result::Result<String, Box<EvalAltResult>> {
+    Ok(compile(pattern)?.replace_all(text, replacement).to_string())
+}
+
+
+fn compile(pattern: &str) -> std::result::Result<Regex, Box<EvalAltResult>> {
+    Regex::new(pattern).map_err(|err| format!("invalid regex '{}': {}", pattern, err).into())
+}
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+
+/// Atomically exchanges the files at `a` and `b`. Returns an error of kind `Unsupported` if the
+/// platform or the file system doesn't support atomic exchanges. On Linux, we use renameat2(2)
+/// with the RENAME_EXCHANGE flag.
+#[cfg(target_os = "linux")]
+pub fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (c_path(a)?, c_path(b)?);
+    let result =
+        unsafe { libc::renameat2(libc::AT_FDCWD, a.as_ptr(), libc::AT_FDCWD, b.as_ptr(), libc::RENAME_EXCHANGE) };
+    check(result)
+}
+
+
+/// Atomically exchanges the files at `a` and `b`. Returns an error of kind `Unsupported` if the
+/// platform or the file system doesn't support atomic exchanges. On macOS, we use renamex_np(2)
+/// with the RENAME_SWAP flag.
+#[cfg(target_os = "macos")]
+pub fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    let (a, b) = (c_path(a)?, c_path(b)?);
+    let result = unsafe { libc::renamex_np(a.as_ptr(), b.as_ptr(), libc::RENAME_SWAP) };
+    check(result)
+}
+
+
+/// Atomic exchanges aren't supported on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "atomic exchange is not supported on this platform"))
+}
+
+
+/// Renames `src` to `dst`, failing with an `AlreadyExists` error if `dst` exists. The check and
+/// the rename happen atomically. Returns an error of kind `Unsupported` if the platform or the
+/// file system doesn't support this. On Linux, we use renameat2(2) with the RENAME_NOREPLACE flag.
+#[cfg(target_os = "linux")]
+pub fn rename_no_replace(src: &Path, dst: &Path) -> io::Result<()> {
+    let (src, dst) = (c_path(src)?, c_path(dst)?);
+    let result = unsafe {
+        libc::renameat2(libc::AT_FDCWD, src.as_ptr(), libc::AT_FDCWD, dst.as_ptr(), libc::RENAME_NOREPLACE)
+    };
+    check(result)
+}
+
+
+/// Renames `src` to `dst`, failing with an `AlreadyExists` error if `dst` exists. The check and
+/// the rename happen atomically. Returns an error of kind `Unsupported` if the platform or the
+/// file system doesn't support this. On macOS, we use renamex_np(2) with the RENAME_EXCL flag.
+#[cfg(target_os = "macos")]
+pub fn rename_no_replace(src: &Path, dst: &Path) -> io::Result<()> {
+    let (src, dst) = (c_path(src)?, c_path(dst)?);
+    let result = unsafe { libc::renamex_np(src.as_ptr(), dst.as_ptr(), libc::RENAME_EXCL) };
+    check(result)
+}
+
+
+/// Atomic no-replace renames aren't supported on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn rename_no_replace(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "atomic no-replace renames are not supported on this platform"))
+}
+
+
+/// Clones the content of the open file `src` into the open, empty file `dst` so the two share
+/// their underlying storage until one is modified, i.e. makes a reflink. Returns an error if the
+/// file system doesn't support reflinks, e.g. if the files are on different file systems. On
+/// Linux, we use the FICLONE ioctl, which btrfs and XFS support among others.
+#[cfg(target_os = "linux")]
+pub fn clone_file(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let result = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    check(result)
+}
+
+
+/// Reflinks between open files aren't supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn clone_file(_src: &File, _dst: &File) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are not supported on this platform"))
+}
+
+
+/// Creates `dst` as a clone of the file `src` so the two share their underlying storage until
+/// one is modified, i.e. makes a reflink. Fails if `dst` exists or if the file system doesn't
+/// support clones. On macOS, we use clonefile(2), which APFS supports. The clone also copies the
+/// file's metadata.
+#[cfg(target_os = "macos")]
+pub fn clone_path(src: &Path, dst: &Path) -> io::Result<()> {
+    let (src, dst) = (c_path(src)?, c_path(dst)?);
+    let result = unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), libc::CLONE_NOFOLLOW) };
+    check(result)
+}
+
+
+/// Cloning files by path isn't supported on this platform.
+#[cfg(not(target_os = "macos"))]
+pub fn clone_path(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "cloning files is not supported on this platform"))
+}
+
+
+// Converts a path to a C string for a system call.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+
+// Converts the result of a system call to an `io::Result`. Errors indicating that the call or
+// its flags aren't supported by the kernel or the file system are reported as `Unsupported` so
+// the caller can fall back on another strategy.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::ENOTSUP) => {
+            Err(io::Error::new(io::ErrorKind::Unsupported, err))
+        }
+        _ => Err(err),
+    }
+}
@@ -0,0 +1,465 @@
+use crate::error::{Error, Result};
+use crate::journal::absolute;
+use crate::audio::{self, AudioInfo};
+use crate::photo::{self, PhotoInfo};
+use crate::longpath::extended;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use std::fs;
+use regex::{NoExpand, Regex, RegexBuilder};
+use std::path::Path;
+
+
+/// A transformation applied to filenames to generate output filenames without the editor.
+#[derive(Debug, Clone)]
+pub enum Transform {
+    /// Replace the filename with one generated from a template.
+    Template(Template),
+
+    /// Change the case of the filename.
+    Case(Case),
+
+    /// Apply a sed-style regex substitution to the filename.
+    Substitute(Substitution),
+
+    /// Replace every occurrence of a literal string in the filename.
+    Replace(Replacement),
+
+    /// Add a string to the start of the filename.
+    Prefix(String),
+
+    /// Add a string to the end of the filename, before its extension.
+    Suffix(String),
+}
+
+
+impl Transform {
+    /// Returns `path` with the transformation applied to its final component. Parent directories
+    /// are left unchanged. The `index` is the zero-based position of the file in the input list.
+    /// Returns an error if a template token can't be filled in, e.g. if a photo has no EXIF date.
+    pub fn apply(&self, path: &str, index: usize) -> Result<String> {
+        let (dir, name, trailing) = split_name(path);
+        let name = match self {
+            Transform::Template(template) => template.render(path, index)?,
+            Transform::Case(case) => change_case(name, *case),
+            Transform::Substitute(substitution) => substitution.apply(name),
+            Transform::Replace(replacement) => replacement.apply(name),
+            Transform::Prefix(prefix) => format!("{}{}", prefix, name),
+            Transform::Suffix(suffix) => {
+                let (stem, ext) = split_extension(name);
+                format!("{}{}{}", stem, suffix, ext)
+            }
+        };
+        Ok(format!("{}{}{}", dir, name, trailing))
+    }
+}
+
+
+/// A case transformation applied to filenames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// Convert the filename to lowercase.
+    Lower,
+
+    /// Convert the filename to uppercase.
+    Upper,
+
+    /// Capitalize the first letter of each word and lowercase the rest. The extension is left
+    /// unchanged.
+    Title,
+}
+
+
+/// Returns the template for a named preset. The 'music' preset arranges audio files by their tags
+/// as 'Artist/Album/NN - Title.ext'. The 'by-date' preset moves files into 'YYYY/MM'
+/// subdirectories by their modification time.
+pub fn preset(name: &str) -> Option<&'static str> {
+    match name {
+        "music" => Some("{tag.artist}/{tag.album}/{tag.track:02} - {tag.title}.{ext}"),
+        "by-date" => Some("{mtime:%Y/%m}/{name}"),
+        _ => None,
+    }
+}
+
+
+/// A sed-style substitution of the form 's/pattern/replacement/flags'.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+
+impl Substitution {
+    /// Parses a substitution expression of the form 's/pattern/replacement/flags'. Any character
+    /// can be used as the delimiter in place of '/'; a delimiter inside the pattern or the
+    /// replacement can be escaped with a backslash. The replacement can refer to capture groups
+    /// as '$1' or '${name}'. Supported flags are 'g' (replace every match instead of the first)
+    /// and 'i' (case-insensitive matching).
+    pub fn parse(expr: &str) -> Result<Substitution> {
+        let invalid = |reason: &str| Error::Transform(format!("invalid --regex expression '{}': {}", expr, reason));
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected an expression of the form 's/pattern/replacement/'"));
+        }
+        let delimiter = match chars.next() {
+            Some(c) if !c.is_alphanumeric() && c != '\\' && !c.is_whitespace() => c,
+            _ => return Err(invalid("expected a delimiter like '/' after 's'")),
+        };
+
+        // Split the remainder on unescaped delimiters.
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                    Some(next) => {
+                        parts.last_mut().unwrap().push(c);
+                        parts.last_mut().unwrap().push(next);
+                    }
+                    None => parts.last_mut().unwrap().push(c),
+                }
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        if parts.len() != 3 {
+            return Err(invalid("expected an expression of the form 's/pattern/replacement/'"));
+        }
+
+        let mut builder = RegexBuilder::new(&parts[0]);
+        let mut global = false;
+        for flag in parts[2].chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => return Err(invalid(&format!("unknown flag '{}'", flag))),
+            }
+        }
+        let regex = builder.build().map_err(|err| invalid(&err.to_string()))?;
+        Ok(Substitution { regex, replacement: parts[1].clone(), global })
+    }
+
+    /// Returns `name` with the first match, or every match if the 'g' flag is set, replaced.
+    pub fn apply(&self, name: &str) -> String {
+        if self.global {
+            self.regex.replace_all(name, self.replacement.as_str()).to_string()
+        } else {
+            self.regex.replace(name, self.replacement.as_str()).to_string()
+        }
+    }
+}
+
+
+/// A literal find-and-replace of the form 'old/new'.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    regex: Regex,
+    replacement: String,
+}
+
+
+impl Replacement {
+    /// Parses a replacement of the form 'old/new'. Filenames can't contain '/' so the first '/'
+    /// always separates the two strings and neither can contain another. The new string can be
+    /// empty to remove the old one. If `ignore_case` is true, the old string is matched
+    /// case-insensitively.
+    pub fn parse(expr: &str, ignore_case: bool) -> Result<Replacement> {
+        let invalid = |reason: &str| Error::Transform(format!("invalid --replace argument '{}': {}", expr, reason));
+        let (old, new) = match expr.split_once('/') {
+            Some((old, new)) if !new.contains('/') => (old, new),
+            _ => return Err(invalid("expected an argument of the form 'old/new'")),
+        };
+        if old.is_empty() {
+            return Err(invalid("the string to replace is empty"));
+        }
+        let regex = RegexBuilder::new(&regex::escape(old))
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| invalid(&err.to_string()))?;
+        Ok(Replacement { regex, replacement: new.to_string() })
+    }
+
+    /// Returns `name` with every occurrence of the old string replaced by the new string.
+    pub fn apply(&self, name: &str) -> String {
+        self.regex.replace_all(name, NoExpand(&self.replacement)).to_string()
+    }
+}
+
+
+/// A filename template like 'track-{n:03}-{stem}.{ext}'. Tokens in braces are replaced with
+/// values derived from each input file; '{{' and '}}' produce literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+
+// A piece of a parsed template.
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Token(String, Option<String>),
+}
+
+
+impl Template {
+    /// Parses a template. Supported tokens are '{n}' (a counter starting at 1, zero-padded to the
+    /// width given by a spec like '{n:03}'), '{name}' (the original filename), '{stem}' (the
+    /// filename without its extension), '{ext}' (the extension without its dot), '{parent}' (the
+    /// name of the directory containing the file), '{mtime}' (the file's modification time,
+    /// formatted using a strftime spec like '{mtime:%Y/%m}'), the EXIF tokens '{exif.date}' (the date a
+    /// photo was taken, formatted using a strftime spec like '{exif.date:%Y-%m-%d}'),
+    /// '{exif.make}', '{exif.model}', and '{exif.camera}', and the audio tag tokens '{tag.artist}',
+    /// '{tag.album}', '{tag.title}', '{tag.genre}', '{tag.year}', '{tag.track}', and '{tag.disc}'
+    /// (the last two accept a width like '{n}').
+    pub fn parse(text: &str) -> Result<Template> {
+        let invalid = |reason: String| Error::Transform(format!("invalid --template '{}': {}", text, reason));
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut token = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => token.push(c),
+                            None => return Err(invalid("unclosed '{'".to_string())),
+                        }
+                    }
+                    let (name, spec) = match token.split_once(':') {
+                        Some((name, spec)) => (name.to_string(), Some(spec.to_string())),
+                        None => (token, None),
+                    };
+                    check_token(&name, spec.as_deref()).map_err(invalid)?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Token(name, spec));
+                }
+                '}' => return Err(invalid("unmatched '}', use '}}' for a literal brace".to_string())),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    /// Returns the filename generated for `path`, the file at position `index` (zero-based) in
+    /// the input list. If the file has no extension, a '.' immediately before '{ext}' is dropped.
+    /// Returns an error if the template uses EXIF or audio tag tokens and the file has no matching
+    /// metadata.
+    pub fn render(&self, path: &str, index: usize) -> Result<String> {
+        let (_, name, _) = split_name(path);
+        let (stem, ext) = split_extension(name);
+        let ext = ext.trim_start_matches('.');
+        let mut photo_info: Option<PhotoInfo> = None;
+        let mut audio_info: Option<AudioInfo> = None;
+        let mut result = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => result.push_str(text),
+                Segment::Token(token, spec) => match token.as_str() {
+                    "n" => result.push_str(&pad(index + 1, spec.as_deref())),
+                    "name" => result.push_str(name),
+                    "stem" => result.push_str(stem),
+                    "ext" => {
+                        if ext.is_empty() && result.ends_with('.') {
+                            result.pop();
+                        }
+                        result.push_str(ext);
+                    }
+                    "parent" => result.push_str(&parent_name(path)),
+                    "mtime" => {
+                        let mtime = fs::metadata(extended(Path::new(path))).and_then(|meta| meta.modified());
+                        let mtime: DateTime<Local> = mtime
+                            .map_err(|err| {
+                                Error::Transform(format!("cannot read the modification time of '{}': {}", path, err))
+                            })?
+                            .into();
+                        result.push_str(&mtime.format(spec.as_deref().unwrap_or("%Y-%m-%d")).to_string());
+                    }
+                    _ if token.starts_with("exif.") => {
+                        if photo_info.is_none() {
+                            photo_info = Some(photo::read(path)?);
+                        }
+                        let info = photo_info.as_ref().unwrap();
+                        let value = match token.as_str() {
+                            "exif.date" => {
+                                let spec = spec.as_deref().unwrap_or("%Y-%m-%d");
+                                info.date.map(|date| date.format(spec).to_string())
+                            }
+                            "exif.make" => info.make.as_deref().map(sanitize),
+                            "exif.model" => info.model.as_deref().map(sanitize),
+                            _ => info.camera().as_deref().map(sanitize),
+                        };
+                        let value = value.ok_or_else(|| {
+                            Error::Transform(format!("the file '{}' has no EXIF data for '{{{}}}'", path, token))
+                        })?;
+                        result.push_str(&value);
+                    }
+                    _ if token.starts_with("tag.") => {
+                        if audio_info.is_none() {
+                            audio_info = Some(audio::read(path)?);
+                        }
+                        let info = audio_info.as_ref().unwrap();
+                        let value = match token.as_str() {
+                            "tag.artist" => info.artist.as_deref().map(sanitize),
+                            "tag.album" => info.album.as_deref().map(sanitize),
+                            "tag.title" => info.title.as_deref().map(sanitize),
+                            "tag.genre" => info.genre.as_deref().map(sanitize),
+                            "tag.track" => info.track.map(|track| pad(track as usize, spec.as_deref())),
+                            "tag.disc" => info.disc.map(|disc| pad(disc as usize, spec.as_deref())),
+                            _ => info.year.map(|year| year.to_string()),
+                        };
+                        let value = value.ok_or_else(|| {
+                            Error::Transform(format!("the file '{}' has no audio tag for '{{{}}}'", path, token))
+                        })?;
+                        result.push_str(&value);
+                    }
+                    _ => {}
+                },
+            }
+        }
+        Ok(result)
+    }
+}
+
+
+// The kind of spec a template token accepts after a ':'.
+enum SpecKind {
+    // A zero-padded width, e.g. '{n:03}'.
+    Width,
+
+    // A strftime date format, e.g. '{exif.date:%Y-%m-%d}'.
+    Date,
+
+    // No spec.
+    Plain,
+}
+
+
+// Returns the kind of spec the named token accepts, or `None` if the token isn't supported.
+fn spec_kind(name: &str) -> Option<SpecKind> {
+    match name {
+        "n" | "tag.track" | "tag.disc" => Some(SpecKind::Width),
+        "mtime" | "exif.date" => Some(SpecKind::Date),
+        "name" | "stem" | "ext" | "parent" => Some(SpecKind::Plain),
+        "exif.make" | "exif.model" | "exif.camera" => Some(SpecKind::Plain),
+        "tag.artist" | "tag.album" | "tag.title" | "tag.genre" | "tag.year" => Some(SpecKind::Plain),
+        _ => None,
+    }
+}
+
+
+// Checks that a template token is supported and its spec, if any, is valid.
+fn check_token(name: &str, spec: Option<&str>) -> std::result::Result<(), String> {
+    let kind = spec_kind(name).ok_or_else(|| format!("unknown token '{{{}}}'", name))?;
+    match (kind, spec) {
+        (_, None) => Ok(()),
+        (SpecKind::Width, Some(spec)) if spec.parse::<usize>().is_err() => Err(format!(
+            "invalid width '{}' for '{{{}}}', expected a number like '03'",
+            spec, name
+        )),
+        (SpecKind::Date, Some(spec)) if StrftimeItems::new(spec).any(|item| matches!(item, Item::Error)) => {
+            Err(format!("invalid date format '{}' for '{{{}}}'", spec, name))
+        }
+        (SpecKind::Plain, Some(_)) => Err(format!("the '{{{}}}' token doesn't take a spec", name)),
+        _ => Ok(()),
+    }
+}
+
+
+// Formats a number zero-padded to the width given by `spec`, if any.
+fn pad(number: usize, spec: Option<&str>) -> String {
+    let width = spec.and_then(|spec| spec.parse::<usize>().ok()).unwrap_or(0);
+    format!("{:0width$}", number, width = width)
+}
+
+
+// Replaces path separators in a metadata value so it can't create unexpected directories, e.g.
+// for the artist 'AC/DC'.
+fn sanitize(value: &str) -> String {
+    value.replace(['/', '\\'], "_")
+}
+
+
+// Returns the name of the directory containing `path`.
+pub(crate) fn parent_name(path: &str) -> String {
+    let absolute = absolute(path.trim_end_matches(['/', std::path::MAIN_SEPARATOR]));
+    Path::new(&absolute)
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+
+// Returns the filename with the case transformation applied.
+fn change_case(name: &str, case: Case) -> String {
+    match case {
+        Case::Lower => name.to_lowercase(),
+        Case::Upper => name.to_uppercase(),
+        Case::Title => {
+            let (stem, ext) = split_extension(name);
+            title_case(stem) + ext
+        }
+    }
+}
+
+
+// Splits a path into its directory prefix (including the final separator), its final component,
+// and any trailing separators, e.g. 'foo/bar/' becomes ('foo/', 'bar', '/').
+pub(crate) fn split_name(path: &str) -> (&str, &str, &str) {
+    let separators = ['/', std::path::MAIN_SEPARATOR];
+    let body = path.trim_end_matches(separators);
+    let trailing = &path[body.len()..];
+    match body.rfind(separators) {
+        Some(index) => (&body[..index + 1], &body[index + 1..], trailing),
+        None => ("", body, trailing),
+    }
+}
+
+
+// Splits a filename into its stem and its extension (including the dot). A leading dot, as in
+// '.bashrc', doesn't begin an extension.
+pub(crate) fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index..]),
+        _ => (name, ""),
+    }
+}
+
+
+// Capitalizes the first letter of each word and lowercases the rest. Words are runs of letters,
+// digits, and apostrophes, so "don't stop" becomes "Don't Stop".
+fn title_case(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut in_word = false;
+    for c in text.chars() {
+        if in_word {
+            result.extend(c.to_lowercase());
+        } else {
+            result.extend(c.to_uppercase());
+        }
+        in_word = c.is_alphanumeric() || c == '\'';
+    }
+    result
+}
@@ -0,0 +1,480 @@
+// A built-in terminal interface for editing filenames, used instead of an external editor when
+// the --tui flag is set.
+
+use chrono::{DateTime, Local};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use ratatui_image::picker::Picker;
+use ratatui_image::protocol::StatefulProtocol;
+use ratatui_image::StatefulImage;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+// The maximum number of bytes read from a file for the preview pane.
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+// The maximum number of directory entries listed in the preview pane.
+const PREVIEW_ENTRIES: usize = 100;
+
+// The number of rows moved by the PageUp and PageDown keys.
+const PAGE_SIZE: usize = 10;
+
+// A problem with an output filename, highlighted as the user edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conflict {
+    // The output filename is empty.
+    Empty,
+
+    // Another file has the same output filename.
+    Duplicate,
+
+    // A file that isn't being renamed already exists at the output filename. This doesn't block
+    // applying the changes as the --force and --on-conflict options decide what happens.
+    Exists,
+}
+
+impl Conflict {
+    fn describe(self) -> &'static str {
+        match self {
+            Conflict::Empty => "empty filename",
+            Conflict::Duplicate => "duplicate filename",
+            Conflict::Exists => "file exists",
+        }
+    }
+
+    // Returns true if the conflict would make the plan fail regardless of the conflict policy.
+    fn is_blocking(self) -> bool {
+        self != Conflict::Exists
+    }
+}
+
+// The interface's input mode.
+enum Mode {
+    // Moving through the list of files.
+    Browse,
+
+    // Editing the selected output filename. The cursor is a character index into the text.
+    Edit { text: Vec<char>, cursor: usize },
+}
+
+// The result of handling a keypress.
+enum Action {
+    Continue,
+    Apply,
+    Cancel,
+}
+
+struct App<'a> {
+    input_files: &'a [String],
+    initial_files: &'a [String],
+    output_files: Vec<String>,
+    conflicts: Vec<Option<Conflict>>,
+    state: ListState,
+    mode: Mode,
+    message: Option<String>,
+    quit_pending: bool,
+
+    // Renders images using the terminal's graphics protocol, e.g. kitty, iTerm2, or sixel, falling
+    // back to Unicode half blocks. `None` if the terminal couldn't be queried.
+    picker: Option<Picker>,
+
+    // The thumbnail for the file at the cached index, if it's an image.
+    thumbnail: Option<(usize, Option<StatefulProtocol>)>,
+}
+
+// Opens the interface with the output filenames initialized to `initial_files` and returns the
+// edited output filenames, one for each input file, or `None` if the user quits without applying
+// the changes. As in the editor buffer, output filenames beginning with '#' mark deletions.
+pub fn edit(input_files: &[String], initial_files: &[String]) -> Result<Option<Vec<String>>, String> {
+    let mut terminal = ratatui::try_init().map_err(|err| format!("cannot initialize the terminal: {}", err))?;
+    let mut app = App::new(input_files, initial_files);
+
+    // The terminal has to be queried for its graphics support after entering the alternate screen
+    // and before reading any events.
+    app.picker = Picker::from_query_stdio().ok();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result.map_err(|err| format!("terminal error: {}", err))
+}
+
+impl<'a> App<'a> {
+    fn new(input_files: &'a [String], initial_files: &'a [String]) -> App<'a> {
+        let output_files = initial_files.to_vec();
+        let conflicts = find_conflicts(input_files, &output_files);
+        App {
+            input_files,
+            initial_files,
+            output_files,
+            conflicts,
+            state: ListState::default().with_selected(Some(0)),
+            mode: Mode::Browse,
+            message: None,
+            quit_pending: false,
+            picker: None,
+            thumbnail: None,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<Vec<String>>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            self.message = None;
+            let action = match self.mode {
+                Mode::Browse => self.handle_browse_key(key),
+                Mode::Edit { .. } => self.handle_edit_key(key),
+            };
+            match action {
+                Action::Continue => {}
+                Action::Apply => return Ok(Some(self.output_files.clone())),
+                Action::Cancel => return Ok(None),
+            }
+        }
+    }
+
+    fn selected(&self) -> usize {
+        self.state.selected().unwrap_or(0).min(self.output_files.len().saturating_sub(1))
+    }
+
+    fn select(&mut self, index: usize) {
+        self.state.select(Some(index.min(self.output_files.len().saturating_sub(1))));
+    }
+
+    fn set_output(&mut self, index: usize, output_file: String) {
+        self.output_files[index] = output_file;
+        self.conflicts = find_conflicts(self.input_files, &self.output_files);
+    }
+
+    // Returns true if the file at `index` is marked for deletion. A file whose own name begins
+    // with '#' isn't deleted while its output filename is unchanged.
+    fn is_deleted(&self, index: usize) -> bool {
+        let output_file = &self.output_files[index];
+        output_file.starts_with('#') && *output_file != self.input_files[index]
+    }
+
+    fn changed_count(&self) -> usize {
+        self.input_files
+            .iter()
+            .zip(&self.output_files)
+            .filter(|(input_file, output_file)| input_file != output_file)
+            .count()
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent) -> Action {
+        let index = self.selected();
+        let quit_pending = std::mem::take(&mut self.quit_pending);
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return match key.code {
+                KeyCode::Char('c') => Action::Cancel,
+                KeyCode::Char('s') => self.apply(),
+                _ => Action::Continue,
+            };
+        }
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.select(index.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(index + 1),
+            KeyCode::PageUp => self.select(index.saturating_sub(PAGE_SIZE)),
+            KeyCode::PageDown => self.select(index + PAGE_SIZE),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(usize::MAX),
+            KeyCode::Enter | KeyCode::Char('i') | KeyCode::Char('e') => {
+                let text: Vec<char> = self.output_files[index].chars().collect();
+                let cursor = text.len();
+                self.mode = Mode::Edit { text, cursor };
+            }
+            KeyCode::Char('d') => {
+                let output_file = match self.output_files[index].strip_prefix('#') {
+                    Some(output_file) if self.is_deleted(index) => output_file.to_string(),
+                    _ => format!("#{}", self.output_files[index]),
+                };
+                self.set_output(index, output_file);
+            }
+            KeyCode::Char('u') => self.set_output(index, self.initial_files[index].clone()),
+            KeyCode::Char('w') => return self.apply(),
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if quit_pending || self.changed_count() == 0 {
+                    return Action::Cancel;
+                }
+                self.quit_pending = true;
+                self.message = Some("Unapplied changes. Press q again to quit without applying them.".to_string());
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn handle_edit_key(&mut self, key: KeyEvent) -> Action {
+        let index = self.selected();
+        let Mode::Edit { text, cursor } = &mut self.mode else {
+            return Action::Continue;
+        };
+        match key.code {
+            KeyCode::Enter => {
+                let output_file: String = text.iter().collect();
+                self.mode = Mode::Browse;
+                self.set_output(index, output_file);
+            }
+            KeyCode::Esc => self.mode = Mode::Browse,
+            KeyCode::Left => *cursor = cursor.saturating_sub(1),
+            KeyCode::Right => *cursor = (*cursor + 1).min(text.len()),
+            KeyCode::Home => *cursor = 0,
+            KeyCode::End => *cursor = text.len(),
+            KeyCode::Backspace if *cursor > 0 => {
+                *cursor -= 1;
+                text.remove(*cursor);
+            }
+            KeyCode::Delete if *cursor < text.len() => {
+                text.remove(*cursor);
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Action::Cancel,
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                text.insert(*cursor, c);
+                *cursor += 1;
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    // Returns Action::Apply unless there are conflicts that would make the plan fail.
+    fn apply(&mut self) -> Action {
+        let blocking = self.conflicts.iter().flatten().filter(|conflict| conflict.is_blocking()).count();
+        if blocking > 0 {
+            self.message = Some(format!("Resolve {} conflicting filename(s) before applying the changes.", blocking));
+            return Action::Continue;
+        }
+        Action::Apply
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(main_area);
+        self.draw_list(frame, list_area);
+        self.draw_preview(frame, preview_area);
+        self.draw_status(frame, status_area);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let selected = self.selected();
+        let items: Vec<ListItem> = (0..self.output_files.len())
+            .map(|index| {
+                if index == selected {
+                    if let Mode::Edit { text, .. } = &self.mode {
+                        return ListItem::new(display(&text.iter().collect::<String>()));
+                    }
+                }
+                ListItem::new(self.row(index))
+            })
+            .collect();
+        let title = format!(" Files ({} changed of {}) ", self.changed_count(), self.output_files.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, area, &mut self.state);
+
+        // Place the terminal cursor in the line being edited. The list is offset by its border
+        // and the highlight symbol.
+        if let Mode::Edit { cursor, .. } = &self.mode {
+            let column = *cursor as u16;
+            let row = selected.saturating_sub(self.state.offset()) as u16;
+            let x = (area.x + 3 + column).min(area.right().saturating_sub(2));
+            frame.set_cursor_position((x, area.y + 1 + row));
+        }
+    }
+
+    // Returns the line displayed for the file at `index`: its output filename, styled to show
+    // whether it's unchanged, renamed, or deleted, followed by any conflict.
+    fn row(&self, index: usize) -> Line<'static> {
+        let input_file = &self.input_files[index];
+        let output_file = &self.output_files[index];
+        let mut spans = if self.is_deleted(index) {
+            vec![
+                Span::styled(display(input_file), Style::new().fg(Color::Red).add_modifier(Modifier::CROSSED_OUT)),
+                Span::styled("  (delete)", Style::new().fg(Color::Red)),
+            ]
+        } else if output_file != input_file {
+            vec![
+                Span::styled(display(output_file), Style::new().fg(Color::Green)),
+                Span::styled(format!("  ← {}", display(input_file)), Style::new().add_modifier(Modifier::DIM)),
+            ]
+        } else {
+            vec![Span::raw(display(output_file))]
+        };
+        if let Some(conflict) = self.conflicts[index] {
+            let color = if conflict.is_blocking() { Color::Red } else { Color::Yellow };
+            let style = Style::new().fg(color).add_modifier(Modifier::BOLD);
+            spans.push(Span::styled(format!("  ✗ {}", conflict.describe()), style));
+        }
+        Line::from(spans)
+    }
+
+    fn draw_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let index = self.selected();
+        let input_file = &self.input_files[index];
+        let output_file = &self.output_files[index];
+        let label = Style::new().add_modifier(Modifier::BOLD);
+
+        let mut lines = vec![Line::from(vec![Span::styled("From: ", label), Span::raw(display(input_file))])];
+        if self.is_deleted(index) {
+            lines.push(Line::from(vec![Span::styled("To:   ", label), Span::raw("(delete)")]));
+        } else {
+            lines.push(Line::from(vec![Span::styled("To:   ", label), Span::raw(display(output_file))]));
+        }
+        if let Some(conflict) = self.conflicts[index] {
+            let color = if conflict.is_blocking() { Color::Red } else { Color::Yellow };
+            lines.push(Line::styled(format!("Conflict: {}", conflict.describe()), Style::new().fg(color)));
+        }
+        lines.push(Line::default());
+        lines.extend(preview(Path::new(input_file)));
+
+        // Images are shown as a thumbnail below their details.
+        let block = Block::bordered().title(" Preview ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let text_height = lines.len() as u16;
+        let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+        match self.thumbnail(index) {
+            Some(thumbnail) => {
+                let [text_area, image_area] =
+                    Layout::vertical([Constraint::Length(text_height), Constraint::Min(0)]).areas(inner);
+                frame.render_widget(paragraph, text_area);
+                frame.render_stateful_widget(StatefulImage::default(), image_area, thumbnail);
+            }
+            None => frame.render_widget(paragraph, inner),
+        }
+    }
+
+    // Returns the thumbnail for the file at `index` if it's an image and the terminal can display
+    // it. Only the most recent thumbnail is kept to limit memory use.
+    fn thumbnail(&mut self, index: usize) -> Option<&mut StatefulProtocol> {
+        let picker = self.picker.as_mut()?;
+        if self.thumbnail.as_ref().map(|(cached, _)| *cached) != Some(index) {
+            let path = Path::new(&self.input_files[index]);
+            let protocol = if is_image(path) {
+                image::open(path).ok().map(|image| picker.new_resize_protocol(image))
+            } else {
+                None
+            };
+            self.thumbnail = Some((index, protocol));
+        }
+        self.thumbnail.as_mut().and_then(|(_, protocol)| protocol.as_mut())
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let text = match (&self.mode, &self.message) {
+            (Mode::Edit { .. }, _) => "Enter: confirm  Esc: cancel  ←/→: move cursor".to_string(),
+            (Mode::Browse, Some(message)) => message.clone(),
+            (Mode::Browse, None) => "↑/↓: select  Enter: edit  d: delete  u: revert  w: apply  q: quit".to_string(),
+        };
+        frame.render_widget(Paragraph::new(text).style(Style::new().add_modifier(Modifier::REVERSED)), area);
+    }
+}
+
+// Returns the conflicts for each output filename: empty filenames, duplicates, and filenames that
+// already exist and aren't being renamed themselves.
+fn find_conflicts(input_files: &[String], output_files: &[String]) -> Vec<Option<Conflict>> {
+    let inputs: HashSet<&str> = input_files.iter().map(|s| s.as_str()).collect();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for output_file in output_files.iter().filter(|output_file| !output_file.starts_with('#')) {
+        *counts.entry(output_file.as_str()).or_default() += 1;
+    }
+    input_files
+        .iter()
+        .zip(output_files)
+        .map(|(input_file, output_file)| {
+            if output_file.starts_with('#') {
+                None
+            } else if output_file.trim().is_empty() {
+                Some(Conflict::Empty)
+            } else if counts[output_file.as_str()] > 1 {
+                Some(Conflict::Duplicate)
+            } else if output_file != input_file
+                && !inputs.contains(output_file.as_str())
+                && fs::symlink_metadata(output_file).is_ok()
+            {
+                Some(Conflict::Exists)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Returns a description of the file at `path` for the preview pane: its type, size, and
+// modification time, followed by the entries of a directory or the start of a text file.
+fn preview(path: &Path) -> Vec<Line<'static>> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) => return vec![Line::raw(format!("cannot read the file: {}", err))],
+    };
+    let mut lines = Vec::new();
+    let kind = if metadata.is_symlink() {
+        let target = fs::read_link(path).map(|target| target.display().to_string()).unwrap_or_default();
+        format!("Symlink to {}", display(&target))
+    } else if metadata.is_dir() {
+        "Directory".to_string()
+    } else {
+        format!("File, {} bytes", metadata.len())
+    };
+    lines.push(Line::raw(kind));
+    if let Ok(modified) = metadata.modified() {
+        let modified: DateTime<Local> = modified.into();
+        lines.push(Line::raw(format!("Modified {}", modified.format("%Y-%m-%d %H:%M"))));
+    }
+    lines.push(Line::default());
+
+    if metadata.is_dir() {
+        let mut names: Vec<String> = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect(),
+            Err(err) => return vec![Line::raw(format!("cannot read the directory: {}", err))],
+        };
+        names.sort();
+        let count = names.len();
+        lines.extend(names.into_iter().take(PREVIEW_ENTRIES).map(|name| Line::raw(display(&name))));
+        if count > PREVIEW_ENTRIES {
+            lines.push(Line::styled(
+                format!("... and {} more", count - PREVIEW_ENTRIES),
+                Style::new().add_modifier(Modifier::DIM),
+            ));
+        }
+    } else if metadata.is_file() && !is_image(path) {
+        let mut bytes = Vec::new();
+        if let Ok(file) = fs::File::open(path) {
+            let _ = file.take(PREVIEW_BYTES).read_to_end(&mut bytes);
+        }
+        if bytes.contains(&0) {
+            lines.push(Line::styled("(binary file)", Style::new().add_modifier(Modifier::DIM)));
+        } else {
+            let text = String::from_utf8_lossy(&bytes);
+            lines.extend(text.lines().map(|line| Line::raw(line.replace('\t', "    "))));
+        }
+    }
+    lines
+}
+
+// Returns true if `path` has the extension of an image format we can decode.
+fn is_image(path: &Path) -> bool {
+    image::ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+}
+
+
+// Returns `name` with control characters replaced so they can't corrupt the display. Each
+// character maps to a single replacement so cursor positions are unaffected.
+pub fn display(name: &str) -> String {
+    name.chars().map(|c| if c.is_control() { '\u{FFFD}' } else { c }).collect()
+}
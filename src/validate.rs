@@ -0,0 +1,289 @@
+use crate::error::{Error, Result};
+use std::path::{Component, Path, PathBuf};
+
+
+// Device names reserved by Windows. A filename is reserved if its stem matches one of these
+// case-insensitively, whatever its extension, e.g. 'con', 'NUL.txt', or 'Com1.tar.gz'.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "COM¹", "COM²", "COM³",
+    "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    "LPT¹", "LPT²", "LPT³",
+];
+
+
+/// Checks that each component of `path` is a valid Windows filename: not a reserved device name
+/// like 'CON' or 'COM1.txt' and not ending with a dot or a space.
+pub fn check_windows_name(path: &str) -> Result<()> {
+    for name in path.split(['/', '\\']) {
+        // Empty components come from leading or doubled separators; '.' and '..' are relative
+        // path components rather than filenames; drive prefixes like 'C:' are also skipped.
+        if name.is_empty() || name == "." || name == ".." || is_drive_prefix(name) {
+            continue;
+        }
+        if name.ends_with('.') || name.ends_with(' ') {
+            return Err(Error::InvalidOutput(
+                path.to_string(),
+                format!("'{}' ends with a dot or a space, which Windows doesn't allow", name),
+            ));
+        }
+        let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ').to_uppercase();
+        if WINDOWS_RESERVED_NAMES.contains(&stem.as_str()) {
+            return Err(Error::InvalidOutput(
+                path.to_string(),
+                format!("'{}' is a reserved device name on Windows", name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+
+// Returns true if `name` is a drive prefix like 'C:'.
+fn is_drive_prefix(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+
+/// The maximum length of a filename component on a file system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameLimit {
+    /// The limit is measured in bytes of UTF-8.
+    Bytes(usize),
+
+    /// The limit is measured in UTF-16 code units.
+    Utf16(usize),
+}
+
+
+/// The filename rules of the file system that an output file will be written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filesystem {
+    /// The file system type as reported by the operating system, e.g. 'ext4' or 'vfat'.
+    pub name: String,
+
+    /// Characters that can't appear in a filename, in addition to the path separators.
+    pub illegal_chars: &'static str,
+
+    /// True if control characters can't appear in a filename.
+    pub no_control_chars: bool,
+
+    /// True if filenames can't end with a dot or a space.
+    pub no_trailing_dots: bool,
+
+    /// The maximum length of a filename component.
+    pub max_len: NameLimit,
+
+    /// True if filenames differing only in case refer to different files.
+    pub case_sensitive: bool,
+}
+
+
+impl Filesystem {
+    /// Returns the rules for a file system type name. Unrecognised types get the rules common to
+    /// Unix file systems: any character except '/' and NUL, up to 255 bytes.
+    pub fn from_name(name: &str) -> Filesystem {
+        let windows = Filesystem {
+            name: name.to_string(),
+            illegal_chars: "\"*:<>?\\|",
+            no_control_chars: true,
+            no_trailing_dots: true,
+            max_len: NameLimit::Utf16(255),
+            case_sensitive: false,
+        };
+        match name.to_lowercase().as_str() {
+            // FUSE block devices are almost always NTFS or exFAT drives.
+            "vfat" | "msdos" | "fat" | "fat32" | "exfat" | "ntfs" | "ntfs3" | "fuseblk" | "refs" => windows,
+            "smbfs" | "cifs" | "smb3" | "windows" => windows,
+            "hfs" => Filesystem {
+                name: name.to_string(),
+                illegal_chars: "",
+                no_control_chars: false,
+                no_trailing_dots: false,
+                max_len: NameLimit::Utf16(255),
+                case_sensitive: false,
+            },
+            "apfs" => Filesystem {
+                name: name.to_string(),
+                illegal_chars: "",
+                no_control_chars: false,
+                no_trailing_dots: false,
+                max_len: NameLimit::Bytes(255),
+                case_sensitive: false,
+            },
+            _ => Filesystem {
+                name: name.to_string(),
+                illegal_chars: "",
+                no_control_chars: false,
+                no_trailing_dots: false,
+                max_len: NameLimit::Bytes(255),
+                case_sensitive: true,
+            },
+        }
+    }
+
+    /// Returns the rules of the file system containing `path`. If `path` doesn't exist, the
+    /// file system of its nearest existing ancestor is used. Returns `None` if the file system
+    /// can't be detected.
+    pub fn detect(path: &Path) -> Option<Filesystem> {
+        let path = existing_ancestor(path)?.canonicalize().ok()?;
+        filesystem_type(&path).map(|name| Filesystem::from_name(&name))
+    }
+}
+
+
+/// Checks that each component of `path` is a valid filename on the file system `fs`.
+pub fn check_filesystem_name(path: &str, fs: &Filesystem) -> Result<()> {
+    let invalid = |reason: String| Err(Error::InvalidOutput(path.to_string(), reason));
+    for name in path.split(['/', std::path::MAIN_SEPARATOR]) {
+        if name.is_empty() || name == "." || name == ".." || (cfg!(windows) && is_drive_prefix(name)) {
+            continue;
+        }
+        if let Some(c) = name.chars().find(|c| fs.illegal_chars.contains(*c)) {
+            return invalid(format!("'{}' contains the character '{}', which {} doesn't allow", name, c, fs.name));
+        }
+        if fs.no_control_chars && name.chars().any(|c| c.is_control()) {
+            let name = name.escape_debug();
+            return invalid(format!("'{}' contains a control character, which {} doesn't allow", name, fs.name));
+        }
+        if fs.no_trailing_dots && (name.ends_with('.') || name.ends_with(' ')) {
+            return invalid(format!("'{}' ends with a dot or a space, which {} doesn't allow", name, fs.name));
+        }
+        let (len, max, unit) = match fs.max_len {
+            NameLimit::Bytes(max) => (name.len(), max, "bytes"),
+            NameLimit::Utf16(max) => (name.encode_utf16().count(), max, "characters"),
+        };
+        if len > max {
+            return invalid(format!("'{}' is {} {} long but {} allows at most {}", name, len, unit, fs.name, max));
+        }
+    }
+    Ok(())
+}
+
+
+/// Checks that `path` resolves to a location inside the canonical directory `root`. Symlinks are
+/// followed for the directories leading to `path` but not for its final component, which is
+/// what gets renamed or created.
+pub fn check_within(path: &str, root: &Path) -> Result<()> {
+    let mut components: Vec<Component> = Path::new(path).components().collect();
+    let last = components.pop();
+    let dir: PathBuf = components.iter().collect();
+
+    // Resolve the nearest existing ancestor of the directory, then apply the remaining
+    // components lexically as they don't exist yet.
+    let ancestor = existing_ancestor(&dir).unwrap_or_else(|| PathBuf::from("."));
+    let mut resolved = match crate::longpath::extended(&ancestor).canonicalize() {
+        Ok(canonical) => canonical,
+        Err(_) => return Ok(()),
+    };
+    let remainder = dir.strip_prefix(&ancestor).unwrap_or(&dir);
+    for component in remainder.components().chain(last) {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+
+    if resolved.starts_with(root) {
+        Ok(())
+    } else {
+        Err(Error::OutsideRoot(path.to_string(), root.display().to_string()))
+    }
+}
+
+
+// Returns `path` or its nearest existing ancestor. A relative path with no existing components
+// resolves to the current directory.
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    let mut candidate = path;
+    loop {
+        if candidate.as_os_str().is_empty() {
+            return Some(PathBuf::from("."));
+        }
+        if crate::longpath::extended(candidate).exists() {
+            return Some(candidate.to_path_buf());
+        }
+        candidate = candidate.parent()?;
+    }
+}
+
+
+// Returns the type of the file system containing the existing, canonical `path`. On Linux, we
+// find the mount point with the longest matching prefix in the kernel's mount table.
+#[cfg(target_os = "linux")]
+fn filesystem_type(path: &Path) -> Option<String> {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    let mut best: Option<(usize, String)> = None;
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        if fields.len() < 3 {
+            continue;
+        }
+        let mount_point = PathBuf::from(unescape_mount_field(fields[1]));
+        if !path.starts_with(&mount_point) {
+            continue;
+        }
+        // Later entries for the same mount point shadow earlier ones, hence '>='.
+        let depth = mount_point.components().count();
+        if best.as_ref().is_none_or(|(best_depth, _)| depth >= *best_depth) {
+            best = Some((depth, fields[2].to_string()));
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
+
+// Decodes the octal escape sequences (e.g. '\040' for a space) in a field of the mount table.
+#[cfg(target_os = "linux")]
+fn unescape_mount_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| (b'0'..=b'7').contains(b)) {
+            decoded.push((bytes[i + 1] - b'0') * 64 + (bytes[i + 2] - b'0') * 8 + (bytes[i + 3] - b'0'));
+            i += 4;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+
+// Returns the type of the file system containing the existing, canonical `path`. On macOS, we
+// ask statfs(2).
+#[cfg(target_os = "macos")]
+fn filesystem_type(path: &Path) -> Option<String> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Some(name.to_string_lossy().to_string())
+}
+
+
+// Returns the type of the file system containing `path`. On Windows, the Win32 API applies the
+// same filename rules on every volume so we don't need to distinguish between them.
+#[cfg(windows)]
+fn filesystem_type(_path: &Path) -> Option<String> {
+    Some("Windows".to_string())
+}
+
+
+// File system detection isn't supported on this platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn filesystem_type(_path: &Path) -> Option<String> {
+    None
+}
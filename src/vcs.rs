@@ -0,0 +1,134 @@
+use crate::git;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+
+/// Version control systems that can perform renames and deletions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vcs {
+    /// Tracked files are renamed using `git mv` and deleted using `git rm`.
+    Git,
+
+    /// Tracked files are renamed using `hg mv` and deleted using `hg rm`.
+    Mercurial,
+
+    /// Jujutsu records renames and deletions automatically when it snapshots the working copy, so
+    /// files are renamed and deleted as normal after taking a snapshot of the current state.
+    Jujutsu,
+}
+
+
+impl Vcs {
+    /// Returns the VCS with the specified name: 'git', 'hg', or 'jj'. The full names
+    /// 'mercurial' and 'jujutsu' are also accepted.
+    pub fn from_name(name: &str) -> Option<Vcs> {
+        match name {
+            "git" => Some(Vcs::Git),
+            "hg" | "mercurial" => Some(Vcs::Mercurial),
+            "jj" | "jujutsu" => Some(Vcs::Jujutsu),
+            _ => None,
+        }
+    }
+
+    /// Returns the VCS's display name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Vcs::Git => "git",
+            Vcs::Mercurial => "Mercurial",
+            Vcs::Jujutsu => "Jujutsu",
+        }
+    }
+
+    /// Returns the VCS managing the current working directory, if any. Jujutsu is checked first
+    /// as Jujutsu repositories are often colocated with a git repository.
+    pub fn detect() -> Option<Vcs> {
+        [Vcs::Jujutsu, Vcs::Git, Vcs::Mercurial].into_iter().find(|vcs| vcs.is_active())
+    }
+
+    /// Returns true if the current working directory is inside a repository managed by this VCS.
+    pub fn is_active(self) -> bool {
+        match self {
+            Vcs::Git => git::is_inside_work_tree(),
+            Vcs::Mercurial => succeeds(Command::new("hg").arg("root")),
+            Vcs::Jujutsu => succeeds(Command::new("jj").arg("root")),
+        }
+    }
+
+    /// Prepares the repository for a batch of operations. For Jujutsu, this snapshots the working
+    /// copy so the state before the operations is recorded.
+    pub fn prepare(self) -> Result<(), String> {
+        match self {
+            Vcs::Git | Vcs::Mercurial => Ok(()),
+            Vcs::Jujutsu => run("jj", Command::new("jj").arg("status")),
+        }
+    }
+
+    /// Returns true if `path` should be renamed and deleted using the VCS's own commands, i.e. if
+    /// it's tracked by git or Mercurial. A directory counts as tracked if it contains any tracked
+    /// files. Always returns false for Jujutsu.
+    pub fn is_tracked(self, path: &str) -> bool {
+        match self {
+            Vcs::Git => git::is_tracked(path),
+            Vcs::Mercurial => {
+                let dir = parent_dir(path);
+                let name = Path::new(path).file_name().map(|s| s.to_os_string()).unwrap_or_default();
+                succeeds(Command::new("hg").current_dir(dir).args(["files", "--"]).arg(name))
+            }
+            Vcs::Jujutsu => false,
+        }
+    }
+
+    /// Renames the tracked file `input_file` to `output_file`.
+    pub fn mv(self, input_file: &str, output_file: &str, force: bool) -> Result<(), String> {
+        match self {
+            Vcs::Git => git::mv(input_file, output_file, force),
+            Vcs::Mercurial => {
+                let mut cmd = Command::new("hg");
+                cmd.arg("mv");
+                if force {
+                    cmd.arg("-f");
+                }
+                cmd.arg("--").arg(input_file).arg(output_file);
+                run("hg", &mut cmd)
+            }
+            Vcs::Jujutsu => Err("Jujutsu doesn't track renames explicitly".to_string()),
+        }
+    }
+
+    /// Removes the tracked file `input_file` from the working tree and the repository.
+    pub fn rm(self, input_file: &str) -> Result<(), String> {
+        match self {
+            Vcs::Git => git::rm(input_file),
+            Vcs::Mercurial => run("hg", Command::new("hg").args(["rm", "-q", "--"]).arg(input_file)),
+            Vcs::Jujutsu => Err("Jujutsu doesn't track deletions explicitly".to_string()),
+        }
+    }
+}
+
+
+// Returns true if the command runs successfully. Its output is discarded.
+fn succeeds(cmd: &mut Command) -> bool {
+    cmd.stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok_and(|status| status.success())
+}
+
+
+// Run a VCS command, returning its error output on failure.
+fn run(program: &str, cmd: &mut Command) -> Result<(), String> {
+    let output = cmd
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|err| format!("failed to run {}: {}", program, err))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+
+// Returns the parent directory of `path`, defaulting to the current directory.
+fn parent_dir(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}